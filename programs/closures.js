@@ -0,0 +1,40 @@
+function counter(n) {
+	let count = 0;
+
+	function inc() {
+		count = count + n;
+	}
+
+	inc();
+	inc();
+	inc();
+
+	return count;
+}
+
+function outer(a) {
+	function sq(x) {
+		return x * x;
+	}
+
+	const twice = (x) => x + x;
+
+	return twice(sq(a));
+}
+
+function deep(x) {
+	let y = 10;
+
+	function middle() {
+		function inner(z) {
+			y = y + z;
+			return x + y;
+		}
+
+		return inner(5);
+	}
+
+	return middle();
+}
+
+counter(4); // must return 12
@@ -0,0 +1,14 @@
+const sq = function (a) {
+	return a * a;
+};
+
+const add = (a, b) => a + b;
+
+let double = x => x + x;
+
+const pick = (a, b) => {
+	if (a == 0) return b;
+	return a;
+};
+
+add(sq(3), double(pick(0, 4))); // must return 17
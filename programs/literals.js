@@ -0,0 +1,24 @@
+function rem(n) {
+	return n % 2;
+}
+
+function is_even(n) {
+	if (rem(n) == 0) return true;
+	return false;
+}
+
+function always(n) {
+	if (true) return n;
+	return 0;
+}
+
+function nothing() {
+	return null;
+}
+
+function check(n) {
+	if (undefined == nothing()) return is_even(n) + always(n);
+	return 0;
+}
+
+check(4);
@@ -0,0 +1,24 @@
+function fib(n) {
+	if (n < 2) return n;
+	return fib(n - 1) + fib(n - 2);
+}
+
+function fact(n) {
+	if (n == 0) return 1;
+	return n * fact(n - 1);
+}
+
+function sub(a, b) {
+	return a - b;
+}
+
+// calls in let values and conditions, evaluated left to right
+function mixed(n) {
+	let x = fact(n) - fib(n);
+	while (x < fact(n)) {
+		x = x + sub(fib(3), 1);
+	}
+	return sub(x, fib(2)) + x;
+}
+
+mixed(4);
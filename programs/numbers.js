@@ -0,0 +1,26 @@
+function average(a, b) {
+	let sum = a + b;
+
+	return sum * 0.5;
+}
+
+function big(n) {
+	let total = n + 3000000000;
+
+	return total;
+}
+
+function frac(x) {
+	return x % 2;
+}
+
+function main() {
+	let a = average(3, 4);
+	let b = frac(7.5);
+	let c = big(1);
+	let ab = a + b;
+
+	return ab + c;
+}
+
+main(); // must return 3.5 + 1.5 + 3000000001 = 3000000006
@@ -0,0 +1,23 @@
+function scale(a, factor = 10, offset = factor + 1) {
+	let scaled = a * factor;
+
+	return scaled + offset;
+}
+
+function sum(base, ...xs) {
+	let i = 0;
+	let total = i + base;
+
+	while (i < xs.length) {
+		total = total + xs[i];
+		i = i + 1;
+	}
+
+	return total;
+}
+
+function mixed(n) {
+	return sum(n, scale(2), scale(1, 2), 4);
+}
+
+mixed(1); // must return 1 + 31 + 5 + 4 = 41
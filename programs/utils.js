@@ -0,0 +1,7 @@
+function helper(a) {       // removed, nothing calls it
+	return a + 1;
+}
+
+export function twice(a) { // kept, it's exported
+	return a + a;
+}
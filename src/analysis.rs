@@ -1,58 +1,65 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
-use crate::parser::{LetStmtBody, ReturnStmt, Stmt};
+use crate::parser::{
+    FuncExprBody, Stmt,
+    visit::{VisitorMut, walk_block_mut},
+};
 
-pub fn dead_code_pass(stmts: &[Stmt], rt_val: Option<&ReturnStmt>) -> Vec<Stmt> {
-    let mut filtered = Vec::new();
+pub mod call_graph;
+pub mod cfg;
+pub mod closure;
+pub mod const_fold;
+pub mod cse;
+pub mod dead_code;
+pub mod dominators;
+pub mod effects;
+pub mod licm;
+pub mod liveness;
+pub mod resolve;
+pub mod shadowing;
+pub mod tail_calls;
+#[cfg(test)]
+pub mod test_util;
+pub mod types;
+pub mod unreachable;
 
-    let mut table = Vec::new();
+/// Turns function expressions bound to a name into plain function declarations, this is only
+/// possible when the binding is never reassigned, otherwise we'd need first class functions.
+/// Name resolution reports the bindings that are, they never get here from a source file
+pub fn lower_func_exprs(mut stmts: Vec<Stmt>) -> Vec<Stmt> {
+    LowerFuncExprs.visit_block_mut(&mut stmts);
 
-    for stmt in stmts.iter() {
-        match stmt {
-            Stmt::LetStmt(LetStmtBody { var_name, value }) => {
-                table.push((var_name, value));
-            }
-            _ => (),
-        }
-    }
-
-    let mut count_table = HashMap::new();
-
-    for (var, binary_stmt) in table {
-        let lhs = &binary_stmt.lhs;
+    stmts
+}
 
-        if let Some(x) = count_table.get_mut(&var) {
-            *x += 1;
-        } else {
-            count_table.insert(var, 0);
-        }
+struct LowerFuncExprs;
 
-        if let Some(x) = count_table.get_mut(&lhs) {
-            *x += 1;
-        };
-    }
+impl VisitorMut for LowerFuncExprs {
+    fn visit_block_mut(&mut self, block: &mut Vec<Stmt>) {
+        let mut declared = HashSet::new();
+        let mut reassigned = HashSet::new();
 
-    if let Some(ReturnStmt::BinaryStmtBody(ret_stmt)) = rt_val {
-        let rt_lhs = ret_stmt.lhs.clone();
-
-        if let Some(x) = count_table.get_mut(&rt_lhs) {
-            *x += 1;
+        for stmt in block.iter() {
+            if let Stmt::FuncExpr(FuncExprBody { var_name, decl, .. }) = stmt
+                && (decl.is_none() || !declared.insert(var_name))
+            {
+                reassigned.insert(var_name.clone());
+            }
         }
-    }
-
-    for stmt in stmts {
-        match stmt {
-            Stmt::LetStmt(LetStmtBody { var_name, .. }) => {
-                // UNWRAP: we just populated count table so it should exit
-                let count = count_table.get(&var_name).unwrap();
 
-                if *count > 0 {
-                    filtered.push(stmt.clone())
+        for stmt in block.iter_mut() {
+            if let Stmt::FuncExpr(FuncExprBody { var_name, func, .. }) = stmt {
+                if reassigned.contains(var_name) {
+                    unreachable!(
+                        "function binding `{}` is reassigned, name resolution reports it as E0015",
+                        var_name
+                    );
                 }
+
+                *stmt = Stmt::FuncDecl(func.clone());
             }
-            _ => filtered.push(stmt.clone()),
         }
-    }
 
-    filtered
+        walk_block_mut(self, block);
+    }
 }
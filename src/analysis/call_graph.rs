@@ -0,0 +1,193 @@
+//! Module level call graph.
+//!
+//! The entry points of a module are its exported functions and the functions called by the
+//! top level statements. Every function an entry point calls, directly or not, is reachable,
+//! the rest is removed before codegen. A module without entry points is a library and keeps
+//! all of its functions.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    lexer::Span,
+    parser::{
+        FuncCallStmt, FuncExprBody, Program, Stmt,
+        visit::{Visitor, walk_func_call},
+    },
+};
+
+/// A top level function that no entry point reaches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removed {
+    pub name: String,
+    pub span: Span,
+}
+
+/// Removes the top level functions that no entry point reaches, returns them in source order
+pub fn remove_unused_funcs(program: &mut Program) -> Vec<Removed> {
+    let graph = CallGraph::new(&program.body);
+    let reachable = graph.reachable();
+
+    let mut removed = Vec::new();
+
+    program.body.retain(|stmt| match func_name(stmt) {
+        Some((name, _)) if reachable.contains(name) => true,
+        Some((name, span)) => {
+            // a reassigned function expression is the same function as its declaration
+            if !removed.iter().any(|func: &Removed| func.name == name) {
+                removed.push(Removed {
+                    name: name.to_string(),
+                    span,
+                });
+            }
+
+            false
+        }
+        None => true,
+    });
+
+    removed
+}
+
+#[derive(Debug, Default)]
+struct CallGraph {
+    // function -> functions its body calls, nested functions included
+    calls: BTreeMap<String, BTreeSet<String>>,
+    entries: BTreeSet<String>,
+}
+
+impl CallGraph {
+    fn new(stmts: &[Stmt]) -> CallGraph {
+        let mut graph = CallGraph::default();
+
+        for stmt in stmts {
+            let mut calls = Calls::default();
+            calls.visit_stmt(stmt);
+
+            let Some((name, _)) = func_name(stmt) else {
+                // a top level call
+                graph.entries.extend(calls.0);
+                continue;
+            };
+
+            if let Stmt::FuncDecl(func) | Stmt::FuncExpr(FuncExprBody { func, .. }) = stmt
+                && func.exported
+            {
+                graph.entries.insert(name.to_string());
+            }
+
+            graph
+                .calls
+                .entry(name.to_string())
+                .or_default()
+                .extend(calls.0);
+        }
+
+        if graph.entries.is_empty() {
+            graph.entries = graph.calls.keys().cloned().collect();
+        }
+
+        graph
+    }
+
+    /// The entry points and every function they call, directly or not
+    fn reachable(&self) -> BTreeSet<&str> {
+        let mut reachable = BTreeSet::new();
+        let mut worklist: Vec<&str> = self.entries.iter().map(String::as_str).collect();
+
+        while let Some(func) = worklist.pop() {
+            if !reachable.insert(func) {
+                continue;
+            }
+
+            if let Some(callees) = self.calls.get(func) {
+                worklist.extend(callees.iter().map(String::as_str));
+            }
+        }
+
+        reachable
+    }
+}
+
+/// The name of a top level function and where it's declared
+fn func_name(stmt: &Stmt) -> Option<(&str, Span)> {
+    match stmt {
+        Stmt::FuncDecl(func) => Some((&func.func_name, func.span)),
+        Stmt::FuncExpr(func_expr) => Some((&func_expr.var_name, func_expr.func.span)),
+        _ => None,
+    }
+}
+
+/// Names of the functions called anywhere in a statement
+#[derive(Default)]
+struct Calls(BTreeSet<String>);
+
+impl Visitor for Calls {
+    fn visit_func_call(&mut self, call: &FuncCallStmt) {
+        self.0.insert(call.function_name.clone());
+
+        walk_func_call(self, call);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lexer::lex,
+        parser::{parse, printer::print_program},
+    };
+
+    fn remove(src: &str) -> (String, Vec<String>) {
+        let mut program = parse(lex(src.to_string())).unwrap();
+
+        let removed = remove_unused_funcs(&mut program)
+            .into_iter()
+            .map(|func| func.name)
+            .collect();
+
+        (print_program(&program), removed)
+    }
+
+    #[test]
+    fn keeps_functions_reachable_from_top_level_calls() {
+        let (printed, removed) = remove(
+            "function unused(a) {\n\treturn helper(a);\n}\n\nfunction helper(a) {\n\treturn a;\n}\n\nfunction f(a) {\n\tfunction inner(b) {\n\t\treturn g(b);\n\t}\n\treturn inner(a);\n}\n\nconst g = (a) => a;\n\nf(id(1));\n\nfunction id(a) {\n\treturn a;\n}\n",
+        );
+
+        assert_eq!(
+            printed,
+            "function f(a) {\n\tfunction inner(b) {\n\t\treturn g(b);\n\t}\n\treturn inner(a);\n}\n\nconst g = (a) => a;\n\nf(id(1));\n\nfunction id(a) {\n\treturn a;\n}\n"
+        );
+        assert_eq!(removed, ["unused", "helper"]);
+    }
+
+    #[test]
+    fn exported_functions_are_entry_points() {
+        let (printed, removed) = remove(
+            "export function f(a, b = g(a)) {\n\treturn b;\n}\n\nfunction g(a) {\n\treturn a;\n}\n\nfunction h(a) {\n\treturn f(a);\n}\n",
+        );
+
+        assert_eq!(
+            printed,
+            "export function f(a, b = g(a)) {\n\treturn b;\n}\n\nfunction g(a) {\n\treturn a;\n}\n"
+        );
+        assert_eq!(removed, ["h"]);
+
+        let (printed, removed) = remove(
+            "export const f = (a) => g(a);\n\nfunction g(a) {\n\treturn a;\n}\n\nconst h = (a) => f(a);\n",
+        );
+
+        assert_eq!(
+            printed,
+            "export const f = (a) => g(a);\n\nfunction g(a) {\n\treturn a;\n}\n"
+        );
+        assert_eq!(removed, ["h"]);
+    }
+
+    #[test]
+    fn keeps_every_function_of_a_library() {
+        let src = "function f(a) {\n\treturn a;\n}\n\nfunction g(a) {\n\treturn a;\n}\n";
+
+        assert_eq!(remove(src), (src.to_string(), Vec::new()));
+    }
+}
@@ -0,0 +1,418 @@
+//! Control-flow graphs.
+//!
+//! A function body is split into basic blocks, straight-line statements that end in a
+//! terminator: a jump, a branch on a condition or a return. Every return goes to a single
+//! exit block, so the exit post-dominates the whole function. Block 0 is the entry and block
+//! 1 the exit.
+//!
+//! A `while` is a header block that branches on the condition to the body or to the code after
+//! the loop, the body jumps back to the header. An `if` branches to its block or to the code
+//! after it, its block returns or jumps to the code after the `if`. Nested functions get a
+//! graph of their own, named `outer.inner` like closure conversion names them.
+
+use std::fmt::{self, Write};
+
+use crate::{
+    analysis::dominators::DomTree,
+    parser::{
+        BinaryStmtBody, FuncDeclBody, IfStmtBody, ReturnStmt, Stmt, WhileStmtBody,
+        printer::{binary_stmt_js, return_js, stmt_js},
+    },
+};
+
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Goto(BlockId),
+    Branch {
+        condition: BinaryStmtBody,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    // goes to the exit block
+    Return(Option<ReturnStmt>),
+    // the exit block, the only one without successors
+    Exit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    // lets, reassignments, calls and expressions, no control flow
+    pub stmts: Vec<Stmt>,
+    pub terminator: Terminator,
+    pub preds: Vec<BlockId>,
+    pub succs: Vec<BlockId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub func: String,
+    pub blocks: Vec<BasicBlock>,
+    pub entry: BlockId,
+    pub exit: BlockId,
+}
+
+impl Cfg {
+    /// The graph of the body of `func`, nested functions are left out
+    pub fn build(name: &str, func: &FuncDeclBody) -> Cfg {
+        let mut cfg = Cfg {
+            func: name.to_string(),
+            blocks: Vec::new(),
+            entry: 0,
+            exit: 1,
+        };
+
+        let entry = cfg.new_block();
+        let exit = cfg.new_block();
+        cfg.blocks[exit].terminator = Terminator::Exit;
+
+        let end = cfg.block(&func.block, entry);
+        cfg.blocks[end].terminator = Terminator::Return(func.return_value.clone());
+
+        cfg.link();
+
+        cfg
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock {
+            stmts: Vec::new(),
+            terminator: Terminator::Return(None),
+            preds: Vec::new(),
+            succs: Vec::new(),
+        });
+
+        self.blocks.len() - 1
+    }
+
+    /// Adds the statements to `current`, returns the block control is in after them
+    fn block(&mut self, stmts: &[Stmt], mut current: BlockId) -> BlockId {
+        for stmt in stmts {
+            match stmt {
+                Stmt::WhileStmt(WhileStmtBody {
+                    condition, block, ..
+                }) => {
+                    let header = self.new_block();
+                    let body = self.new_block();
+                    let after = self.new_block();
+
+                    self.blocks[current].terminator = Terminator::Goto(header);
+                    self.blocks[header].terminator = Terminator::Branch {
+                        condition: condition.clone(),
+                        then: body,
+                        otherwise: after,
+                    };
+
+                    let end = self.block(block, body);
+                    self.blocks[end].terminator = Terminator::Goto(header);
+
+                    current = after;
+                }
+                Stmt::IfStmt(IfStmtBody {
+                    condition,
+                    if_block,
+                    if_block_rt_val,
+                    ..
+                }) => {
+                    let then = self.new_block();
+                    let after = self.new_block();
+
+                    self.blocks[current].terminator = Terminator::Branch {
+                        condition: condition.clone(),
+                        then,
+                        otherwise: after,
+                    };
+
+                    let end = self.block(if_block, then);
+                    self.blocks[end].terminator = match if_block_rt_val {
+                        Some(rt_val) => Terminator::Return(Some(rt_val.clone())),
+                        None => Terminator::Goto(after),
+                    };
+
+                    current = after;
+                }
+                // declarations are hoisted, they don't run where they're written
+                Stmt::FuncDecl(_) | Stmt::FuncExpr(_) => (),
+                _ => self.blocks[current].stmts.push(stmt.clone()),
+            }
+        }
+
+        current
+    }
+
+    /// Fills in the predecessors and successors from the terminators
+    fn link(&mut self) {
+        for block in 0..self.blocks.len() {
+            let succs = match &self.blocks[block].terminator {
+                Terminator::Goto(target) => vec![*target],
+                Terminator::Branch {
+                    then, otherwise, ..
+                } => vec![*then, *otherwise],
+                Terminator::Return(_) => vec![self.exit],
+                Terminator::Exit => Vec::new(),
+            };
+
+            for succ in &succs {
+                self.blocks[*succ].preds.push(block);
+            }
+
+            self.blocks[block].succs = succs;
+        }
+    }
+
+    /// The graph as a DOT cluster, the dashed edges are the dominator tree
+    fn dot(&self, out: &mut String) -> fmt::Result {
+        let doms = DomTree::dominators(self);
+        let node = |block: BlockId| format!("\"{}.bb{}\"", self.func, block);
+
+        writeln!(out, "  subgraph \"cluster_{}\" {{", self.func)?;
+        writeln!(out, "    label=\"{}\"", escape(&self.func))?;
+
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("bb{}\\l", id);
+
+            for stmt in &block.stmts {
+                label.push_str(&escape(&stmt_js(stmt)));
+                label.push_str("\\l");
+            }
+
+            label.push_str(&escape(&terminator_text(&block.terminator)));
+            label.push_str("\\l");
+
+            writeln!(out, "    {} [label=\"{}\"]", node(id), label)?;
+        }
+
+        for (id, block) in self.blocks.iter().enumerate() {
+            match &block.terminator {
+                Terminator::Branch {
+                    then, otherwise, ..
+                } => {
+                    writeln!(out, "    {} -> {} [label=\"true\"]", node(id), node(*then))?;
+                    writeln!(
+                        out,
+                        "    {} -> {} [label=\"false\"]",
+                        node(id),
+                        node(*otherwise)
+                    )?;
+                }
+                _ => {
+                    for succ in &block.succs {
+                        writeln!(out, "    {} -> {}", node(id), node(*succ))?;
+                    }
+                }
+            }
+
+            if let Some(idom) = doms.idom(id) {
+                writeln!(
+                    out,
+                    "    {} -> {} [style=dashed color=blue constraint=false]",
+                    node(idom),
+                    node(id)
+                )?;
+            }
+        }
+
+        writeln!(out, "  }}")
+    }
+}
+
+/// Blocks with their predecessors, immediate dominator and immediate post-dominator
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let doms = DomTree::dominators(self);
+        let post_doms = DomTree::post_dominators(self);
+        let block_name =
+            |block: Option<BlockId>| block.map_or("-".to_string(), |b| format!("bb{}", b));
+
+        writeln!(f, "function {}", self.func)?;
+
+        for (id, block) in self.blocks.iter().enumerate() {
+            let preds = block
+                .preds
+                .iter()
+                .map(|pred| format!("bb{}", pred))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            writeln!(
+                f,
+                "bb{}: preds [{}] idom {} ipdom {}",
+                id,
+                preds,
+                block_name(doms.idom(id)),
+                block_name(post_doms.idom(id))
+            )?;
+
+            for stmt in &block.stmts {
+                writeln!(f, "\t{}", stmt_js(stmt))?;
+            }
+
+            writeln!(f, "\t{}", terminator_text(&block.terminator))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn terminator_text(terminator: &Terminator) -> String {
+    match terminator {
+        Terminator::Goto(target) => format!("goto bb{}", target),
+        Terminator::Branch {
+            condition,
+            then,
+            otherwise,
+        } => format!(
+            "if ({}) goto bb{} else bb{}",
+            binary_stmt_js(condition),
+            then,
+            otherwise
+        ),
+        Terminator::Return(Some(rt_val)) => format!("return {}", return_js(rt_val)),
+        Terminator::Return(None) => "return".to_string(),
+        Terminator::Exit => "exit".to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The graphs of every function of a program, nested ones included, in source order
+pub fn build_cfgs(stmts: &[Stmt]) -> Vec<Cfg> {
+    let mut cfgs = Vec::new();
+    collect_cfgs(stmts, None, &mut cfgs);
+
+    cfgs
+}
+
+fn collect_cfgs(stmts: &[Stmt], parent: Option<&str>, cfgs: &mut Vec<Cfg>) {
+    for stmt in stmts {
+        let func = match stmt {
+            Stmt::FuncDecl(func) => func,
+            Stmt::FuncExpr(func_expr) => &func_expr.func,
+            Stmt::WhileStmt(while_stmt) => {
+                collect_cfgs(&while_stmt.block, parent, cfgs);
+                continue;
+            }
+            Stmt::IfStmt(if_stmt) => {
+                collect_cfgs(&if_stmt.if_block, parent, cfgs);
+                continue;
+            }
+            _ => continue,
+        };
+
+        let name = match parent {
+            Some(parent) => format!("{}.{}", parent, func.func_name),
+            None => func.func_name.clone(),
+        };
+
+        cfgs.push(Cfg::build(&name, func));
+        collect_cfgs(&func.block, Some(&name), cfgs);
+    }
+}
+
+/// Every graph in one DOT file, a cluster per function
+pub fn cfgs_to_dot(cfgs: &[Cfg]) -> String {
+    let mut out = String::from("digraph cfg {\n  node [shape=box fontname=monospace]\n");
+
+    for cfg in cfgs {
+        // UNWRAP: writing to a String can't fail
+        cfg.dot(&mut out).unwrap();
+    }
+
+    out.push_str("}\n");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::lex, parser::parse};
+
+    fn cfgs(src: &str) -> Vec<Cfg> {
+        build_cfgs(&parse(lex(src.to_string())).unwrap().body)
+    }
+
+    #[test]
+    fn splits_functions_into_blocks() {
+        let cfgs = cfgs(
+            "function f(a) {\n\tlet i = 0;\n\twhile (i < a) {\n\t\ti = i + 1;\n\t}\n\tif (i == 3) {\n\t\treturn 1;\n\t}\n\tg(i);\n\treturn i;\n}\n",
+        );
+
+        assert_eq!(cfgs.len(), 1);
+
+        let cfg = &cfgs[0];
+        let succs: Vec<_> = cfg.blocks.iter().map(|block| block.succs.clone()).collect();
+        let preds: Vec<_> = cfg.blocks.iter().map(|block| block.preds.clone()).collect();
+
+        // entry, exit, loop header, loop body, after the loop, if block, after the if
+        assert_eq!(
+            succs,
+            [
+                vec![2],
+                vec![],
+                vec![3, 4],
+                vec![2],
+                vec![5, 6],
+                vec![1],
+                vec![1]
+            ]
+        );
+        assert_eq!(
+            preds,
+            [
+                vec![],
+                vec![5, 6],
+                vec![0, 3],
+                vec![2],
+                vec![2],
+                vec![4],
+                vec![4]
+            ]
+        );
+        assert_eq!(cfg.blocks[6].stmts.len(), 1);
+    }
+
+    #[test]
+    fn dumps_blocks_with_dominators() {
+        let cfgs = cfgs(
+            "function f(a) {\n\tlet b = a;\n\tfunction g(x) {\n\t\treturn x;\n\t}\n\tif (a == 0) {\n\t\tb = 1;\n\t}\n\treturn g(b);\n}\n",
+        );
+
+        assert_eq!(
+            cfgs.iter().map(|cfg| cfg.to_string()).collect::<String>(),
+            "function f\n\
+             bb0: preds [] idom - ipdom bb3\n\
+             \tlet b = a;\n\
+             \tif (a == 0) goto bb2 else bb3\n\
+             bb1: preds [bb3] idom bb3 ipdom -\n\
+             \texit\n\
+             bb2: preds [bb0] idom bb0 ipdom bb3\n\
+             \tb = 1;\n\
+             \tgoto bb3\n\
+             bb3: preds [bb0, bb2] idom bb0 ipdom bb1\n\
+             \treturn g(b)\n\
+             function f.g\n\
+             bb0: preds [] idom - ipdom bb1\n\
+             \treturn x\n\
+             bb1: preds [bb0] idom bb0 ipdom -\n\
+             \texit\n"
+        );
+    }
+
+    #[test]
+    fn dumps_dot_clusters() {
+        let dot = cfgs_to_dot(&cfgs(
+            "function f(a) {\n\tif (a == 0) {\n\t\ta = 1;\n\t}\n\treturn a;\n}\n",
+        ));
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("  subgraph \"cluster_f\" {\n"));
+        assert!(dot.contains("\"f.bb0\" [label=\"bb0\\lif (a == 0) goto bb2 else bb3\\l\"]"));
+        assert!(dot.contains("\"f.bb0\" -> \"f.bb2\" [label=\"true\"]"));
+        assert!(dot.contains("\"f.bb0\" -> \"f.bb3\" [style=dashed color=blue constraint=false]"));
+        assert!(dot.ends_with("  }\n}\n"));
+    }
+}
@@ -0,0 +1,513 @@
+//! Closure conversion for nested functions.
+//!
+//! Every nested function is lifted to the top level of the module under a mangled
+//! `outer.inner` name. A nested function that doesn't touch anything of the functions around
+//! it is lambda lifted as is, every other one becomes a closure that takes the environment of
+//! its parent as a hidden first parameter.
+//!
+//! Variables captured by a nested function don't live in a wasm local, they live in an
+//! environment the enclosing function allocates in linear memory when it's called. Slot 0 of
+//! an environment holds the environment of the parent function, so a closure nested a few
+//! levels deep can walk up the chain to the variable it needs.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::parser::{
+    FuncCallStmt, FuncDeclBody, FuncExprBody, IfStmtBody, LetStmtBody, Operand, Stmt,
+    WhileStmtBody,
+    visit::{
+        Visitor, VisitorMut, walk_func_call, walk_func_call_mut, walk_func_decl,
+        walk_func_decl_mut, walk_let_stmt, walk_operand,
+    },
+};
+
+/// Size in bytes of a slot in an environment
+pub const ENV_SLOT_SIZE: usize = 8;
+
+/// Which environment a variable or a called closure lives in, seen from the current function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvRef {
+    /// the environment allocated by the current function
+    Own,
+    /// the environment reached by following `n` parent links from the parent environment
+    Outer(usize),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FuncEnv {
+    /// nested function lifted to the top level, these aren't exported
+    pub lifted: bool,
+    /// takes the environment of the enclosing function as its first parameter
+    pub parent_env: bool,
+    /// allocates an environment on entry, either for its own captured variables or to pass
+    /// the parent link on to the closures nested in it
+    pub has_env: bool,
+    /// variables of this function captured by nested functions, in slot order
+    pub captured: Vec<String>,
+    /// variables of the enclosing functions used by this function, with their byte offset
+    pub outer: HashMap<String, (EnvRef, usize)>,
+    /// the (mangled) name of the function each variable of `outer` is declared in
+    pub owners: HashMap<String, String>,
+    /// called closures with the environment they expect as their first argument
+    pub closure_calls: HashMap<String, EnvRef>,
+}
+
+impl FuncEnv {
+    /// Environment and byte offset of a variable that doesn't live in a local
+    pub fn lookup(&self, var: &str) -> Option<(EnvRef, usize)> {
+        if let Some(slot) = self.captured.iter().position(|x| x == var) {
+            return Some((EnvRef::Own, (slot + 1) * ENV_SLOT_SIZE));
+        }
+
+        self.outer.get(var).copied()
+    }
+
+    /// Size in bytes of the environment this function allocates
+    pub fn env_size(&self) -> usize {
+        (self.captured.len() + 1) * ENV_SLOT_SIZE
+    }
+}
+
+enum TopLevel {
+    Stmt(Box<Stmt>),
+    // a top level function and the functions nested in it
+    Funcs(Range<usize>),
+}
+
+struct FuncNode {
+    name: String,
+    parent: Option<usize>,
+    func: FuncDeclBody,
+    children: Vec<usize>,
+    // nested functions by their unmangled name
+    nested: HashMap<String, usize>,
+    vars: HashSet<String>,
+}
+
+/// Lifts every nested function to the top level, returns the flattened statements and the
+/// environment of every function keyed by its (mangled) name
+pub fn convert_closures(stmts: Vec<Stmt>) -> (Vec<Stmt>, HashMap<String, FuncEnv>) {
+    let mut nodes = Vec::new();
+    let mut top_level = Vec::new();
+
+    for stmt in stmts {
+        match stmt {
+            Stmt::FuncDecl(func) => {
+                let start = add_node(&mut nodes, func, None);
+
+                top_level.push(TopLevel::Funcs(start..nodes.len()));
+            }
+            _ => top_level.push(TopLevel::Stmt(Box::new(stmt))),
+        }
+    }
+
+    let mut needs: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); nodes.len()];
+    let mut captured: Vec<Vec<String>> = vec![Vec::new(); nodes.len()];
+    // resolved variables of enclosing functions, (name, declaring function)
+    let mut outer_vars: Vec<Vec<(String, usize)>> = vec![Vec::new(); nodes.len()];
+    // resolved calls to nested functions, (name, callee, function the callee is declared in)
+    let mut nested_calls: Vec<Vec<(String, usize, usize)>> = vec![Vec::new(); nodes.len()];
+
+    for idx in 0..nodes.len() {
+        // default values are evaluated with the environment of the function they belong to,
+        // walking the declaration itself covers them
+        let mut names = Names::default();
+        walk_func_decl(&mut names, &nodes[idx].func);
+
+        let Names {
+            vars: vars_used,
+            calls,
+        } = names;
+
+        for var in vars_used {
+            if nodes[idx].vars.contains(&var) || outer_vars[idx].iter().any(|(x, _)| *x == var) {
+                continue;
+            }
+
+            let mut scope = nodes[idx].parent;
+
+            while let Some(outer) = scope {
+                if nodes[outer].vars.contains(&var) {
+                    if !captured[outer].contains(&var) {
+                        captured[outer].push(var.clone());
+                    }
+
+                    needs[idx].insert(outer);
+                    outer_vars[idx].push((var, outer));
+
+                    break;
+                }
+
+                scope = nodes[outer].parent;
+            }
+        }
+
+        for call in calls {
+            let mut scope = Some(idx);
+
+            while let Some(outer) = scope {
+                if let Some(callee) = nodes[outer].nested.get(&call) {
+                    nested_calls[idx].push((call, *callee, outer));
+
+                    break;
+                }
+
+                scope = nodes[outer].parent;
+            }
+        }
+    }
+
+    // calling a closure needs the environment it was declared in, and a function needs every
+    // environment its nested functions need from above it, this goes on until nothing changes
+    // since closures can call each other recursively
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for idx in 0..nodes.len() {
+            let mut required = Vec::new();
+
+            for (_, callee, declared_in) in nested_calls[idx].iter() {
+                if !needs[*callee].is_empty() && *declared_in != idx {
+                    required.push(*declared_in);
+                }
+            }
+
+            for child in nodes[idx].children.iter() {
+                required.extend(needs[*child].iter().filter(|x| **x != idx));
+            }
+
+            for outer in required {
+                changed |= needs[idx].insert(outer);
+            }
+        }
+    }
+
+    let depth = |from: usize, to: usize| -> EnvRef {
+        if from == to {
+            return EnvRef::Own;
+        }
+
+        let mut hops = 0;
+        let mut scope = nodes[from].parent;
+
+        while scope != Some(to) {
+            hops += 1;
+            // UNWRAP: `to` is always an enclosing function of `from`
+            scope = nodes[scope.unwrap()].parent;
+        }
+
+        EnvRef::Outer(hops)
+    };
+
+    let mut envs = HashMap::new();
+
+    for idx in 0..nodes.len() {
+        let mut env = FuncEnv {
+            lifted: nodes[idx].parent.is_some(),
+            parent_env: !needs[idx].is_empty(),
+            has_env: nodes[idx]
+                .children
+                .iter()
+                .any(|child| !needs[*child].is_empty()),
+            captured: captured[idx].clone(),
+            ..FuncEnv::default()
+        };
+
+        for (var, declared_in) in outer_vars[idx].iter() {
+            let slot = captured[*declared_in]
+                .iter()
+                .position(|x| x == var)
+                .unwrap();
+
+            env.outer.insert(
+                var.clone(),
+                (depth(idx, *declared_in), (slot + 1) * ENV_SLOT_SIZE),
+            );
+            env.owners
+                .insert(var.clone(), nodes[*declared_in].name.clone());
+        }
+
+        for (_, callee, declared_in) in nested_calls[idx].iter() {
+            if !needs[*callee].is_empty() {
+                env.closure_calls
+                    .insert(nodes[*callee].name.clone(), depth(idx, *declared_in));
+            }
+        }
+
+        envs.insert(nodes[idx].name.clone(), env);
+    }
+
+    let renames: Vec<HashMap<String, String>> = nested_calls
+        .iter()
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|(call, callee, _)| (call.clone(), nodes[*callee].name.clone()))
+                .collect()
+        })
+        .collect();
+
+    let mut funcs: Vec<Option<FuncDeclBody>> = nodes
+        .into_iter()
+        .zip(renames.iter())
+        .map(|(node, renames)| {
+            let mut func = FuncDeclBody {
+                func_name: node.name,
+                ..node.func
+            };
+
+            walk_func_decl_mut(&mut RenameCalls(renames), &mut func);
+
+            Some(func)
+        })
+        .collect();
+
+    let mut flattened = Vec::new();
+
+    for item in top_level {
+        match item {
+            TopLevel::Stmt(stmt) => flattened.push(*stmt),
+            TopLevel::Funcs(range) => {
+                for idx in range {
+                    // UNWRAP: every node belongs to exactly one top level function
+                    flattened.push(Stmt::FuncDecl(funcs[idx].take().unwrap()));
+                }
+            }
+        }
+    }
+
+    (flattened, envs)
+}
+
+/// Adds a function and the functions nested in it, returns the index of the function
+fn add_node(nodes: &mut Vec<FuncNode>, func: FuncDeclBody, parent: Option<usize>) -> usize {
+    let name = match parent {
+        Some(parent) => format!("{}.{}", nodes[parent].name, func.func_name),
+        None => func.func_name.clone(),
+    };
+
+    let mut nested_funcs = Vec::new();
+    let block = strip_nested_funcs(func.block, &mut nested_funcs);
+
+    let mut vars = HashSet::new();
+
+    for param in func.arguments.iter() {
+        vars.insert(param.name.clone());
+    }
+
+    collect_let_vars(&block, &mut vars);
+
+    let idx = nodes.len();
+
+    nodes.push(FuncNode {
+        name,
+        parent,
+        func: FuncDeclBody { block, ..func },
+        children: Vec::new(),
+        nested: HashMap::new(),
+        vars,
+    });
+
+    for nested in nested_funcs {
+        let nested_name = nested.func_name.clone();
+        let child = add_node(nodes, nested, Some(idx));
+
+        nodes[idx].children.push(child);
+        nodes[idx].nested.insert(nested_name, child);
+    }
+
+    idx
+}
+
+fn strip_nested_funcs(stmts: Vec<Stmt>, nested: &mut Vec<FuncDeclBody>) -> Vec<Stmt> {
+    let mut stripped = Vec::new();
+
+    for stmt in stmts {
+        match stmt {
+            Stmt::FuncDecl(func) => nested.push(func),
+            Stmt::WhileStmt(WhileStmtBody {
+                condition,
+                block,
+                span,
+            }) => stripped.push(Stmt::WhileStmt(WhileStmtBody {
+                condition,
+                block: strip_nested_funcs(block, nested),
+                span,
+            })),
+            Stmt::IfStmt(IfStmtBody {
+                condition,
+                if_block,
+                if_block_rt_val,
+                span,
+            }) => stripped.push(Stmt::IfStmt(IfStmtBody {
+                condition,
+                if_block: strip_nested_funcs(if_block, nested),
+                if_block_rt_val,
+                span,
+            })),
+            _ => stripped.push(stmt),
+        }
+    }
+
+    stripped
+}
+
+fn collect_let_vars(stmts: &[Stmt], vars: &mut HashSet<String>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::LetStmt(LetStmtBody { var_name, .. }) => {
+                vars.insert(var_name.clone());
+            }
+            Stmt::WhileStmt(WhileStmtBody { block, .. })
+            | Stmt::IfStmt(IfStmtBody {
+                if_block: block, ..
+            }) => collect_let_vars(block, vars),
+            _ => (),
+        }
+    }
+}
+
+/// Every variable read or written and every function called in a function body, nested
+/// functions are left out since they are handled on their own
+#[derive(Default)]
+struct Names {
+    vars: Vec<String>,
+    calls: Vec<String>,
+}
+
+impl Visitor for Names {
+    fn visit_let_stmt(&mut self, let_stmt: &LetStmtBody) {
+        self.vars.push(let_stmt.var_name.clone());
+        walk_let_stmt(self, let_stmt);
+    }
+
+    fn visit_reassign_stmt(&mut self, reassign: &LetStmtBody) {
+        self.vars.push(reassign.var_name.clone());
+        walk_let_stmt(self, reassign);
+    }
+
+    fn visit_func_call(&mut self, call: &FuncCallStmt) {
+        self.calls.push(call.function_name.clone());
+        walk_func_call(self, call);
+    }
+
+    fn visit_operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Literal(literal, _) => {
+                if !literal.chars().all(char::is_numeric) {
+                    self.vars.push(literal.clone());
+                }
+            }
+            Operand::Index(array, _, _) | Operand::Length(array, _) => {
+                self.vars.push(array.clone())
+            }
+            Operand::Call(_) | Operand::Bool(_) | Operand::Null | Operand::Undefined => (),
+        }
+
+        walk_operand(self, operand);
+    }
+
+    fn visit_func_decl(&mut self, _: &FuncDeclBody) {}
+
+    fn visit_func_expr(&mut self, _: &FuncExprBody) {}
+}
+
+/// Points calls to nested functions at their lifted names
+struct RenameCalls<'a>(&'a HashMap<String, String>);
+
+impl VisitorMut for RenameCalls<'_> {
+    fn visit_func_call_mut(&mut self, call: &mut FuncCallStmt) {
+        if let Some(name) = self.0.get(&call.function_name) {
+            call.function_name = name.clone();
+        }
+
+        walk_func_call_mut(self, call);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::test_util::parse_resolved,
+        parser::{ReturnStmt, Stmt},
+    };
+
+    fn convert(src: &str) -> (Vec<Stmt>, HashMap<String, FuncEnv>) {
+        let (stmts, envs, _) = parse_resolved(src);
+
+        (stmts, envs)
+    }
+
+    fn func_names(stmts: &[Stmt]) -> Vec<&str> {
+        stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::FuncDecl(func) => Some(func.func_name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lifts_function_without_captures() {
+        let (stmts, envs) = convert(
+            "function outer(a) {\n\tfunction sq(x) {\n\t\treturn x * x;\n\t}\n\treturn sq(a);\n}",
+        );
+
+        assert_eq!(func_names(&stmts), vec!["outer", "outer.sq"]);
+
+        let sq = &envs["outer.sq"];
+        assert!(sq.lifted);
+        assert!(!sq.parent_env);
+        assert!(!envs["outer"].has_env);
+        assert!(envs["outer"].closure_calls.is_empty());
+
+        match &stmts[0] {
+            Stmt::FuncDecl(FuncDeclBody {
+                return_value: Some(ReturnStmt::FuncCallStmt(call)),
+                ..
+            }) => assert_eq!(call.function_name, "outer.sq"),
+            _ => panic!("Expected return of a call"),
+        }
+    }
+
+    #[test]
+    fn captures_variables_of_enclosing_function() {
+        let (_, envs) = convert(
+            "function counter(n) {\n\tlet count = 0;\n\tfunction inc() {\n\t\tcount = count + n;\n\t}\n\tinc();\n\treturn count;\n}",
+        );
+
+        let counter = &envs["counter"];
+        assert!(counter.has_env);
+        assert_eq!(counter.captured, vec!["count", "n"]);
+        assert_eq!(counter.lookup("n"), Some((EnvRef::Own, 16)));
+        assert_eq!(counter.closure_calls.get("counter.inc"), Some(&EnvRef::Own));
+
+        let inc = &envs["counter.inc"];
+        assert!(inc.parent_env);
+        assert_eq!(inc.lookup("count"), Some((EnvRef::Outer(0), 8)));
+    }
+
+    #[test]
+    fn walks_parent_links_for_deeply_nested_closures() {
+        let (_, envs) = convert(
+            "function a(x) {\n\tfunction b() {\n\t\tfunction c() {\n\t\t\treturn x;\n\t\t}\n\t\treturn c();\n\t}\n\treturn b();\n}",
+        );
+
+        // b has nothing of its own captured but has to pass the parent link on to c
+        let b = &envs["a.b"];
+        assert!(b.parent_env);
+        assert!(b.has_env);
+        assert!(b.captured.is_empty());
+        assert_eq!(b.closure_calls.get("a.b.c"), Some(&EnvRef::Own));
+
+        assert_eq!(envs["a.b.c"].lookup("x"), Some((EnvRef::Outer(1), 8)));
+        assert_eq!(envs["a"].closure_calls.get("a.b"), Some(&EnvRef::Own));
+    }
+}
@@ -0,0 +1,517 @@
+//! Constant folding and propagation.
+//!
+//! Walks a function body in order and tracks the variables that hold a known constant. An
+//! expression whose operands are all constants is evaluated here and replaced by its value,
+//! a variable that's still read next to something unknown is replaced by its value when the
+//! literal has the same type as the variable, so the types inferred for the function stay
+//! valid.
+//!
+//! Values are computed the way the generated code would compute them, in the inferred type
+//! with wrapping integers and unsigned comparisons. Anything that would trap at runtime, a
+//! remainder by 0 or a float that doesn't fit an integer, is left to trap.
+//!
+//! After an `if` a variable is only constant when both paths agree on it. A loop runs the
+//! body until the variables it keeps constant don't change, those are the loop invariant
+//! ones. Variables in an environment are never constant, closures can write them.
+
+use std::collections::HashMap;
+
+use crate::{
+    analysis::{
+        closure::FuncEnv,
+        types::{Types, ValType, is_comparison},
+    },
+    lexer::Token,
+    parser::{
+        BinaryStmtBody, FuncCallStmt, IfStmtBody, LetStmtBody, Operand, ReturnStmt, Stmt,
+        WhileStmtBody, is_number,
+    },
+};
+
+/// Folds the constants of a function body and its return value
+pub fn const_fold_pass(
+    stmts: &[Stmt],
+    rt_val: Option<&ReturnStmt>,
+    func: &str,
+    env: &FuncEnv,
+    types: &Types,
+) -> (Vec<Stmt>, Option<ReturnStmt>) {
+    let folder = Folder { func, env, types };
+    let mut consts = Consts::new();
+
+    let block = folder.block(stmts, &mut consts);
+    let rt_val = rt_val.map(|rt_val| folder.return_stmt(rt_val, &consts));
+
+    (block, rt_val)
+}
+
+/// A value with the type it has in the generated code
+#[derive(Debug, Clone, Copy)]
+enum Const {
+    I32(i32),
+    I64(i64),
+    F64(f64),
+}
+
+impl PartialEq for Const {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Const::I32(a), Const::I32(b)) => a == b,
+            (Const::I64(a), Const::I64(b)) => a == b,
+            // 0.0 and -0.0 are different constants
+            (Const::F64(a), Const::F64(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl Const {
+    /// A number literal as the type it's inferred to have
+    fn parse(text: &str) -> Option<Const> {
+        match ValType::of_number(text) {
+            ValType::I32 => text.parse().ok().map(Const::I32),
+            ValType::I64 => text.parse().ok().map(Const::I64),
+            ValType::F64 => text.parse().ok().map(Const::F64),
+        }
+    }
+
+    fn from_bool(value: bool) -> Const {
+        Const::I32(value as i32)
+    }
+
+    fn ty(self) -> ValType {
+        match self {
+            Const::I32(_) => ValType::I32,
+            Const::I64(_) => ValType::I64,
+            Const::F64(_) => ValType::F64,
+        }
+    }
+
+    /// Converts like the generated code does, `None` when the conversion traps
+    fn convert(self, to: ValType) -> Option<Const> {
+        let converted = match (self, to) {
+            (Const::I32(value), ValType::I64) => Const::I64(value as i64),
+            (Const::I32(value), ValType::F64) => Const::F64(value as f64),
+            (Const::I64(value), ValType::I32) => Const::I32(value as i32),
+            (Const::I64(value), ValType::F64) => Const::F64(value as f64),
+            (Const::F64(value), ValType::I32) => {
+                let value = value.trunc();
+
+                if !(-2147483648.0..2147483648.0).contains(&value) {
+                    return None;
+                }
+
+                Const::I32(value as i32)
+            }
+            (Const::F64(value), ValType::I64) => {
+                let value = value.trunc();
+
+                if !(-9223372036854775808.0..9223372036854775808.0).contains(&value) {
+                    return None;
+                }
+
+                Const::I64(value as i64)
+            }
+            (value, _) => value,
+        };
+
+        Some(converted)
+    }
+
+    /// Applies `op` to two constants of the same type, `None` when it traps or gives a float
+    /// that can't be written as a literal
+    fn binary(op: &Token, lhs: Const, rhs: Const) -> Option<Const> {
+        let value = match (lhs, rhs) {
+            (Const::I32(a), Const::I32(b)) => match op {
+                Token::Add => Const::I32(a.wrapping_add(b)),
+                Token::Sub => Const::I32(a.wrapping_sub(b)),
+                Token::Mul => Const::I32(a.wrapping_mul(b)),
+                Token::Percent if b != 0 => Const::I32(((a as u32) % (b as u32)) as i32),
+                // the lexer has `<` as `GreaterThan` and `>` as `LessThan`
+                Token::GreaterThan => Const::from_bool((a as u32) < (b as u32)),
+                Token::LessThan => Const::from_bool((a as u32) > (b as u32)),
+                Token::DoubleEq => Const::from_bool(a == b),
+                Token::NotEq => Const::from_bool(a != b),
+                _ => return None,
+            },
+            (Const::I64(a), Const::I64(b)) => match op {
+                Token::Add => Const::I64(a.wrapping_add(b)),
+                Token::Sub => Const::I64(a.wrapping_sub(b)),
+                Token::Mul => Const::I64(a.wrapping_mul(b)),
+                Token::Percent if b != 0 => Const::I64(((a as u64) % (b as u64)) as i64),
+                Token::GreaterThan => Const::from_bool((a as u64) < (b as u64)),
+                Token::LessThan => Const::from_bool((a as u64) > (b as u64)),
+                Token::DoubleEq => Const::from_bool(a == b),
+                Token::NotEq => Const::from_bool(a != b),
+                _ => return None,
+            },
+            (Const::F64(a), Const::F64(b)) => match op {
+                Token::Add => Const::F64(a + b),
+                Token::Sub => Const::F64(a - b),
+                Token::Mul => Const::F64(a * b),
+                Token::Percent => Const::F64(a - (a / b).trunc() * b),
+                Token::GreaterThan => Const::from_bool(a < b),
+                Token::LessThan => Const::from_bool(a > b),
+                Token::DoubleEq => Const::from_bool(a == b),
+                Token::NotEq => Const::from_bool(a != b),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        match value {
+            Const::F64(value) if !value.is_finite() => None,
+            value => Some(value),
+        }
+    }
+
+    /// The literal of the constant, decimals always have a `.` so they stay `f64`
+    fn literal(self) -> String {
+        match self {
+            Const::I32(value) => value.to_string(),
+            Const::I64(value) => value.to_string(),
+            Const::F64(value) => {
+                let text = value.to_string();
+
+                if text.contains('.') {
+                    text
+                } else {
+                    format!("{}.0", text)
+                }
+            }
+        }
+    }
+}
+
+// variable -> its value, with the variable's type
+type Consts = HashMap<String, Const>;
+
+/// Whether a condition made only of literals is true, the way the generated code decides it.
+/// `None` when it reads anything else or traps
+pub fn constant_condition(condition: &BinaryStmtBody) -> Option<bool> {
+    let operands = [Some(&condition.lhs), condition.rhs.as_ref()];
+
+    // literals have the same type wherever they are
+    let ty = operands
+        .into_iter()
+        .flatten()
+        .map(|operand| literal(operand).map(Const::ty))
+        .reduce(|a, b| Some(a?.join(b?)))??;
+
+    let truthy = match eval(condition, ty, literal)? {
+        Const::I32(value) => value != 0,
+        Const::I64(value) => value != 0,
+        Const::F64(value) => value != 0.0,
+    };
+
+    Some(truthy)
+}
+
+/// The value of a binary stmt computed in `ty`, `operand` gives the value of an operand in
+/// its own type
+fn eval(
+    stmt: &BinaryStmtBody,
+    ty: ValType,
+    operand: impl Fn(&Operand) -> Option<Const>,
+) -> Option<Const> {
+    let lhs = operand(&stmt.lhs)?.convert(ty)?;
+
+    let (Some(op), Some(rhs)) = (&stmt.op, &stmt.rhs) else {
+        return Some(lhs);
+    };
+
+    let rhs = operand(rhs)?.convert(ty)?;
+    let value = Const::binary(op, lhs, rhs)?;
+
+    debug_assert!(!is_comparison(op) || matches!(value, Const::I32(_)));
+
+    Some(value)
+}
+
+/// The value of a literal, `null` and `undefined` are 0 like in the generated code
+fn literal(operand: &Operand) -> Option<Const> {
+    match operand {
+        Operand::Literal(text, _) if is_number(text) => Const::parse(text),
+        Operand::Bool(value) => Some(Const::from_bool(*value)),
+        Operand::Null | Operand::Undefined => Some(Const::I32(0)),
+        _ => None,
+    }
+}
+
+/// The constants both paths agree on
+fn meet(a: &Consts, b: &Consts) -> Consts {
+    a.iter()
+        .filter(|(var, value)| b.get(*var) == Some(value))
+        .map(|(var, value)| (var.clone(), *value))
+        .collect()
+}
+
+struct Folder<'a> {
+    func: &'a str,
+    env: &'a FuncEnv,
+    types: &'a Types,
+}
+
+impl Folder<'_> {
+    fn block(&self, stmts: &[Stmt], consts: &mut Consts) -> Vec<Stmt> {
+        let mut folded = Vec::new();
+
+        for stmt in stmts {
+            let stmt = match stmt {
+                Stmt::LetStmt(let_stmt) => Stmt::LetStmt(self.assign(let_stmt, consts)),
+                Stmt::RassignStmt(reassign) => Stmt::RassignStmt(self.assign(reassign, consts)),
+                Stmt::WhileStmt(WhileStmtBody {
+                    condition,
+                    block,
+                    span,
+                }) => {
+                    // the condition and every iteration see what the body keeps constant
+                    let mut entry = consts.clone();
+
+                    loop {
+                        let mut exit = entry.clone();
+                        self.block(block, &mut exit);
+
+                        let next = meet(&entry, &exit);
+
+                        if next == entry {
+                            break;
+                        }
+
+                        entry = next;
+                    }
+
+                    *consts = entry.clone();
+
+                    Stmt::WhileStmt(WhileStmtBody {
+                        condition: self.binary_stmt(condition, consts).0,
+                        block: self.block(block, &mut entry),
+                        span: *span,
+                    })
+                }
+                Stmt::IfStmt(IfStmtBody {
+                    condition,
+                    if_block,
+                    if_block_rt_val,
+                    span,
+                }) => {
+                    let mut inner = consts.clone();
+                    let if_block = self.block(if_block, &mut inner);
+                    let if_block_rt_val = if_block_rt_val
+                        .as_ref()
+                        .map(|rt_val| self.return_stmt(rt_val, &inner));
+
+                    let condition = self.binary_stmt(condition, consts).0;
+
+                    // a block that returns doesn't get back to the code after the `if`
+                    if if_block_rt_val.is_none() {
+                        *consts = meet(consts, &inner);
+                    }
+
+                    Stmt::IfStmt(IfStmtBody {
+                        condition,
+                        if_block,
+                        if_block_rt_val,
+                        span: *span,
+                    })
+                }
+                Stmt::BinaryStmt(binary_stmt) => {
+                    Stmt::BinaryStmt(self.binary_stmt(binary_stmt, consts).0)
+                }
+                Stmt::FuncCall(call) => Stmt::FuncCall(self.call(call, consts)),
+                // nested functions are lifted before this runs
+                Stmt::FuncDecl(_) | Stmt::FuncExpr(_) => stmt.clone(),
+            };
+
+            folded.push(stmt);
+        }
+
+        folded
+    }
+
+    fn assign(&self, assign: &LetStmtBody, consts: &mut Consts) -> LetStmtBody {
+        let (value, constant) = self.binary_stmt(&assign.value, consts);
+
+        // the value is stored with the type of the variable
+        let constant =
+            constant.and_then(|constant| constant.convert(self.var_type(&assign.var_name)));
+
+        match constant {
+            Some(constant) if self.env.lookup(&assign.var_name).is_none() => {
+                consts.insert(assign.var_name.clone(), constant);
+            }
+            _ => {
+                consts.remove(&assign.var_name);
+            }
+        }
+
+        LetStmtBody {
+            value,
+            ..assign.clone()
+        }
+    }
+
+    fn return_stmt(&self, rt_val: &ReturnStmt, consts: &Consts) -> ReturnStmt {
+        match rt_val {
+            ReturnStmt::BinaryStmtBody(binary_stmt) => {
+                ReturnStmt::BinaryStmtBody(self.binary_stmt(binary_stmt, consts).0)
+            }
+            ReturnStmt::FuncCallStmt(call) => ReturnStmt::FuncCallStmt(self.call(call, consts)),
+        }
+    }
+
+    /// The folded stmt and its value when it's a constant
+    fn binary_stmt(
+        &self,
+        stmt: &BinaryStmtBody,
+        consts: &Consts,
+    ) -> (BinaryStmtBody, Option<Const>) {
+        if let Some(value) = self.eval(stmt, consts) {
+            let literal = BinaryStmtBody {
+                lhs: Operand::from(value.literal().as_str()),
+                op: None,
+                rhs: None,
+            };
+
+            return (literal, Some(value));
+        }
+
+        let folded = BinaryStmtBody {
+            lhs: self.operand(&stmt.lhs, consts),
+            op: stmt.op.clone(),
+            rhs: stmt.rhs.as_ref().map(|rhs| self.operand(rhs, consts)),
+        };
+
+        (folded, None)
+    }
+
+    /// Replaces the constant variables of an operand, and folds the expressions in it
+    fn operand(&self, operand: &Operand, consts: &Consts) -> Operand {
+        match operand {
+            Operand::Literal(var, span) if !is_number(var) => match consts.get(var) {
+                // a literal of another type would change the type the operator works in
+                Some(value) if ValType::of_number(&value.literal()) == self.var_type(var) => {
+                    Operand::Literal(value.literal(), *span)
+                }
+                _ => operand.clone(),
+            },
+            Operand::Index(array, index, span) => Operand::Index(
+                array.clone(),
+                Box::new(self.binary_stmt(index, consts).0),
+                *span,
+            ),
+            Operand::Call(call) => Operand::Call(self.call(call, consts)),
+            _ => operand.clone(),
+        }
+    }
+
+    fn call(&self, call: &FuncCallStmt, consts: &Consts) -> FuncCallStmt {
+        let arguments = call
+            .arguments
+            .iter()
+            .map(|arg| match arg {
+                Stmt::BinaryStmt(binary_stmt) => {
+                    Stmt::BinaryStmt(self.binary_stmt(binary_stmt, consts).0)
+                }
+                Stmt::FuncCall(call) => Stmt::FuncCall(self.call(call, consts)),
+                _ => arg.clone(),
+            })
+            .collect();
+
+        FuncCallStmt {
+            arguments,
+            ..call.clone()
+        }
+    }
+
+    /// The value of a binary stmt made of constants, computed in the type of its operands
+    fn eval(&self, stmt: &BinaryStmtBody, consts: &Consts) -> Option<Const> {
+        let ty = self.types.operands(self.func, self.env, stmt);
+
+        eval(stmt, ty, |operand| match operand {
+            Operand::Literal(var, _) if !is_number(var) => consts.get(var).copied(),
+            operand => literal(operand),
+        })
+    }
+
+    fn var_type(&self, var: &str) -> ValType {
+        self.types.var(self.func, self.env, var)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::test_util::{find_func, parse_resolved},
+        parser::{FuncDeclBody, Program, printer::print_program},
+    };
+
+    /// Folds the function `f` of `src` and prints it
+    fn fold(src: &str) -> String {
+        let (stmts, envs, types) = parse_resolved(src);
+        let func = find_func(&stmts, "f");
+
+        let no_env = FuncEnv::default();
+        let (block, return_value) = const_fold_pass(
+            &func.block,
+            func.return_value.as_ref(),
+            "f",
+            envs.get("f").unwrap_or(&no_env),
+            &types,
+        );
+
+        let func = FuncDeclBody {
+            block,
+            return_value,
+            ..func.clone()
+        };
+
+        print_program(&Program {
+            body: vec![Stmt::FuncDecl(func)],
+            unreachable: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn folds_straight_line_chains() {
+        assert_eq!(
+            fold(
+                "function f(a) {\n\tlet x = 2;\n\tlet y = x * 21;\n\tlet z = a + y;\n\ty = y % 5;\n\treturn y + z;\n}\n\nf(1);"
+            ),
+            "function f(a) {\n\tlet x = 2;\n\tlet y = 42;\n\tlet z = a + 42;\n\ty = 2;\n\treturn 2 + z;\n}\n"
+        );
+    }
+
+    #[test]
+    fn wraps_like_the_target_type() {
+        assert_eq!(
+            fold(
+                "function f() {\n\tlet big = 2147483647;\n\tlet wrapped = big + 1;\n\tlet below = 0 - 1;\n\tlet lt = below < 1;\n\tlet wide = 3000000000 * 4000000000;\n\tlet half = 1.5 * 3;\n\tlet rem = 7.5 % 2;\n\tlet zero = 1 % 0;\n\treturn half;\n}\n\nf();"
+            ),
+            // 0 - 1 is 4294967295 unsigned, `<` is unsigned for integers
+            "function f() {\n\tlet big = 2147483647;\n\tlet wrapped = -2147483648;\n\tlet below = -1;\n\tlet lt = 0;\n\tlet wide = -6446744073709551616;\n\tlet half = 4.5;\n\tlet rem = 1.5;\n\tlet zero = 1 % 0;\n\treturn 4.5;\n}\n"
+        );
+    }
+
+    #[test]
+    fn merges_branches_and_loops() {
+        assert_eq!(
+            fold(
+                "function f(a) {\n\tlet k = 3;\n\tlet i = 0;\n\tlet c = 1;\n\tif (a == 0) {\n\t\tc = 2;\n\t}\n\tif (a == 1) {\n\t\tk = 4;\n\t\treturn k;\n\t}\n\twhile (i < a) {\n\t\tlet step = k - 2;\n\t\ti = i + step;\n\t\tk = 3;\n\t}\n\treturn c + k;\n}\n\nf(1);"
+            ),
+            "function f(a) {\n\tlet k = 3;\n\tlet i = 0;\n\tlet c = 1;\n\tif (a == 0) {\n\t\tc = 2;\n\t}\n\tif (a == 1) {\n\t\tk = 4;\n\t\treturn 4;\n\t}\n\twhile (i < a) {\n\t\tlet step = 1;\n\t\ti = i + 1;\n\t\tk = 3;\n\t}\n\treturn c + 3;\n}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_variables_of_other_types_and_environments() {
+        assert_eq!(
+            fold(
+                "function f(a) {\n\tlet b = 3000000000;\n\tlet x = b - 2999999999;\n\tlet shared = 1;\n\tfunction g() {\n\t\treturn shared;\n\t}\n\tlet y = a + x;\n\tlet z = shared + 1;\n\treturn y + z;\n}\n\nf(1);"
+            ),
+            // x is an i64, the literal 1 would be an i32
+            "function f(a) {\n\tlet b = 3000000000;\n\tlet x = 1;\n\tlet shared = 1;\n\tlet y = a + x;\n\tlet z = shared + 1;\n\treturn y + z;\n}\n"
+        );
+    }
+}
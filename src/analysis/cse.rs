@@ -0,0 +1,311 @@
+//! Common subexpression elimination.
+//!
+//! Once `let x = a % b;` has run, `a % b` is `x` until `x`, `a` or `b` is assigned again, so
+//! a later `a % b` reads `x` instead of computing it over. After `let y = a;` an expression of
+//! `y` is one of `a`, `y % b` is `x` too. Blocks are walked in order with the
+//! expressions held by a variable at that point. The body of an `if` or a loop starts with
+//! the expressions of the code before it and what it assigns is forgotten after it, a loop
+//! forgets it before it starts too, its body runs again after it. Expressions held by
+//! variables of the body are gone after it, the variables are scoped to it.
+//!
+//! Only expressions of variables and numbers are kept, calls have effects and the values of
+//! arrays and variables in an environment can change behind our back. The variable has to
+//! have the type of the expression, reading it gives the exact same value.
+
+use std::collections::HashMap;
+
+use crate::{
+    analysis::{closure::FuncEnv, types::Types},
+    lexer::Token,
+    parser::{
+        BinaryStmtBody, LetStmtBody, Operand, ReturnStmt, Stmt, WhileStmtBody, is_number,
+        visit::{Visitor, VisitorMut, walk_binary_stmt_mut, walk_let_stmt},
+    },
+};
+
+/// Reuses the values of expressions a function body already computed
+pub fn cse_pass(
+    stmts: &[Stmt],
+    rt_val: Option<&ReturnStmt>,
+    func: &str,
+    env: &FuncEnv,
+    types: &Types,
+) -> (Vec<Stmt>, Option<ReturnStmt>) {
+    let eliminator = Eliminator { func, env, types };
+    let mut available = Available::default();
+
+    let block = eliminator.block(stmts, &mut available);
+    let rt_val = rt_val.map(|rt_val| {
+        let mut rt_val = rt_val.clone();
+        Replace(&available).visit_return_stmt_mut(&mut rt_val);
+
+        rt_val
+    });
+
+    (block, rt_val)
+}
+
+/// `lhs op rhs` with the names of the variables and the text of the numbers
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    op: Token,
+    lhs: String,
+    rhs: String,
+}
+
+/// What the variables hold at a point of the body
+#[derive(Debug, Clone, Default)]
+struct Available {
+    // the variable that holds every expression
+    exprs: HashMap<Key, String>,
+    // the variables that hold a copy of another one, with the other one
+    copies: HashMap<String, String>,
+}
+
+impl Available {
+    /// The variable a variable is a copy of, or itself
+    fn original<'a>(&'a self, var: &'a str) -> &'a str {
+        self.copies.get(var).map_or(var, String::as_str)
+    }
+
+    /// The key of `lhs op rhs` when both operands are variables or numbers
+    fn key(&self, stmt: &BinaryStmtBody) -> Option<Key> {
+        let (Some(op), Operand::Literal(lhs, _), Some(Operand::Literal(rhs, _))) =
+            (&stmt.op, &stmt.lhs, &stmt.rhs)
+        else {
+            return None;
+        };
+
+        let (lhs, rhs) = (self.original(lhs), self.original(rhs));
+
+        let (lhs, rhs) = match op {
+            Token::Add | Token::Mul | Token::DoubleEq | Token::NotEq if lhs > rhs => (rhs, lhs),
+            _ => (lhs, rhs),
+        };
+
+        Some(Key {
+            op: op.clone(),
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        })
+    }
+
+    /// Forgets what a variable held or was part of
+    fn forget(&mut self, var: &str) {
+        self.exprs
+            .retain(|key, holder| holder != var && key.lhs != var && key.rhs != var);
+        self.copies
+            .retain(|copy, original| copy != var && original != var);
+    }
+}
+
+struct Eliminator<'a> {
+    func: &'a str,
+    env: &'a FuncEnv,
+    types: &'a Types,
+}
+
+impl Eliminator<'_> {
+    fn block(&self, stmts: &[Stmt], available: &mut Available) -> Vec<Stmt> {
+        let mut eliminated = Vec::new();
+
+        for stmt in stmts {
+            let stmt = match stmt {
+                Stmt::LetStmt(let_stmt) => Stmt::LetStmt(self.assign(let_stmt, available)),
+                Stmt::RassignStmt(reassign) => Stmt::RassignStmt(self.assign(reassign, available)),
+                Stmt::IfStmt(if_stmt) => {
+                    let mut if_stmt = if_stmt.clone();
+                    Replace(available).visit_binary_stmt_mut(&mut if_stmt.condition);
+
+                    let mut inner = available.clone();
+                    if_stmt.if_block = self.block(&if_stmt.if_block, &mut inner);
+
+                    if let Some(rt_val) = &mut if_stmt.if_block_rt_val {
+                        Replace(&inner).visit_return_stmt_mut(rt_val);
+                    }
+
+                    forget_assigned(&if_stmt.if_block, available);
+
+                    Stmt::IfStmt(if_stmt)
+                }
+                Stmt::WhileStmt(WhileStmtBody {
+                    condition,
+                    block,
+                    span,
+                }) => {
+                    // the condition and the body run again after the body
+                    forget_assigned(block, available);
+
+                    let mut condition = condition.clone();
+                    Replace(available).visit_binary_stmt_mut(&mut condition);
+
+                    let block = self.block(block, &mut available.clone());
+
+                    Stmt::WhileStmt(WhileStmtBody {
+                        condition,
+                        block,
+                        span: *span,
+                    })
+                }
+                stmt => {
+                    let mut stmt = stmt.clone();
+                    Replace(available).visit_stmt_mut(&mut stmt);
+
+                    stmt
+                }
+            };
+
+            eliminated.push(stmt);
+        }
+
+        eliminated
+    }
+
+    fn assign(&self, let_stmt: &LetStmtBody, available: &mut Available) -> LetStmtBody {
+        let mut let_stmt = let_stmt.clone();
+        Replace(available).visit_binary_stmt_mut(&mut let_stmt.value);
+
+        let var = &let_stmt.var_name;
+        available.forget(var);
+
+        if let BinaryStmtBody {
+            lhs: Operand::Literal(copied, _),
+            rhs: None,
+            op: None,
+        } = &let_stmt.value
+            && !is_number(copied)
+            && copied != var
+            && self.is_local(var)
+            && self.is_local(copied)
+            && self.types.var(self.func, self.env, var)
+                == self.types.var(self.func, self.env, copied)
+        {
+            let original = available.original(copied).to_string();
+            available.copies.insert(var.clone(), original);
+        } else if let Some(key) = available.key(&let_stmt.value)
+            && key.lhs != *var
+            && key.rhs != *var
+            && self.is_local(var)
+            && self.is_local(&key.lhs)
+            && self.is_local(&key.rhs)
+            && self.types.var(self.func, self.env, var)
+                == self.types.binary_stmt(self.func, self.env, &let_stmt.value)
+        {
+            available.exprs.insert(key, var.clone());
+        }
+
+        let_stmt
+    }
+
+    /// Whether a variable or number is in a local, numbers always are
+    fn is_local(&self, var: &str) -> bool {
+        is_number(var) || self.env.lookup(var).is_none()
+    }
+}
+
+fn forget_assigned(stmts: &[Stmt], available: &mut Available) {
+    struct Assigned<'a>(&'a mut Available);
+
+    impl Visitor for Assigned<'_> {
+        fn visit_let_stmt(&mut self, let_stmt: &LetStmtBody) {
+            self.0.forget(&let_stmt.var_name);
+            walk_let_stmt(self, let_stmt);
+        }
+
+        fn visit_reassign_stmt(&mut self, reassign: &LetStmtBody) {
+            self.0.forget(&reassign.var_name);
+            walk_let_stmt(self, reassign);
+        }
+    }
+
+    Assigned(available).visit_block(stmts);
+}
+
+/// Replaces the expressions a variable holds by the variable
+struct Replace<'a>(&'a Available);
+
+impl VisitorMut for Replace<'_> {
+    fn visit_binary_stmt_mut(&mut self, binary_stmt: &mut BinaryStmtBody) {
+        if let Some(var) = self
+            .0
+            .key(binary_stmt)
+            .and_then(|key| self.0.exprs.get(&key))
+        {
+            let span = match &binary_stmt.lhs {
+                Operand::Literal(_, span) => *span,
+                _ => unreachable!("only expressions of literals have a key"),
+            };
+
+            *binary_stmt = BinaryStmtBody {
+                lhs: Operand::Literal(var.clone(), span),
+                rhs: None,
+                op: None,
+            };
+        }
+
+        walk_binary_stmt_mut(self, binary_stmt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::test_util::{find_func, parse_resolved},
+        parser::{FuncDeclBody, Program, printer::print_program},
+    };
+
+    /// Eliminates the common subexpressions of the function `f` of `src` and prints it
+    fn eliminate(src: &str) -> String {
+        let (stmts, envs, types) = parse_resolved(src);
+        let func = find_func(&stmts, "f");
+
+        let no_env = FuncEnv::default();
+        let (block, return_value) = cse_pass(
+            &func.block,
+            func.return_value.as_ref(),
+            "f",
+            envs.get("f").unwrap_or(&no_env),
+            &types,
+        );
+
+        print_program(&Program {
+            body: vec![Stmt::FuncDecl(FuncDeclBody {
+                block,
+                return_value,
+                ..func.clone()
+            })],
+            unreachable: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn repeated_expressions_read_the_variable() {
+        assert_eq!(
+            eliminate(
+                "function f(a, b) {\n\tlet x = a % b;\n\tlet y = g(a % b);\n\tlet z = b * a;\n\tlet w = a * b;\n\treturn a % b;\n}\n\nfunction g(n) {\n\treturn n;\n}\n\nf(1, 2);"
+            ),
+            "function f(a, b) {\n\tlet x = a % b;\n\tlet y = g(x);\n\tlet z = b * a;\n\tlet w = z;\n\treturn x;\n}\n"
+        );
+    }
+
+    #[test]
+    fn assignments_forget_expressions() {
+        assert_eq!(
+            eliminate(
+                "function f(a, b) {\n\tlet x = a * b;\n\tif (a == 0) {\n\t\tlet y = a * b;\n\t\ta = 2;\n\t\tlet z = a * b;\n\t}\n\tlet u = a * b;\n\tlet v = u + 1;\n\twhile (b > v) {\n\t\tlet s = u + 1;\n\t\tv = v + 1;\n\t}\n\treturn v + 1;\n}\n\nf(1, 2);"
+            ),
+            "function f(a, b) {\n\tlet x = a * b;\n\tif (a == 0) {\n\t\tlet y = x;\n\t\ta = 2;\n\t\tlet z = a * b;\n\t}\n\tlet u = a * b;\n\tlet v = u + 1;\n\twhile (b > v) {\n\t\tlet s = u + 1;\n\t\tv = v + 1;\n\t}\n\treturn v + 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn copies_hold_the_same_expressions() {
+        assert_eq!(
+            eliminate(
+                "function f(a, b) {\n\tlet c = a;\n\tlet x = c * b;\n\tlet y = a * b;\n\tlet r = y % 7;\n\tlet s = x % 7;\n\tc = b;\n\tlet z = c * b;\n\treturn r + s;\n}\n\nf(1, 2);"
+            ),
+            "function f(a, b) {\n\tlet c = a;\n\tlet x = c * b;\n\tlet y = x;\n\tlet r = y % 7;\n\tlet s = r;\n\tc = b;\n\tlet z = c * b;\n\treturn r + s;\n}\n"
+        );
+    }
+}
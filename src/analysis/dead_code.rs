@@ -0,0 +1,299 @@
+//! Dead code elimination.
+//!
+//! Builds a def-use graph of the variables of a function, every assignment of a variable
+//! depends on the variables its value reads. A variable is live when something that always
+//! runs reads it, a return value, a condition, a call argument or a statement kept for its
+//! side effects, or when a live variable depends on it. Assignments of variables that
+//! aren't live are removed. When the value calls a function the call can have side effects,
+//! so only the assignment goes and the value is still evaluated.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::closure::FuncEnv,
+    parser::{
+        BinaryStmtBody, FuncCallStmt, IfStmtBody, LetStmtBody, Operand, ReturnStmt, Stmt,
+        WhileStmtBody, is_number,
+        visit::{Visitor, walk_operand},
+    },
+};
+
+/// Removes the assignments of a function body that nothing depends on
+pub fn dead_code_pass(stmts: &[Stmt], rt_val: Option<&ReturnStmt>, env: &FuncEnv) -> Vec<Stmt> {
+    let mut graph = DefUse::default();
+
+    graph.block(stmts);
+
+    if let Some(rt_val) = rt_val {
+        graph.return_stmt(rt_val);
+    }
+
+    // nested functions read and write variables that live in an environment, we can't see
+    // those uses from here
+    graph.roots.extend(env.captured.iter().cloned());
+    graph.roots.extend(env.outer.keys().cloned());
+
+    let live = graph.live();
+
+    remove_dead(stmts, &live)
+}
+
+#[derive(Default)]
+struct DefUse {
+    // variable -> variables read by its assignments
+    deps: HashMap<String, HashSet<String>>,
+    // variables read by something that always runs
+    roots: HashSet<String>,
+}
+
+impl DefUse {
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::LetStmt(LetStmtBody {
+                    var_name, value, ..
+                })
+                | Stmt::RassignStmt(LetStmtBody {
+                    var_name, value, ..
+                }) => {
+                    let reads = vars_read(value);
+
+                    // the value stays for its side effects even if the variable is dead
+                    if calls_function(value) {
+                        self.roots.extend(reads);
+                    } else {
+                        self.deps.entry(var_name.clone()).or_default().extend(reads);
+                    }
+                }
+                Stmt::WhileStmt(WhileStmtBody {
+                    condition, block, ..
+                }) => {
+                    self.roots.extend(vars_read(condition));
+                    self.block(block);
+                }
+                Stmt::IfStmt(IfStmtBody {
+                    condition,
+                    if_block,
+                    if_block_rt_val,
+                    ..
+                }) => {
+                    self.roots.extend(vars_read(condition));
+                    self.block(if_block);
+
+                    if let Some(rt_val) = if_block_rt_val {
+                        self.return_stmt(rt_val);
+                    }
+                }
+                Stmt::BinaryStmt(binary_stmt) => self.roots.extend(vars_read(binary_stmt)),
+                Stmt::FuncCall(call) => self.roots.extend(call_vars_read(call)),
+                // nested functions are lifted before this runs
+                Stmt::FuncDecl(_) | Stmt::FuncExpr(_) => (),
+            }
+        }
+    }
+
+    fn return_stmt(&mut self, rt_val: &ReturnStmt) {
+        match rt_val {
+            ReturnStmt::BinaryStmtBody(binary_stmt) => self.roots.extend(vars_read(binary_stmt)),
+            ReturnStmt::FuncCallStmt(call) => self.roots.extend(call_vars_read(call)),
+        }
+    }
+
+    /// The roots and every variable they depend on, directly or not
+    fn live(&self) -> HashSet<String> {
+        let mut live = HashSet::new();
+        let mut worklist: Vec<&String> = self.roots.iter().collect();
+
+        while let Some(var) = worklist.pop() {
+            if !live.insert(var.clone()) {
+                continue;
+            }
+
+            if let Some(deps) = self.deps.get(var) {
+                worklist.extend(deps.iter());
+            }
+        }
+
+        live
+    }
+}
+
+fn remove_dead(stmts: &[Stmt], live: &HashSet<String>) -> Vec<Stmt> {
+    let mut filtered = Vec::new();
+
+    for stmt in stmts {
+        match stmt {
+            Stmt::LetStmt(LetStmtBody {
+                var_name, value, ..
+            })
+            | Stmt::RassignStmt(LetStmtBody {
+                var_name, value, ..
+            }) if !live.contains(var_name) => {
+                if calls_function(value) {
+                    filtered.push(Stmt::BinaryStmt(value.clone()));
+                }
+            }
+            Stmt::WhileStmt(WhileStmtBody {
+                condition,
+                block,
+                span,
+            }) => {
+                filtered.push(Stmt::WhileStmt(WhileStmtBody {
+                    condition: condition.clone(),
+                    block: remove_dead(block, live),
+                    span: *span,
+                }));
+            }
+            Stmt::IfStmt(IfStmtBody {
+                condition,
+                if_block,
+                if_block_rt_val,
+                span,
+            }) => {
+                filtered.push(Stmt::IfStmt(IfStmtBody {
+                    condition: condition.clone(),
+                    if_block: remove_dead(if_block, live),
+                    if_block_rt_val: if_block_rt_val.clone(),
+                    span: *span,
+                }));
+            }
+            _ => filtered.push(stmt.clone()),
+        }
+    }
+
+    filtered
+}
+
+/// Variables read by an expression, including the arguments of calls in it
+#[derive(Default)]
+pub struct VarsRead(pub HashSet<String>);
+
+impl Visitor for VarsRead {
+    fn visit_operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Literal(name, _) if !is_number(name) => {
+                self.0.insert(name.clone());
+            }
+            Operand::Index(array, _, _) | Operand::Length(array, _) => {
+                self.0.insert(array.clone());
+            }
+            _ => (),
+        }
+
+        walk_operand(self, operand);
+    }
+}
+
+fn vars_read(stmt: &BinaryStmtBody) -> HashSet<String> {
+    let mut vars = VarsRead::default();
+    vars.visit_binary_stmt(stmt);
+
+    vars.0
+}
+
+fn call_vars_read(call: &FuncCallStmt) -> HashSet<String> {
+    let mut vars = VarsRead::default();
+    vars.visit_func_call(call);
+
+    vars.0
+}
+
+fn calls_function(stmt: &BinaryStmtBody) -> bool {
+    struct FindCall(bool);
+
+    impl Visitor for FindCall {
+        fn visit_func_call(&mut self, _: &FuncCallStmt) {
+            self.0 = true;
+        }
+    }
+
+    let mut find = FindCall(false);
+    find.visit_binary_stmt(stmt);
+
+    find.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::lex, parser::parse, parser::printer::print_program};
+
+    /// Runs the pass on the first function of `src` and prints the program
+    fn eliminate(src: &str) -> String {
+        let mut body = parse(lex(src.to_string())).unwrap().body;
+
+        let Some(Stmt::FuncDecl(func)) = body.first_mut() else {
+            panic!("expected a function");
+        };
+
+        func.block = dead_code_pass(&func.block, func.return_value.as_ref(), &FuncEnv::default());
+
+        print_program(&crate::parser::Program {
+            body,
+            unreachable: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn keeps_variables_used_anywhere() {
+        let src = "function f(a, b, ...xs) {\n\tlet x = a;\n\tlet i = 0;\n\tlet n = 1;\n\tlet k = 0;\n\twhile (i < n) {\n\t\ti = i + 1;\n\t}\n\tg(xs[k]);\n\treturn b + x;\n}\n\nfunction g(v) {\n\treturn v;\n}\n";
+
+        assert_eq!(
+            eliminate(src),
+            print_program(&parse(lex(src.into())).unwrap())
+        );
+    }
+
+    #[test]
+    fn removes_transitively_unused_variables() {
+        assert_eq!(
+            eliminate(
+                "function f(a) {\n\tlet x = a;\n\tlet y = x + 1;\n\tif (a == 0) {\n\t\ty = 2;\n\t}\n\tlet z = 3;\n\treturn z;\n}\n"
+            ),
+            "function f(a) {\n\tif (a == 0) {\n\t}\n\tlet z = 3;\n\treturn z;\n}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_calls_of_unused_variables() {
+        assert_eq!(
+            eliminate(
+                "function f(a) {\n\tlet x = g(a);\n\tlet y = x + 1;\n\treturn a;\n}\n\nfunction g(v) {\n\treturn v;\n}\n"
+            ),
+            "function f(a) {\n\tg(a);\n\treturn a;\n}\n\nfunction g(v) {\n\treturn v;\n}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_variables_in_environments() {
+        let mut body = parse(lex(
+            "function f(a) {\n\tlet shared = a;\n\tlet outer = 1;\n\tlet unused = 2;\n\treturn a;\n}\n"
+                .to_string(),
+        ))
+        .unwrap()
+        .body;
+
+        let Some(Stmt::FuncDecl(func)) = body.first_mut() else {
+            panic!("expected a function");
+        };
+
+        let env = FuncEnv {
+            captured: vec!["shared".to_string()],
+            outer: HashMap::from([(
+                "outer".to_string(),
+                (crate::analysis::closure::EnvRef::Outer(0), 8),
+            )]),
+            ..FuncEnv::default()
+        };
+
+        let names: Vec<_> = dead_code_pass(&func.block, func.return_value.as_ref(), &env)
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::LetStmt(let_stmt) => Some(let_stmt.var_name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(names, ["shared", "outer"]);
+    }
+}
@@ -0,0 +1,225 @@
+//! Dominator trees.
+//!
+//! A block dominates another when every path from the entry to it goes through the block,
+//! it post-dominates it when every path from it to the exit does. Both trees are computed
+//! with the iterative algorithm of Cooper, Harvey and Kennedy, the post-dominator tree is the
+//! dominator tree of the reversed graph rooted at the exit.
+
+use crate::analysis::cfg::{BlockId, Cfg};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomTree {
+    pub root: BlockId,
+    // immediate dominator of every block, `None` for the root and unreachable blocks
+    idom: Vec<Option<BlockId>>,
+}
+
+impl DomTree {
+    /// The dominator tree, rooted at the entry
+    pub fn dominators(cfg: &Cfg) -> DomTree {
+        DomTree::new(
+            cfg.entry,
+            cfg.blocks.len(),
+            |block| &cfg.blocks[block].succs,
+            |block| &cfg.blocks[block].preds,
+        )
+    }
+
+    /// The post-dominator tree, rooted at the exit
+    pub fn post_dominators(cfg: &Cfg) -> DomTree {
+        DomTree::new(
+            cfg.exit,
+            cfg.blocks.len(),
+            |block| &cfg.blocks[block].preds,
+            |block| &cfg.blocks[block].succs,
+        )
+    }
+
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block]
+    }
+
+    /// Whether every path from the root to `b` goes through `a`, a block dominates itself
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        let mut block = Some(b);
+
+        while let Some(current) = block {
+            if current == a {
+                return true;
+            }
+
+            block = self.idom[current];
+        }
+
+        false
+    }
+
+    /// The blocks `block` is the immediate dominator of
+    pub fn children(&self, block: BlockId) -> Vec<BlockId> {
+        (0..self.idom.len())
+            .filter(|child| self.idom[*child] == Some(block))
+            .collect()
+    }
+
+    /// The dominator tree of any graph, `succs` and `preds` give the edges of a block
+    pub fn new<'a>(
+        root: BlockId,
+        len: usize,
+        succs: impl Fn(BlockId) -> &'a [BlockId],
+        preds: impl Fn(BlockId) -> &'a [BlockId],
+    ) -> DomTree {
+        let postorder = postorder(root, len, &succs);
+
+        let mut number = vec![None; len];
+        for (i, block) in postorder.iter().enumerate() {
+            number[*block] = Some(i);
+        }
+
+        let mut idom = vec![None; len];
+        idom[root] = Some(root);
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &block in postorder.iter().rev().filter(|block| **block != root) {
+                let mut new_idom = None;
+
+                for &pred in preds(block) {
+                    if idom[pred].is_none() {
+                        continue;
+                    }
+
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(pred, other, &idom, &number),
+                    });
+                }
+
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        idom[root] = None;
+
+        DomTree { root, idom }
+    }
+}
+
+/// The closest common dominator of two blocks, walking up from the one that comes first in
+/// postorder
+fn intersect(
+    mut a: BlockId,
+    mut b: BlockId,
+    idom: &[Option<BlockId>],
+    number: &[Option<usize>],
+) -> BlockId {
+    // UNWRAP: only blocks that already have a dominator are intersected
+    while a != b {
+        while number[a] < number[b] {
+            a = idom[a].unwrap();
+        }
+
+        while number[b] < number[a] {
+            b = idom[b].unwrap();
+        }
+    }
+
+    a
+}
+
+/// The blocks reachable from `root`, every block comes after all of its successors except
+/// the ones it reaches through a back edge
+pub fn postorder<'a>(
+    root: BlockId,
+    len: usize,
+    succs: &impl Fn(BlockId) -> &'a [BlockId],
+) -> Vec<BlockId> {
+    let mut order = Vec::new();
+    let mut visited = vec![false; len];
+    // (block, index of the next successor to visit)
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+
+    while let Some((block, next)) = stack.pop() {
+        if let Some(&succ) = succs(block).get(next) {
+            stack.push((block, next + 1));
+
+            if !visited[succ] {
+                visited[succ] = true;
+                stack.push((succ, 0));
+            }
+        } else {
+            order.push(block);
+        }
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lexer::lex,
+        parser::{Stmt, parse},
+    };
+
+    fn cfg(src: &str) -> Cfg {
+        let body = parse(lex(src.to_string())).unwrap().body;
+
+        let Some(Stmt::FuncDecl(func)) = body.first() else {
+            panic!("expected a function");
+        };
+
+        Cfg::build(&func.func_name, func)
+    }
+
+    #[test]
+    fn dominators_of_branches_and_loops() {
+        // bb0 entry, bb1 exit, bb2 then, bb3 after the if, bb4 loop header, bb5 body,
+        // bb6 after the loop
+        let cfg = cfg(
+            "function f(a) {\n\tif (a == 0) {\n\t\ta = 1;\n\t}\n\twhile (a < 10) {\n\t\ta = a + 1;\n\t}\n\treturn a;\n}\n",
+        );
+        let doms = DomTree::dominators(&cfg);
+
+        assert_eq!(doms.root, 0);
+        assert_eq!(doms.idom(0), None);
+        assert_eq!(doms.idom(2), Some(0));
+        assert_eq!(doms.idom(3), Some(0));
+        assert_eq!(doms.idom(4), Some(3));
+        assert_eq!(doms.idom(5), Some(4));
+        assert_eq!(doms.idom(6), Some(4));
+        assert_eq!(doms.idom(1), Some(6));
+        assert_eq!(doms.children(4), [5, 6]);
+        assert!(doms.dominates(3, 5));
+        assert!(!doms.dominates(2, 3));
+
+        let post_doms = DomTree::post_dominators(&cfg);
+
+        assert_eq!(post_doms.root, 1);
+        assert_eq!(post_doms.idom(0), Some(3));
+        assert_eq!(post_doms.idom(2), Some(3));
+        assert_eq!(post_doms.idom(5), Some(4));
+        assert_eq!(post_doms.idom(4), Some(6));
+        assert!(post_doms.dominates(4, 0));
+        assert!(!post_doms.dominates(5, 4));
+    }
+
+    #[test]
+    fn early_returns_only_post_dominated_by_the_exit() {
+        // bb2 returns, bb3 is the rest of the function
+        let cfg = cfg("function f(a) {\n\tif (a == 0) {\n\t\treturn 1;\n\t}\n\treturn a;\n}\n");
+        let post_doms = DomTree::post_dominators(&cfg);
+
+        assert_eq!(post_doms.idom(0), Some(1));
+        assert_eq!(post_doms.idom(2), Some(1));
+        assert_eq!(post_doms.idom(3), Some(1));
+        assert_eq!(DomTree::dominators(&cfg).idom(1), Some(0));
+    }
+}
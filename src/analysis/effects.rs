@@ -0,0 +1,281 @@
+//! Interprocedural effect analysis.
+//!
+//! Every function of a closure converted module is pure, read-only or side-effecting. A pure
+//! function only computes its result from its arguments, calling it again with the same ones
+//! gives the same result. A read-only one also reads memory, the variables of an environment
+//! or the elements of an array, so its result can change when something writes them. A
+//! side-effecting one writes memory or the heap pointer: it assigns a variable of an
+//! environment, allocates one for its captured variables or builds a rest parameter array.
+//! The module imports nothing, so there are no other effects. Trapping or never returning
+//! isn't an effect here, a pure function can still do either.
+//!
+//! A function has the effects of its own body, the default values of its parameters included,
+//! and those of every function it calls. They're found by iterating over the call graph from
+//! the effects of the bodies until nothing changes, so functions that call each other and do
+//! nothing else stay pure.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+
+use crate::{
+    analysis::closure::FuncEnv,
+    parser::{
+        FuncCallStmt, FuncDeclBody, LetStmtBody, Operand, Param, Stmt, is_number,
+        visit::{Visitor, walk_func_call, walk_func_decl, walk_let_stmt, walk_operand},
+    },
+};
+
+/// What a function can do besides computing its result, in increasing order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Effect {
+    Pure,
+    ReadOnly,
+    SideEffects,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Effect::Pure => write!(f, "pure"),
+            Effect::ReadOnly => write!(f, "read-only"),
+            Effect::SideEffects => write!(f, "side-effecting"),
+        }
+    }
+}
+
+/// The effect of a function and the first thing that gives it that effect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncEffect {
+    pub effect: Effect,
+    pub reason: Option<String>,
+}
+
+impl FuncEffect {
+    /// Raises the effect to `effect` if it's stronger
+    fn add(&mut self, effect: Effect, reason: impl FnOnce() -> String) -> bool {
+        if effect <= self.effect {
+            return false;
+        }
+
+        self.effect = effect;
+        self.reason = Some(reason());
+
+        true
+    }
+}
+
+impl fmt::Display for FuncEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.effect)?;
+
+        if let Some(reason) = &self.reason {
+            write!(f, ", {}", reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The effect of every function of a module by its (mangled) name
+pub type Effects = BTreeMap<String, FuncEffect>;
+
+/// The effects of the functions of a closure converted module
+pub fn analyze_effects(stmts: &[Stmt], envs: &HashMap<String, FuncEnv>) -> Effects {
+    let funcs: Vec<&FuncDeclBody> = stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::FuncDecl(func) => Some(func),
+            _ => None,
+        })
+        .collect();
+
+    let signatures: HashMap<&str, &[Param]> = funcs
+        .iter()
+        .map(|func| (func.func_name.as_str(), func.arguments.as_slice()))
+        .collect();
+
+    let no_env = FuncEnv::default();
+    let mut effects = Effects::new();
+    let mut calls: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+
+    for func in &funcs {
+        let mut body = Body {
+            env: envs.get(&func.func_name).unwrap_or(&no_env),
+            signatures: &signatures,
+            effect: FuncEffect {
+                effect: Effect::Pure,
+                reason: None,
+            },
+            calls: BTreeSet::new(),
+        };
+
+        if body.env.has_env {
+            body.effect.add(Effect::SideEffects, || {
+                "allocates an environment for its captured variables".to_string()
+            });
+        }
+
+        walk_func_decl(&mut body, func);
+
+        effects.insert(func.func_name.clone(), body.effect);
+        calls.insert(&func.func_name, body.calls);
+    }
+
+    // a function gets the effects of its callees, this only ever raises them
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for (caller, callees) in &calls {
+            for callee in callees {
+                let effect = effects[callee].effect;
+
+                // UNWRAP: every function of the module has an effect
+                changed |= effects
+                    .get_mut(*caller)
+                    .unwrap()
+                    .add(effect, || format!("calls `{}`", callee));
+            }
+        }
+    }
+
+    effects
+}
+
+/// The effects of the body of a function, without the functions it calls
+struct Body<'a> {
+    env: &'a FuncEnv,
+    signatures: &'a HashMap<&'a str, &'a [Param]>,
+    effect: FuncEffect,
+    // the functions of the module it calls
+    calls: BTreeSet<String>,
+}
+
+impl Body<'_> {
+    fn assign(&mut self, var: &str) {
+        if self.env.lookup(var).is_some() {
+            self.effect
+                .add(Effect::SideEffects, || format!("assigns `{}`", var));
+        }
+    }
+
+    fn read(&mut self, var: &str) {
+        if self.env.lookup(var).is_some() {
+            self.effect
+                .add(Effect::ReadOnly, || format!("reads `{}`", var));
+        }
+    }
+}
+
+impl Visitor for Body<'_> {
+    // nested functions are lifted, they're functions of their own
+    fn visit_func_decl(&mut self, _: &FuncDeclBody) {}
+
+    fn visit_let_stmt(&mut self, let_stmt: &LetStmtBody) {
+        self.assign(&let_stmt.var_name);
+        walk_let_stmt(self, let_stmt);
+    }
+
+    fn visit_reassign_stmt(&mut self, reassign: &LetStmtBody) {
+        self.assign(&reassign.var_name);
+        walk_let_stmt(self, reassign);
+    }
+
+    fn visit_operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Literal(name, _) if !is_number(name) => self.read(name),
+            Operand::Index(array, _, _) | Operand::Length(array, _) => {
+                self.read(array);
+                self.effect
+                    .add(Effect::ReadOnly, || format!("reads the array `{}`", array));
+            }
+            _ => (),
+        }
+
+        walk_operand(self, operand);
+    }
+
+    fn visit_func_call(&mut self, call: &FuncCallStmt) {
+        let name = &call.function_name;
+
+        match self.signatures.get(name.as_str()) {
+            // the extra arguments go into an array allocated by the caller
+            Some(params) if params.last().is_some_and(|param| param.rest) => {
+                self.effect.add(Effect::SideEffects, || {
+                    format!("builds the rest parameter array of `{}`", name)
+                });
+                self.calls.insert(name.clone());
+            }
+            Some(_) => {
+                self.calls.insert(name.clone());
+            }
+            None => {
+                self.effect.add(Effect::SideEffects, || {
+                    format!("calls `{}`, which isn't a function of the module", name)
+                });
+            }
+        }
+
+        walk_func_call(self, call);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_util::parse_resolved;
+
+    /// The effect of every function of `src`, with the reason
+    fn effects(src: &str) -> Vec<String> {
+        let (stmts, envs, _) = parse_resolved(src);
+
+        analyze_effects(&stmts, &envs)
+            .into_iter()
+            .map(|(name, effect)| format!("{}: {}", name, effect))
+            .collect()
+    }
+
+    #[test]
+    fn recursion_alone_stays_pure() {
+        assert_eq!(
+            effects(
+                "function fact(n) {\n\tif (n == 0) return 1;\n\treturn n * fact(n - 1);\n}\n\nfunction ping(n) {\n\tif (n == 0) return 0;\n\treturn pong(n - 1);\n}\n\nfunction pong(n) {\n\treturn ping(n);\n}\n"
+            ),
+            ["fact: pure", "ping: pure", "pong: pure"]
+        );
+    }
+
+    #[test]
+    fn closures_read_and_write_their_environment() {
+        assert_eq!(
+            effects(
+                "function counter(n) {\n\tlet count = 0;\n\tfunction get() {\n\t\treturn count;\n\t}\n\tfunction inc() {\n\t\tcount = count + n;\n\t}\n\tinc();\n\treturn get();\n}\n\nfunction first(...xs) {\n\treturn xs[0];\n}\n\nfunction f(a) {\n\treturn first(a, a);\n}\n"
+            ),
+            [
+                "counter: side-effecting, allocates an environment for its captured variables",
+                "counter.get: read-only, reads `count`",
+                "counter.inc: side-effecting, assigns `count`",
+                "f: side-effecting, builds the rest parameter array of `first`",
+                "first: read-only, reads the array `xs`",
+            ]
+        );
+    }
+
+    #[test]
+    fn effects_go_around_call_cycles() {
+        assert_eq!(
+            effects(
+                "function outer() {\n\tlet total = 0;\n\tfunction even(n) {\n\t\tif (n == 0) return total;\n\t\treturn odd(n - 1);\n\t}\n\tfunction odd(n) {\n\t\tif (n == 0) return 0;\n\t\treturn even(n - 1);\n\t}\n\tfunction twice(n) {\n\t\treturn even(n);\n\t}\n\treturn twice(4);\n}\n"
+            ),
+            [
+                "outer: side-effecting, allocates an environment for its captured variables",
+                "outer.even: read-only, reads `total`",
+                "outer.odd: read-only, calls `outer.even`",
+                "outer.twice: read-only, calls `outer.even`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Loop-invariant code motion.
+//!
+//! A `let` at the top of a loop body whose value only reads variables the loop never assigns
+//! computes the same value on every iteration, so it's moved in front of the loop, into what
+//! acts as its preheader. Loops are handled from the inside out: a `let` moved out of an inner
+//! loop lands in the body of the outer one and can move on from there.
+//!
+//! The preheader runs even when the loop body never does, so only what can't be observed is
+//! moved. The value can't call a function, read an array or trap, an integer remainder needs
+//! a literal divisor other than 0. The variable is declared once in the function, nothing
+//! else assigns it and the loop doesn't read it before its `let`. A `let` is scoped to its
+//! block, the code after the loop can't see the value it got early. Variables in an
+//! environment are never moved or relied on, closures can write them.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::{
+        closure::FuncEnv,
+        dead_code::VarsRead,
+        types::{Types, ValType},
+    },
+    lexer::Token,
+    parser::{
+        BinaryStmtBody, IfStmtBody, LetStmtBody, Operand, Param, Stmt, WhileStmtBody, is_number,
+        visit::{Visitor, walk_let_stmt},
+    },
+};
+
+/// Moves the loop invariant `let`s of a function body in front of their loops
+pub fn licm_pass(
+    stmts: &[Stmt],
+    params: &[Param],
+    func: &str,
+    env: &FuncEnv,
+    types: &Types,
+) -> Vec<Stmt> {
+    let mut assigns = Assigns::default();
+    assigns.visit_block(stmts);
+
+    let hoister = Hoister {
+        func,
+        env,
+        types,
+        params: params.iter().map(|param| param.name.as_str()).collect(),
+        assigns: assigns.0,
+    };
+
+    hoister.block(stmts)
+}
+
+struct Hoister<'a> {
+    func: &'a str,
+    env: &'a FuncEnv,
+    types: &'a Types,
+    params: HashSet<&'a str>,
+    // how many `let`s and assignments every variable of the function has
+    assigns: HashMap<String, usize>,
+}
+
+impl Hoister<'_> {
+    fn block(&self, stmts: &[Stmt]) -> Vec<Stmt> {
+        let mut hoisted = Vec::new();
+
+        for stmt in stmts {
+            match stmt {
+                Stmt::WhileStmt(while_stmt) => {
+                    let mut body = self.block(&while_stmt.block);
+                    hoisted.extend(self.hoist(&while_stmt.condition, &mut body));
+
+                    hoisted.push(Stmt::WhileStmt(WhileStmtBody {
+                        block: body,
+                        ..while_stmt.clone()
+                    }));
+                }
+                Stmt::IfStmt(if_stmt) => hoisted.push(Stmt::IfStmt(IfStmtBody {
+                    if_block: self.block(&if_stmt.if_block),
+                    ..if_stmt.clone()
+                })),
+                stmt => hoisted.push(stmt.clone()),
+            }
+        }
+
+        hoisted
+    }
+
+    /// Takes the invariant `let`s out of a loop body, in the order they have to run
+    fn hoist(&self, condition: &BinaryStmtBody, body: &mut Vec<Stmt>) -> Vec<Stmt> {
+        let mut preheader = Vec::new();
+
+        // a `let` that moves out no longer assigns its variable in the loop, the ones that
+        // read it can follow
+        while let Some(i) = self.invariant(condition, body) {
+            preheader.push(body.remove(i));
+        }
+
+        preheader
+    }
+
+    /// The position of the first `let` of the body that can move in front of the loop
+    fn invariant(&self, condition: &BinaryStmtBody, body: &[Stmt]) -> Option<usize> {
+        let mut assigned = Assigns::default();
+        assigned.visit_block(body);
+
+        let mut read = VarsRead::default();
+        read.visit_binary_stmt(condition);
+
+        for (i, stmt) in body.iter().enumerate() {
+            if let Stmt::LetStmt(let_stmt) = stmt
+                && self.movable(let_stmt, &read.0)
+                && self.is_invariant(&let_stmt.value, &assigned.0)
+            {
+                return Some(i);
+            }
+
+            read.visit_stmt(stmt);
+        }
+
+        None
+    }
+
+    /// Whether the variable of a `let` gets no other value and isn't read before it
+    fn movable(&self, let_stmt: &LetStmtBody, read: &HashSet<String>) -> bool {
+        let var = let_stmt.var_name.as_str();
+
+        self.assigns.get(var) == Some(&1)
+            && !self.params.contains(var)
+            && self.env.lookup(var).is_none()
+            && !read.contains(var)
+    }
+
+    /// Whether an expression gives the same value on every iteration without any effect
+    fn is_invariant(&self, value: &BinaryStmtBody, assigned: &HashMap<String, usize>) -> bool {
+        let operands = [Some(&value.lhs), value.rhs.as_ref()];
+
+        let invariant = operands.into_iter().flatten().all(|operand| match operand {
+            Operand::Literal(name, _) if is_number(name) => true,
+            Operand::Literal(name, _) => {
+                !assigned.contains_key(name) && self.env.lookup(name).is_none()
+            }
+            Operand::Bool(_) | Operand::Null | Operand::Undefined => true,
+            Operand::Index(..) | Operand::Length(..) | Operand::Call(_) => false,
+        });
+
+        invariant && !self.traps(value)
+    }
+
+    /// Whether an expression can trap, only an integer remainder by 0 does
+    fn traps(&self, value: &BinaryStmtBody) -> bool {
+        if value.op != Some(Token::Percent)
+            || self.types.operands(self.func, self.env, value) == ValType::F64
+        {
+            return false;
+        }
+
+        !matches!(
+            &value.rhs,
+            Some(Operand::Literal(name, _)) if is_number(name) && name.parse::<f64>().is_ok_and(|n| n != 0.0)
+        )
+    }
+}
+
+/// The variables a block declares or assigns, with how many times it does
+#[derive(Default)]
+struct Assigns(HashMap<String, usize>);
+
+impl Visitor for Assigns {
+    fn visit_let_stmt(&mut self, let_stmt: &LetStmtBody) {
+        *self.0.entry(let_stmt.var_name.clone()).or_default() += 1;
+        walk_let_stmt(self, let_stmt);
+    }
+
+    fn visit_reassign_stmt(&mut self, reassign: &LetStmtBody) {
+        *self.0.entry(reassign.var_name.clone()).or_default() += 1;
+        walk_let_stmt(self, reassign);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::test_util::{find_func, parse_resolved},
+        parser::{FuncDeclBody, Program, printer::print_program},
+    };
+
+    /// Hoists the invariants of the function `f` of `src` and prints it
+    fn hoist(src: &str) -> String {
+        let (stmts, envs, types) = parse_resolved(src);
+        let func = find_func(&stmts, "f");
+
+        let no_env = FuncEnv::default();
+        let block = licm_pass(
+            &func.block,
+            &func.arguments,
+            "f",
+            envs.get("f").unwrap_or(&no_env),
+            &types,
+        );
+
+        print_program(&Program {
+            body: vec![Stmt::FuncDecl(FuncDeclBody {
+                block,
+                ..func.clone()
+            })],
+            unreachable: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn invariants_move_out_of_nested_loops() {
+        assert_eq!(
+            hoist(
+                "function f(a, b, n) {\n\tlet i = 0;\n\twhile (n > i) {\n\t\tlet k = a * b;\n\t\tlet j = 0;\n\t\twhile (3 > j) {\n\t\t\tlet m = k + 1;\n\t\t\tlet step = j + 1;\n\t\t\tj = step;\n\t\t}\n\t\ti = i + 1;\n\t}\n\treturn i;\n}\n\nf(1, 2, 3);"
+            ),
+            "function f(a, b, n) {\n\tlet i = 0;\n\tlet k = a * b;\n\tlet m = k + 1;\n\twhile (n > i) {\n\t\tlet j = 0;\n\t\twhile (3 > j) {\n\t\t\tlet step = j + 1;\n\t\t\tj = step;\n\t\t}\n\t\ti = i + 1;\n\t}\n\treturn i;\n}\n"
+        );
+    }
+
+    #[test]
+    fn what_could_be_observed_stays() {
+        let f = "function f(a, b, n) {\n\tlet i = 0;\n\twhile (n > i) {\n\t\tlet r = a % b;\n\t\tlet c = g(a);\n\t\tlet x = a + i;\n\t\ti = i + 1;\n\t\tlet y = a + 1;\n\t\ty = 2;\n\t\tlet z = n % 0;\n\t}\n\treturn i;\n}\n";
+
+        // a remainder can trap on a loop that never runs, a call can have effects, `i`
+        // changes in the loop and `y` is assigned again
+        assert_eq!(
+            hoist(&format!(
+                "{}\nfunction g(a) {{\n\treturn a;\n}}\n\nf(1, 2, 3);",
+                f
+            )),
+            f
+        );
+    }
+
+    #[test]
+    fn float_remainders_and_literal_divisors_move() {
+        assert_eq!(
+            hoist(
+                "function f(a, n) {\n\tlet x = a + 0.5;\n\twhile (n > 0) {\n\t\tlet r = x % 2;\n\t\tlet s = a % 8;\n\t\tn = n - 1;\n\t}\n\treturn n;\n}\n\nf(1, 2);"
+            ),
+            "function f(a, n) {\n\tlet x = a + 0.5;\n\tlet r = x % 2;\n\tlet s = a % 8;\n\twhile (n > 0) {\n\t\tn = n - 1;\n\t}\n\treturn n;\n}\n"
+        );
+    }
+}
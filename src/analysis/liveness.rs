@@ -0,0 +1,271 @@
+//! Liveness analysis and local coalescing.
+//!
+//! A variable is live at a point when some path from there reads it before it's assigned
+//! again. The variables live at the start and the end of every block of the control-flow
+//! graph are computed backwards until nothing changes. An assignment then interferes with
+//! every variable live after it: two variables that never interfere are never live at the
+//! same time, so if they have the same type they can share a local. Locals are handed out
+//! greedily, in the order the variables are declared.
+
+use std::collections::{BTreeSet, HashSet};
+use std::hash::Hash;
+
+use crate::{
+    analysis::{cfg::Cfg, cfg::Terminator, dead_code::VarsRead, types::ValType},
+    parser::{LetStmtBody, Stmt, visit::Visitor},
+};
+
+/// How many locals a function needs with and without coalescing, parameters not included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coalesced {
+    pub func: String,
+    pub locals: usize,
+    pub slots: usize,
+}
+
+/// Pairs of variables that are live at the same time
+#[derive(Debug)]
+pub struct Interference<T> {
+    edges: HashSet<(T, T)>,
+}
+
+impl<T: Clone + Eq + Hash> Default for Interference<T> {
+    fn default() -> Self {
+        Interference {
+            edges: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Clone + Eq + Hash> Interference<T> {
+    pub fn add(&mut self, a: &T, b: &T) {
+        if a != b {
+            self.edges.insert((a.clone(), b.clone()));
+            self.edges.insert((b.clone(), a.clone()));
+        }
+    }
+
+    pub fn interferes(&self, a: &T, b: &T) -> bool {
+        self.edges.contains(&(a.clone(), b.clone()))
+    }
+}
+
+/// A local and the variables it holds, the first one names it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slot<T> {
+    pub ty: ValType,
+    pub vars: Vec<T>,
+}
+
+/// Gives every variable a local shared with the variables of its type it doesn't interfere
+/// with. The `fixed` ones are the parameters, they each keep a local of their own that the
+/// other variables can still join. Returns the locals, the parameters first
+pub fn coalesce<T: Clone + Eq + Hash>(
+    fixed: Vec<(T, ValType)>,
+    vars: Vec<(T, ValType)>,
+    interference: &Interference<T>,
+) -> Vec<Slot<T>> {
+    let mut slots: Vec<Slot<T>> = fixed
+        .into_iter()
+        .map(|(var, ty)| Slot {
+            ty,
+            vars: vec![var],
+        })
+        .collect();
+
+    for (var, ty) in vars {
+        let slot = slots.iter_mut().find(|slot| {
+            slot.ty == ty
+                && slot
+                    .vars
+                    .iter()
+                    .all(|other| !interference.interferes(&var, other))
+        });
+
+        match slot {
+            Some(slot) => slot.vars.push(var),
+            None => slots.push(Slot {
+                ty,
+                vars: vec![var],
+            }),
+        }
+    }
+
+    slots
+}
+
+/// The variables live at the start and at the end of every block of a graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Liveness {
+    pub live_in: Vec<BTreeSet<String>>,
+    pub live_out: Vec<BTreeSet<String>>,
+}
+
+impl Liveness {
+    pub fn new(cfg: &Cfg) -> Liveness {
+        let mut liveness = Liveness {
+            live_in: vec![BTreeSet::new(); cfg.blocks.len()],
+            live_out: vec![BTreeSet::new(); cfg.blocks.len()],
+        };
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            // blocks are numbered roughly in source order, going backwards needs fewer rounds
+            for block in (0..cfg.blocks.len()).rev() {
+                let live_out: BTreeSet<String> = cfg.blocks[block]
+                    .succs
+                    .iter()
+                    .flat_map(|succ| liveness.live_in[*succ].iter().cloned())
+                    .collect();
+
+                let live_in = live_before(cfg, block, live_out.clone(), |_, _| ());
+
+                if live_in != liveness.live_in[block] || live_out != liveness.live_out[block] {
+                    liveness.live_in[block] = live_in;
+                    liveness.live_out[block] = live_out;
+                    changed = true;
+                }
+            }
+        }
+
+        liveness
+    }
+
+    /// Every assignment interferes with the variables live after it, and the parameters,
+    /// assigned on entry, with the variables live there
+    pub fn interference(&self, cfg: &Cfg, params: &[String]) -> Interference<String> {
+        let mut interference = Interference::default();
+
+        for block in 0..cfg.blocks.len() {
+            live_before(cfg, block, self.live_out[block].clone(), |var, live| {
+                for other in live {
+                    interference.add(var, other);
+                }
+            });
+        }
+
+        for param in params {
+            for other in self.live_in[cfg.entry].iter().chain(params) {
+                interference.add(param, other);
+            }
+        }
+
+        interference
+    }
+}
+
+/// Walks a block backwards from the variables live at its end, calls `assigned` with every
+/// assigned variable and the variables live right after it, returns the variables live at
+/// the start
+fn live_before(
+    cfg: &Cfg,
+    block: usize,
+    mut live: BTreeSet<String>,
+    mut assigned: impl FnMut(&String, &BTreeSet<String>),
+) -> BTreeSet<String> {
+    let mut vars = VarsRead::default();
+
+    match &cfg.blocks[block].terminator {
+        Terminator::Branch { condition, .. } => vars.visit_binary_stmt(condition),
+        Terminator::Return(Some(rt_val)) => vars.visit_return_stmt(rt_val),
+        Terminator::Goto(_) | Terminator::Return(None) | Terminator::Exit => (),
+    }
+
+    live.extend(vars.0);
+
+    for stmt in cfg.blocks[block].stmts.iter().rev() {
+        if let Stmt::LetStmt(LetStmtBody { var_name, .. })
+        | Stmt::RassignStmt(LetStmtBody { var_name, .. }) = stmt
+        {
+            assigned(var_name, &live);
+            live.remove(var_name);
+        }
+
+        let mut vars = VarsRead::default();
+        vars.visit_stmt(stmt);
+        live.extend(vars.0);
+    }
+
+    live
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::lex, parser::parse};
+
+    fn cfg(src: &str) -> Cfg {
+        let body = parse(lex(src.to_string())).unwrap().body;
+
+        let Some(Stmt::FuncDecl(func)) = body.first() else {
+            panic!("expected a function");
+        };
+
+        Cfg::build(&func.func_name, func)
+    }
+
+    fn vars(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn live_across_a_loop() {
+        let cfg = cfg(
+            "function gcd(a, b) {\n\twhile (b != 0) {\n\t\tlet t = b;\n\t\tb = a % b;\n\t\ta = t;\n\t}\n\treturn a;\n}\n",
+        );
+        let liveness = Liveness::new(&cfg);
+
+        assert_eq!(liveness.live_in[cfg.entry], vars(&["a", "b"]));
+        // `t` is only live between its `let` and `a = t`
+        for block in 0..cfg.blocks.len() {
+            assert!(!liveness.live_in[block].contains("t"));
+            assert!(!liveness.live_out[block].contains("t"));
+        }
+
+        let interference = liveness.interference(&cfg, &["a".to_string(), "b".to_string()]);
+
+        assert!(interference.interferes(&"a".to_string(), &"b".to_string()));
+        assert!(interference.interferes(&"t".to_string(), &"a".to_string()));
+        assert!(interference.interferes(&"t".to_string(), &"b".to_string()));
+    }
+
+    #[test]
+    fn variables_that_are_never_live_together_share_a_local() {
+        let cfg = cfg(
+            "function f(a) {\n\tlet x = a * 2;\n\tlet h = 0.5;\n\tlet y = x + 1;\n\tlet z = y * h;\n\treturn z + a;\n}\n",
+        );
+        let interference = Liveness::new(&cfg).interference(&cfg, &["a".to_string()]);
+
+        let slots = coalesce(
+            vec![("a".to_string(), ValType::I32)],
+            vec![
+                ("x".to_string(), ValType::I32),
+                ("h".to_string(), ValType::F64),
+                ("y".to_string(), ValType::I32),
+                ("z".to_string(), ValType::F64),
+            ],
+            &interference,
+        );
+
+        assert_eq!(
+            slots,
+            [
+                Slot {
+                    ty: ValType::I32,
+                    vars: vec!["a".to_string()],
+                },
+                Slot {
+                    ty: ValType::I32,
+                    vars: vec!["x".to_string(), "y".to_string()],
+                },
+                Slot {
+                    ty: ValType::F64,
+                    vars: vec!["h".to_string(), "z".to_string()],
+                },
+            ]
+        );
+    }
+}
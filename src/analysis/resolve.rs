@@ -0,0 +1,886 @@
+//! Name resolution.
+//!
+//! Builds a symbol table with a scope for the module, every function and every block, and
+//! checks that each variable that's read or written and each function that's called is
+//! declared somewhere it's visible. A typo is reported with its location instead of turning
+//! into a `local.get` of a local that doesn't exist.
+//!
+//! The functions, bindings and `let`s of a block are all declared before any statement of
+//! it is resolved, so they're visible in their whole scope and a function can be called
+//! before the line it's declared on. A `let` is no different: JavaScript throws when one is
+//! read before its line, here the read isn't reported and gets whatever the local holds
+//! before it's assigned. A name can be declared once per scope, the parameters of a function share its
+//! scope, and a `let` in a block can shadow one of an enclosing scope.
+//!
+//! Every call is also checked against the parameters of the function it resolves to, a call
+//! with too few or too many arguments is reported with the call and the declaration. It's
+//! only a warning: like in JavaScript the missing parameters are `undefined` and the extra
+//! arguments are evaluated and ignored.
+
+use std::fmt;
+
+use crate::{
+    lexer::Span,
+    parser::{
+        FuncCallStmt, FuncDeclBody, FuncExprBody, IfStmtBody, LetStmtBody, Operand, Param, Program,
+        ReturnStmt, Stmt, WhileStmtBody, is_number,
+        visit::{Visitor, walk_block, walk_func_call, walk_let_stmt, walk_operand, walk_program},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Param,
+    // `...xs`, the only arrays there are
+    RestParam,
+    Local,
+    Global,
+}
+
+impl SymbolKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Param => "param",
+            SymbolKind::RestParam => "rest param",
+            SymbolKind::Local => "local",
+            SymbolKind::Global => "global",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // where the name is declared
+    pub span: Span,
+    // only functions have one
+    pub signature: Option<Signature>,
+}
+
+/// How many arguments a function can be called with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    /// parameters up to the last one without a default value
+    pub required: usize,
+    /// every parameter but the rest parameter
+    pub params: usize,
+    pub rest: bool,
+}
+
+impl Signature {
+    pub fn of(params: &[Param]) -> Self {
+        let regular: Vec<_> = params.iter().filter(|param| !param.rest).collect();
+
+        Signature {
+            required: regular
+                .iter()
+                .rposition(|param| param.default.is_none())
+                .map_or(0, |idx| idx + 1),
+            params: regular.len(),
+            rest: regular.len() < params.len(),
+        }
+    }
+
+    pub fn accepts(&self, given: usize) -> bool {
+        given >= self.required && (self.rest || given <= self.params)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+
+        if self.rest {
+            write!(
+                f,
+                "at least {} argument{}",
+                self.required,
+                plural(self.required)
+            )
+        } else if self.required == self.params {
+            write!(f, "{} argument{}", self.params, plural(self.params))
+        } else {
+            write!(f, "{} to {} arguments", self.required, self.params)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScopeKind {
+    Module,
+    Function(String),
+    // the block of an `if` or a `while`
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    pub parent: Option<usize>,
+    pub symbols: Vec<Symbol>,
+}
+
+/// Every scope of a program, scope 0 is the module
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolTable {
+    pub scopes: Vec<Scope>,
+}
+
+impl SymbolTable {
+    /// Finds the declaration `name` refers to in `scope`, looking through the enclosing scopes
+    pub fn lookup(&self, scope: usize, name: &str) -> Option<&Symbol> {
+        let mut current = Some(scope);
+
+        while let Some(idx) = current {
+            let scope = &self.scopes[idx];
+
+            // the last declaration wins when a name is declared twice
+            if let Some(symbol) = scope.symbols.iter().rev().find(|x| x.name == name) {
+                return Some(symbol);
+            }
+
+            current = scope.parent;
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, scope) in self.scopes.iter().enumerate() {
+            write!(f, "scope {} ", idx)?;
+
+            match &scope.kind {
+                ScopeKind::Module => write!(f, "module")?,
+                ScopeKind::Function(name) => write!(f, "function {}", name)?,
+                ScopeKind::Block => write!(f, "block")?,
+            }
+
+            if let Some(parent) = scope.parent {
+                write!(f, " in {}", parent)?;
+            }
+
+            writeln!(f)?;
+
+            for symbol in scope.symbols.iter() {
+                writeln!(
+                    f,
+                    "  {} {} {}",
+                    symbol.kind.as_str(),
+                    symbol.name,
+                    symbol.span
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolveErrorKind {
+    /// E0011: a variable that isn't declared
+    UnknownIdentifier,
+    /// E0012: a call to a function that isn't declared
+    UndefinedFunction,
+    /// E0013: a call to a variable or a parameter
+    NotAFunction,
+    /// E0014: a call with too few or too many arguments, a warning
+    WrongArgumentCount { given: usize, signature: Signature },
+    /// E0015: a function expression assigned to a binding that already has a value
+    ReassignedFunction,
+    /// E0016: a function read like a variable, functions can only be called
+    FunctionAsValue,
+    /// E0017: a value assigned to a function
+    AssignmentToFunction,
+    /// E0018: two parameters of a function with the same name
+    DuplicateParam,
+    /// E0019: a `let` of a name that's already declared in the same scope
+    DuplicateDeclaration,
+    /// E0020: an index or `.length` of something that isn't a rest parameter
+    NotAnArray,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveError {
+    pub kind: ResolveErrorKind,
+    pub name: String,
+    // where the name is used
+    pub span: Span,
+    // where the function is declared, for errors about a call or a function, where the name
+    // was first declared, for a duplicate, or where the variable that isn't an array is
+    pub declared: Option<Span>,
+}
+
+impl ResolveError {
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            ResolveErrorKind::UnknownIdentifier => "E0011",
+            ResolveErrorKind::UndefinedFunction => "E0012",
+            ResolveErrorKind::NotAFunction => "E0013",
+            ResolveErrorKind::WrongArgumentCount { .. } => "E0014",
+            ResolveErrorKind::ReassignedFunction => "E0015",
+            ResolveErrorKind::FunctionAsValue => "E0016",
+            ResolveErrorKind::AssignmentToFunction => "E0017",
+            ResolveErrorKind::DuplicateParam => "E0018",
+            ResolveErrorKind::DuplicateDeclaration => "E0019",
+            ResolveErrorKind::NotAnArray => "E0020",
+        }
+    }
+
+    /// Whether the program still compiles, the code does what JavaScript does
+    pub fn is_warning(&self) -> bool {
+        matches!(self.kind, ResolveErrorKind::WrongArgumentCount { .. })
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = if self.is_warning() {
+            "warning"
+        } else {
+            "error"
+        };
+
+        write!(f, "{}[{}]: ", level, self.code())?;
+
+        match self.kind {
+            ResolveErrorKind::UnknownIdentifier => {
+                write!(f, "cannot find variable `{}` in this scope", self.name)
+            }
+            ResolveErrorKind::UndefinedFunction => {
+                write!(f, "cannot find function `{}` in this scope", self.name)
+            }
+            ResolveErrorKind::NotAFunction => write!(f, "`{}` is not a function", self.name),
+            ResolveErrorKind::WrongArgumentCount { given, signature } => write!(
+                f,
+                "`{}` takes {} but {} {} given",
+                self.name,
+                signature,
+                given,
+                if given == 1 { "was" } else { "were" }
+            ),
+            ResolveErrorKind::ReassignedFunction => write!(
+                f,
+                "function binding `{}` is reassigned, only bindings that are never reassigned can be compiled",
+                self.name
+            ),
+            ResolveErrorKind::FunctionAsValue => write!(
+                f,
+                "function `{}` is used as a value, functions can only be called",
+                self.name
+            ),
+            ResolveErrorKind::AssignmentToFunction => {
+                write!(f, "cannot assign to function `{}`", self.name)
+            }
+            ResolveErrorKind::DuplicateParam => {
+                write!(f, "parameter `{}` is declared twice", self.name)
+            }
+            ResolveErrorKind::DuplicateDeclaration => {
+                write!(f, "`{}` is already declared in this scope", self.name)
+            }
+            ResolveErrorKind::NotAnArray => write!(
+                f,
+                "`{}` is not an array, only rest parameters can be indexed",
+                self.name
+            ),
+        }
+    }
+}
+
+/// Resolves every name in the program, all errors are collected before returning. The table
+/// comes with the warnings
+pub fn resolve(program: &Program) -> Result<(SymbolTable, Vec<ResolveError>), Vec<ResolveError>> {
+    let mut resolver = Resolver {
+        table: SymbolTable::default(),
+        scope: 0,
+        errors: Vec::new(),
+    };
+
+    resolver.visit_program(program);
+
+    let (warnings, errors): (Vec<_>, Vec<_>) = resolver
+        .errors
+        .into_iter()
+        .partition(ResolveError::is_warning);
+
+    if errors.is_empty() {
+        Ok((resolver.table, warnings))
+    } else {
+        Err(errors)
+    }
+}
+
+struct Resolver {
+    table: SymbolTable,
+    // the scope being resolved
+    scope: usize,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver {
+    /// Opens a new scope inside `parent`, returns the scope to go back to
+    fn enter(&mut self, kind: ScopeKind, parent: Option<usize>) -> usize {
+        self.table.scopes.push(Scope {
+            kind,
+            parent,
+            symbols: Vec::new(),
+        });
+
+        std::mem::replace(&mut self.scope, self.table.scopes.len() - 1)
+    }
+
+    /// Declares a parameter or a variable, a name that's already in the scope is reported
+    fn declare(&mut self, name: &str, kind: SymbolKind, span: Span) {
+        let first = self.table.scopes[self.scope]
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.span);
+
+        if let Some(first) = first {
+            let kind = if matches!(kind, SymbolKind::Param | SymbolKind::RestParam) {
+                ResolveErrorKind::DuplicateParam
+            } else {
+                ResolveErrorKind::DuplicateDeclaration
+            };
+
+            self.errors.push(ResolveError {
+                kind,
+                name: name.to_string(),
+                span,
+                declared: Some(first),
+            });
+        }
+
+        self.table.scopes[self.scope].symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            span,
+            signature: None,
+        });
+    }
+
+    fn declare_func(&mut self, name: &str, func: &FuncDeclBody) {
+        self.table.scopes[self.scope].symbols.push(Symbol {
+            name: name.to_string(),
+            kind: SymbolKind::Function,
+            span: func.span,
+            signature: Some(Signature::of(&func.arguments)),
+        });
+    }
+
+    /// Declares the functions and variables of a block in the current scope
+    fn declare_block(&mut self, stmts: &[Stmt], var_kind: SymbolKind) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::FuncDecl(func) => self.declare_func(&func.func_name, func),
+                Stmt::FuncExpr(FuncExprBody {
+                    var_name,
+                    decl: Some(_),
+                    func,
+                }) => {
+                    // functions are declared once per scope, a second binding would replace one
+                    let declared = self.table.scopes[self.scope]
+                        .symbols
+                        .iter()
+                        .find(|symbol| symbol.name == *var_name)
+                        .filter(|symbol| symbol.kind == SymbolKind::Function)
+                        .map(|symbol| symbol.span);
+
+                    if let Some(declared) = declared {
+                        self.reassigned(var_name, func.span, declared);
+                    }
+
+                    self.declare_func(var_name, func)
+                }
+                Stmt::LetStmt(let_stmt) => {
+                    self.declare(&let_stmt.var_name, var_kind, let_stmt.span)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Resolves a block in a scope of its own
+    fn visit_scoped_block(&mut self, stmts: &[Stmt], rt_val: Option<&ReturnStmt>) {
+        let outer = self.enter(ScopeKind::Block, Some(self.scope));
+
+        self.declare_block(stmts, SymbolKind::Local);
+        walk_block(self, stmts);
+
+        if let Some(rt_val) = rt_val {
+            self.visit_return_stmt(rt_val);
+        }
+
+        self.scope = outer;
+    }
+
+    fn reassigned(&mut self, name: &str, span: Span, declared: Span) {
+        self.errors.push(ResolveError {
+            kind: ResolveErrorKind::ReassignedFunction,
+            name: name.to_string(),
+            span,
+            declared: Some(declared),
+        });
+    }
+
+    /// Checks a variable that's read, or written when `assigned`
+    fn check_var(&mut self, name: &str, span: Span, assigned: bool) {
+        if is_number(name) {
+            return;
+        }
+
+        let error = match self.table.lookup(self.scope, name) {
+            None => Some((ResolveErrorKind::UnknownIdentifier, None)),
+            // functions aren't values, there's nothing to read or to overwrite
+            Some(symbol) if symbol.kind == SymbolKind::Function => {
+                let kind = if assigned {
+                    ResolveErrorKind::AssignmentToFunction
+                } else {
+                    ResolveErrorKind::FunctionAsValue
+                };
+
+                Some((kind, Some(symbol.span)))
+            }
+            Some(_) => None,
+        };
+
+        if let Some((kind, declared)) = error {
+            self.errors.push(ResolveError {
+                kind,
+                name: name.to_string(),
+                span,
+                declared,
+            });
+        }
+    }
+
+    /// Checks a variable that's indexed or whose `.length` is read, only rest parameters
+    /// live in memory
+    fn check_array(&mut self, name: &str, span: Span) {
+        let error = match self.table.lookup(self.scope, name) {
+            None => Some((ResolveErrorKind::UnknownIdentifier, None)),
+            Some(symbol) if symbol.kind != SymbolKind::RestParam => {
+                Some((ResolveErrorKind::NotAnArray, Some(symbol.span)))
+            }
+            Some(_) => None,
+        };
+
+        if let Some((kind, declared)) = error {
+            self.errors.push(ResolveError {
+                kind,
+                name: name.to_string(),
+                span,
+                declared,
+            });
+        }
+    }
+}
+
+impl Visitor for Resolver {
+    fn visit_program(&mut self, program: &Program) {
+        self.enter(ScopeKind::Module, None);
+        self.declare_block(&program.body, SymbolKind::Global);
+        walk_program(self, program);
+    }
+
+    fn visit_func_decl(&mut self, func: &FuncDeclBody) {
+        let outer = self.enter(
+            ScopeKind::Function(func.func_name.clone()),
+            Some(self.scope),
+        );
+
+        for param in func.arguments.iter() {
+            let kind = if param.rest {
+                SymbolKind::RestParam
+            } else {
+                SymbolKind::Param
+            };
+
+            self.declare(&param.name, kind, param.span);
+        }
+
+        for param in func.arguments.iter() {
+            if let Some(default) = &param.default {
+                self.visit_binary_stmt(default);
+            }
+        }
+
+        self.declare_block(&func.block, SymbolKind::Local);
+        walk_block(self, &func.block);
+
+        if let Some(rt_val) = &func.return_value {
+            self.visit_return_stmt(rt_val);
+        }
+
+        self.scope = outer;
+    }
+
+    fn visit_func_expr(&mut self, func_expr: &FuncExprBody) {
+        // a reassignment needs a binding to reassign, and the binding can't change once it's
+        // a function
+        if func_expr.decl.is_none() {
+            match self.table.lookup(self.scope, &func_expr.var_name) {
+                Some(symbol) => {
+                    let declared = symbol.span;
+                    self.reassigned(&func_expr.var_name, func_expr.func.span, declared);
+                }
+                None => self.check_var(&func_expr.var_name, func_expr.func.span, true),
+            }
+        }
+
+        self.visit_func_decl(&func_expr.func);
+    }
+
+    fn visit_while_stmt(&mut self, while_stmt: &WhileStmtBody) {
+        self.visit_binary_stmt(&while_stmt.condition);
+        self.visit_scoped_block(&while_stmt.block, None);
+    }
+
+    fn visit_if_stmt(&mut self, if_stmt: &IfStmtBody) {
+        self.visit_binary_stmt(&if_stmt.condition);
+        self.visit_scoped_block(&if_stmt.if_block, if_stmt.if_block_rt_val.as_ref());
+    }
+
+    fn visit_reassign_stmt(&mut self, reassign: &LetStmtBody) {
+        self.check_var(&reassign.var_name, reassign.span, true);
+        walk_let_stmt(self, reassign);
+    }
+
+    fn visit_func_call(&mut self, call: &FuncCallStmt) {
+        let given = call.arguments.len();
+
+        let error = match self.table.lookup(self.scope, &call.function_name) {
+            None => Some((ResolveErrorKind::UndefinedFunction, None)),
+            Some(symbol) if symbol.kind != SymbolKind::Function => {
+                Some((ResolveErrorKind::NotAFunction, Some(symbol.span)))
+            }
+            Some(Symbol {
+                signature: Some(signature),
+                span,
+                ..
+            }) if !signature.accepts(given) => Some((
+                ResolveErrorKind::WrongArgumentCount {
+                    given,
+                    signature: *signature,
+                },
+                Some(*span),
+            )),
+            Some(_) => None,
+        };
+
+        if let Some((kind, declared)) = error {
+            self.errors.push(ResolveError {
+                kind,
+                name: call.function_name.clone(),
+                span: call.span,
+                declared,
+            });
+        }
+
+        walk_func_call(self, call);
+    }
+
+    fn visit_operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Literal(name, span) => self.check_var(name, *span, false),
+            Operand::Index(name, _, span) | Operand::Length(name, span) => {
+                self.check_array(name, *span)
+            }
+            Operand::Call(_) | Operand::Bool(_) | Operand::Null | Operand::Undefined => (),
+        }
+
+        walk_operand(self, operand);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::lex, parser::parse};
+
+    fn resolve_src(src: &str) -> Result<(SymbolTable, Vec<ResolveError>), Vec<ResolveError>> {
+        resolve(&parse(lex(src.to_string())).unwrap())
+    }
+
+    #[test]
+    fn builds_scopes_for_functions_and_blocks() {
+        let (table, _) = resolve_src(
+            "function count(n, limit) {\n\tlet i = 0;\n\twhile (i < limit) {\n\t\tlet next = i + n;\n\t\ti = next;\n\t}\n\treturn helper(i);\n}\n\nconst helper = (x) => x;\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            table.to_string(),
+            "scope 0 module\n  function count 1:10\n  function helper 10:7\nscope 1 function count in 0\n  param n 1:16\n  param limit 1:19\n  local i 2:6\nscope 2 block in 1\n  local next 4:7\nscope 3 function helper in 0\n  param x 10:17\n"
+        );
+
+        assert_eq!(table.lookup(2, "n").unwrap().kind, SymbolKind::Param);
+        assert_eq!(
+            table.lookup(2, "helper").unwrap().kind,
+            SymbolKind::Function
+        );
+        assert!(table.lookup(1, "next").is_none());
+    }
+
+    #[test]
+    fn reports_unknown_names_with_locations() {
+        let errors = resolve_src(
+            "function f(a) {\n\tlet b = a + typo;\n\tif (b == 0) {\n\t\tlet c = 1;\n\t}\n\tmissing(c);\n\tb = a(1);\n\treturn b;\n}\n",
+        )
+        .unwrap_err();
+
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| (e.kind, e.name.as_str(), e.span.line, e.span.col))
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                (ResolveErrorKind::UnknownIdentifier, "typo", 2, 14),
+                (ResolveErrorKind::UndefinedFunction, "missing", 6, 2),
+                // `c` is only declared inside the if block
+                (ResolveErrorKind::UnknownIdentifier, "c", 6, 10),
+                (ResolveErrorKind::NotAFunction, "a", 7, 6),
+            ]
+        );
+
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0011]: cannot find variable `typo` in this scope"
+        );
+    }
+
+    #[test]
+    fn resolves_closures_and_hoisted_functions() {
+        let table = resolve_src(
+            "function outer(n) {\n\tlet total = inner(n);\n\tfunction inner(x) {\n\t\treturn x + n;\n\t}\n\tconst twice = (y) => inner(y) + total;\n\treturn twice(1);\n}\n\nouter(2);",
+        );
+
+        assert!(table.is_ok());
+    }
+
+    #[test]
+    fn checks_argument_counts() {
+        let (_, warnings) = resolve_src(
+            "function scale(a, factor = 10) {\n\treturn a * factor;\n}\n\nfunction sum(base, ...xs) {\n\treturn base;\n}\n\nfunction f(n) {\n\tlet a = scale(n) + scale(n, 2);\n\tlet b = sum(n, 1, 2, 3);\n\tlet c = scale() + sum();\n\treturn scale(n, 1, 2);\n}\n",
+        )
+        .unwrap();
+
+        let found: Vec<_> = warnings
+            .iter()
+            .map(|e| (e.to_string(), e.span.line, e.declared.unwrap().line))
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                (
+                    "warning[E0014]: `scale` takes 1 to 2 arguments but 0 were given".to_string(),
+                    12,
+                    1
+                ),
+                (
+                    "warning[E0014]: `sum` takes at least 1 argument but 0 were given".to_string(),
+                    12,
+                    5
+                ),
+                (
+                    "warning[E0014]: `scale` takes 1 to 2 arguments but 3 were given".to_string(),
+                    13,
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn signature_counts_trailing_defaults_only() {
+        let signature = |src: &str| {
+            let (table, _) = resolve_src(src).unwrap();
+            table.scopes[0].symbols[0].signature.unwrap()
+        };
+
+        assert_eq!(signature("function f(a) {\n}\n").to_string(), "1 argument");
+        assert_eq!(
+            signature("function f(a = 1, b) {\n}\n").to_string(),
+            "2 arguments"
+        );
+        assert!(signature("const f = (a, b = 2) => a + b;").accepts(1));
+        assert!(!signature("const f = (a, b = 2) => a + b;").accepts(3));
+    }
+
+    #[test]
+    fn reports_reassigned_function_bindings() {
+        let errors = resolve_src(
+            "let f = x => x;\nf = x => x + 1;\nconst g = () => 1;\nconst g = () => 2;\n",
+        )
+        .unwrap_err();
+
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                (
+                    e.code(),
+                    e.name.as_str(),
+                    e.span.line,
+                    e.declared.unwrap().line,
+                )
+            })
+            .collect();
+
+        assert_eq!(found, [("E0015", "g", 4, 3), ("E0015", "f", 2, 1)]);
+        assert_eq!(
+            errors[1].to_string(),
+            "error[E0015]: function binding `f` is reassigned, only bindings that are never reassigned can be compiled"
+        );
+    }
+
+    #[test]
+    fn reports_functions_used_as_values() {
+        let errors = resolve_src(
+            "function g() {\n\treturn 1;\n}\n\nfunction f(a) {\n\tlet b = g + a;\n\tg = 3;\n\treturn g;\n}\n",
+        )
+        .unwrap_err();
+
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                (
+                    e.code(),
+                    e.name.as_str(),
+                    e.span.line,
+                    e.declared.unwrap().line,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                ("E0016", "g", 6, 1),
+                ("E0017", "g", 7, 1),
+                ("E0016", "g", 8, 1)
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0016]: function `g` is used as a value, functions can only be called"
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "error[E0017]: cannot assign to function `g`"
+        );
+    }
+
+    #[test]
+    fn reports_names_declared_twice() {
+        let errors = resolve_src(
+            "function f(a, b, a) {\n\tlet c = 1;\n\tlet b = 2;\n\tif (c == 1) {\n\t\tlet c = 3;\n\t\tlet c = 4;\n\t}\n\tlet c = 5;\n\treturn c;\n}\n",
+        )
+        .unwrap_err();
+
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                (
+                    e.code(),
+                    e.name.as_str(),
+                    e.span.line,
+                    e.declared.unwrap().line,
+                )
+            })
+            .collect();
+
+        // the `c` of the `if` block shadows the one of the function, only its second `let` is
+        // reported
+        assert_eq!(
+            found,
+            [
+                ("E0018", "a", 1, 1),
+                ("E0019", "b", 3, 1),
+                ("E0019", "c", 8, 2),
+                ("E0019", "c", 6, 5),
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0018]: parameter `a` is declared twice"
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "error[E0019]: `b` is already declared in this scope"
+        );
+    }
+
+    #[test]
+    fn reports_indexes_of_values_that_are_not_arrays() {
+        let errors = resolve_src(
+            "function f(n, ...xs) {\n\tlet a = xs[0] + xs.length;\n\tlet b = n[0];\n\treturn a.length;\n}\n",
+        )
+        .unwrap_err();
+
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| {
+                (
+                    e.code(),
+                    e.name.as_str(),
+                    e.span.line,
+                    e.declared.unwrap().line,
+                )
+            })
+            .collect();
+
+        assert_eq!(found, [("E0020", "n", 3, 1), ("E0020", "a", 4, 2)]);
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0020]: `n` is not an array, only rest parameters can be indexed"
+        );
+    }
+
+    #[test]
+    fn resolves_programs() {
+        for src in [
+            include_str!("../../programs/gcd.js"),
+            include_str!("../../programs/factorial.js"),
+            include_str!("../../programs/ack.js"),
+            include_str!("../../programs/recursive_gcd.js"),
+            include_str!("../../programs/dead_code.js"),
+            include_str!("../../programs/function_expr.js"),
+            include_str!("../../programs/closures.js"),
+            include_str!("../../programs/params.js"),
+            include_str!("../../programs/nested_calls.js"),
+            include_str!("../../programs/literals.js"),
+            include_str!("../../programs/numbers.js"),
+            include_str!("../../programs/utils.js"),
+        ] {
+            assert_eq!(
+                resolve_src(src).map(|(_, warnings)| warnings),
+                Ok(Vec::new())
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_argument_counts_are_only_warnings() {
+        // `k(7)` passes `undefined` for `b`, `k(1, 2, 3)` ignores the `3`
+        let (_, warnings) = resolve_src(
+            "function k(a, b) {\n\treturn a;\n}\n\nfunction f() {\n\tlet x = k(7);\n\treturn k(1, 2, 3) + x;\n}\n",
+        )
+        .unwrap();
+
+        assert!(warnings.iter().all(ResolveError::is_warning));
+        assert_eq!(warnings.len(), 2);
+
+        let errors = resolve_src("function f() {\n\treturn g(7);\n}\n").unwrap_err();
+
+        assert!(!errors[0].is_warning());
+    }
+}
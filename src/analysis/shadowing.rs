@@ -0,0 +1,179 @@
+//! Shadowed variables.
+//!
+//! Locals are keyed by name from here on: liveness, coalescing, type inference and the SSA
+//! builder all see one `x` per function. A `let` in an `if` or `while` block that shadows a
+//! variable of an enclosing scope would be merged with it, so it gets a name of its own,
+//! `x@1`, and every use in its block is renamed with it. `@` can't be part of a JavaScript
+//! name, so the new names never clash with the program's own.
+//!
+//! Like name resolution, a `let` is visible in its whole block, uses before it in the same
+//! block refer to it too.
+
+use std::collections::HashMap;
+
+use crate::parser::{
+    FuncDeclBody, IfStmtBody, LetStmtBody, Operand, Stmt, WhileStmtBody,
+    visit::{VisitorMut, walk_block_mut, walk_func_decl_mut, walk_let_stmt_mut, walk_operand_mut},
+};
+
+/// Gives every `let` that shadows a variable of an enclosing scope a unique name
+pub fn rename_shadowed(mut stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let mut renamer = Renamer::default();
+
+    renamer.scopes.push(HashMap::new());
+    renamer.declare_block(&stmts, false);
+    walk_block_mut(&mut renamer, &mut stmts);
+
+    stmts
+}
+
+#[derive(Default)]
+struct Renamer {
+    // the name every visible variable has after renaming, innermost scope last
+    scopes: Vec<HashMap<String, String>>,
+    renamed: usize,
+}
+
+impl Renamer {
+    fn lookup(&self, name: &str) -> Option<&String> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn rename(&self, name: &mut String) {
+        if let Some(renamed) = self.lookup(name) {
+            *name = renamed.clone();
+        }
+    }
+
+    /// Declares the `let`s of a block in the innermost scope, renaming the ones that shadow
+    fn declare_block(&mut self, block: &[Stmt], shadows: bool) {
+        for stmt in block {
+            let Stmt::LetStmt(LetStmtBody { var_name, .. }) = stmt else {
+                continue;
+            };
+
+            if self.scopes.last().unwrap().contains_key(var_name) {
+                continue;
+            }
+
+            let name = if shadows && self.lookup(var_name).is_some() {
+                self.renamed += 1;
+                format!("{}@{}", var_name, self.renamed)
+            } else {
+                var_name.clone()
+            };
+
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(var_name.clone(), name);
+        }
+    }
+
+    fn visit_scoped_block(&mut self, block: &mut Vec<Stmt>) {
+        self.scopes.push(HashMap::new());
+        self.declare_block(block, true);
+        walk_block_mut(self, block);
+        self.scopes.pop();
+    }
+}
+
+impl VisitorMut for Renamer {
+    fn visit_func_decl_mut(&mut self, func: &mut FuncDeclBody) {
+        let params = func
+            .arguments
+            .iter()
+            .map(|param| (param.name.clone(), param.name.clone()))
+            .collect();
+
+        // the function body is a scope of its own, its `let`s live in the function's frame
+        // and never clash with the ones of the caller
+        self.scopes.push(params);
+        self.declare_block(&func.block, false);
+        walk_func_decl_mut(self, func);
+        self.scopes.pop();
+    }
+
+    fn visit_while_stmt_mut(&mut self, while_stmt: &mut WhileStmtBody) {
+        self.visit_binary_stmt_mut(&mut while_stmt.condition);
+        self.visit_scoped_block(&mut while_stmt.block);
+    }
+
+    fn visit_if_stmt_mut(&mut self, if_stmt: &mut IfStmtBody) {
+        self.visit_binary_stmt_mut(&mut if_stmt.condition);
+
+        self.scopes.push(HashMap::new());
+        self.declare_block(&if_stmt.if_block, true);
+        walk_block_mut(self, &mut if_stmt.if_block);
+        if let Some(return_stmt) = &mut if_stmt.if_block_rt_val {
+            self.visit_return_stmt_mut(return_stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn visit_let_stmt_mut(&mut self, let_stmt: &mut LetStmtBody) {
+        walk_let_stmt_mut(self, let_stmt);
+        self.rename(&mut let_stmt.var_name);
+    }
+
+    fn visit_reassign_stmt_mut(&mut self, reassign: &mut LetStmtBody) {
+        walk_let_stmt_mut(self, reassign);
+        self.rename(&mut reassign.var_name);
+    }
+
+    fn visit_operand_mut(&mut self, operand: &mut Operand) {
+        match operand {
+            Operand::Literal(name, _) | Operand::Index(name, _, _) | Operand::Length(name, _) => {
+                self.rename(name)
+            }
+            _ => (),
+        }
+
+        walk_operand_mut(self, operand);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lexer::lex,
+        parser::{Program, parse, printer::print_program},
+    };
+
+    fn renamed(src: &str) -> String {
+        let body = rename_shadowed(parse(lex(src.to_string())).unwrap().body);
+
+        print_program(&Program {
+            body,
+            unreachable: vec![],
+        })
+    }
+
+    #[test]
+    fn renames_lets_that_shadow() {
+        assert_eq!(
+            renamed(
+                "function f(n) {\n\tlet x = 100;\n\tlet i = 0;\n\twhile (i < n) {\n\t\tlet x = i + 1;\n\t\ti = x;\n\t}\n\treturn x + i;\n}\n"
+            ),
+            "function f(n) {\n\tlet x = 100;\n\tlet i = 0;\n\twhile (i < n) {\n\t\tlet x@1 = i + 1;\n\t\ti = x@1;\n\t}\n\treturn x + i;\n}\n"
+        );
+    }
+
+    #[test]
+    fn renames_shadowed_params_and_nested_blocks() {
+        assert_eq!(
+            renamed(
+                "function f(a) {\n\tif (a == 1) {\n\t\tlet a = 2;\n\t\tif (a == 2) {\n\t\t\tlet a = 3;\n\t\t\treturn a;\n\t\t}\n\t\treturn a;\n\t}\n\treturn a;\n}\n"
+            ),
+            "function f(a) {\n\tif (a == 1) {\n\t\tlet a@1 = 2;\n\t\tif (a@1 == 2) {\n\t\t\tlet a@2 = 3;\n\t\t\treturn a@2;\n\t\t}\n\t\treturn a@1;\n\t}\n\treturn a;\n}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_lets_that_shadow_nothing() {
+        let src = "function f(a) {\n\tlet b = 1;\n\tif (a == 1) {\n\t\tlet c = 2;\n\t\tb = c;\n\t}\n\twhile (a < 3) {\n\t\tlet c = 3;\n\t\ta = c;\n\t}\n\treturn b;\n}\n";
+
+        assert_eq!(renamed(src), src);
+    }
+}
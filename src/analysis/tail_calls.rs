@@ -0,0 +1,151 @@
+//! Calls in tail position.
+//!
+//! A call is in tail position when the function returns its result as is, nothing is left to
+//! do in the caller once it returns. That's a `return f(...)`, at the end of the function or
+//! in an `if`, where `f` returns the type the function does: a call whose result has to be
+//! converted first isn't one. The caller's frame isn't needed anymore for those calls, so
+//! they can replace it instead of growing the stack.
+
+use std::collections::HashSet;
+
+use crate::{
+    analysis::types::Types,
+    lexer::Span,
+    parser::{
+        FuncCallStmt, FuncDeclBody, FuncExprBody, ReturnStmt,
+        visit::{Visitor, walk_func_decl},
+    },
+};
+
+/// The spans of the calls in tail position in a closure converted function
+pub fn tail_calls(
+    func: &FuncDeclBody,
+    types: &Types,
+    returns_value: &HashSet<String>,
+) -> HashSet<Span> {
+    find_tail_calls(func, types, returns_value)
+        .into_iter()
+        .map(|call| call.span)
+        .collect()
+}
+
+/// The spans of the tail calls of a function to itself with an argument for every parameter,
+/// a loop can take their place
+pub fn self_tail_calls(
+    func: &FuncDeclBody,
+    types: &Types,
+    returns_value: &HashSet<String>,
+) -> HashSet<Span> {
+    let regular = func.arguments.iter().filter(|param| !param.rest).count();
+
+    find_tail_calls(func, types, returns_value)
+        .into_iter()
+        .filter(|call| call.function_name == func.func_name && call.arguments.len() >= regular)
+        .map(|call| call.span)
+        .collect()
+}
+
+fn find_tail_calls(
+    func: &FuncDeclBody,
+    types: &Types,
+    returns_value: &HashSet<String>,
+) -> Vec<FuncCallStmt> {
+    let mut calls = TailCalls {
+        func: &func.func_name,
+        types,
+        returns_value,
+        calls: Vec::new(),
+    };
+
+    walk_func_decl(&mut calls, func);
+
+    calls.calls
+}
+
+struct TailCalls<'a> {
+    func: &'a str,
+    types: &'a Types,
+    // functions that leave a value on the stack
+    returns_value: &'a HashSet<String>,
+    calls: Vec<FuncCallStmt>,
+}
+
+impl Visitor for TailCalls<'_> {
+    fn visit_return_stmt(&mut self, return_stmt: &ReturnStmt) {
+        if let ReturnStmt::FuncCallStmt(call) = return_stmt
+            && self.returns_value.contains(&call.function_name)
+            && self.types.result(&call.function_name) == self.types.result(self.func)
+        {
+            self.calls.push(call.clone());
+        }
+    }
+
+    // nested functions have their own returns
+    fn visit_func_decl(&mut self, _: &FuncDeclBody) {}
+
+    fn visit_func_expr(&mut self, _: &FuncExprBody) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analysis::test_util::parse_resolved, parser::Stmt};
+
+    /// The lines and columns of the tail calls of every function
+    fn tail_call_positions(src: &str) -> Vec<(String, Vec<(usize, usize)>)> {
+        let (stmts, _, types) = parse_resolved(src);
+
+        let returns_value: HashSet<String> = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::FuncDecl(func) if func.return_value.is_some() => Some(func.func_name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::FuncDecl(func) => {
+                    let mut positions: Vec<(usize, usize)> =
+                        tail_calls(func, &types, &returns_value)
+                            .into_iter()
+                            .map(|span| (span.line, span.col))
+                            .collect();
+                    positions.sort_unstable();
+
+                    Some((func.func_name.clone(), positions))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn returned_calls_are_tail_calls() {
+        let positions = tail_call_positions(
+            "function ack(m, n) {\n\tif (m == 0) return n + 1;\n\tif (n == 0) return ack(m - 1, 1);\n\n\treturn ack(m - 1, ack(m, n - 1));\n}\n",
+        );
+
+        // the inner `ack(m, n - 1)` is an argument, the outer call still has to be made
+        assert_eq!(positions, [("ack".to_string(), vec![(3, 21), (5, 9)])]);
+    }
+
+    #[test]
+    fn calls_whose_result_is_used_or_converted_are_not() {
+        let positions = tail_call_positions(
+            "function fact(n) {\n\tif (n == 0) return 1;\n\treturn n * fact(n - 1);\n}\n\nfunction half(n) {\n\treturn n * 0.5;\n}\n\nfunction whole(n) {\n\treturn fact(n);\n}\n\nfunction mixed(n) {\n\tif (n == 0) return half(n);\n\treturn fact(n);\n}\n",
+        );
+
+        assert_eq!(
+            positions,
+            [
+                ("fact".to_string(), vec![]),
+                ("half".to_string(), vec![]),
+                ("whole".to_string(), vec![(11, 9)]),
+                // `mixed` returns an f64, the result of `fact` is converted
+                ("mixed".to_string(), vec![(15, 21)]),
+            ]
+        );
+    }
+}
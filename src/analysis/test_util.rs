@@ -0,0 +1,34 @@
+//! Fixtures shared by the tests of the analyses.
+
+use std::collections::HashMap;
+
+use crate::{
+    analysis::{
+        closure::{FuncEnv, convert_closures},
+        lower_func_exprs,
+        types::{Types, infer_types},
+    },
+    lexer::lex,
+    parser::{FuncDeclBody, Stmt, parse},
+};
+
+/// Parses `src` and lowers it the way codegen does before running the analyses: function
+/// expressions become declarations, closures get an environment and the types are inferred
+pub fn parse_resolved(src: &str) -> (Vec<Stmt>, HashMap<String, FuncEnv>, Types) {
+    let (stmts, envs) =
+        convert_closures(lower_func_exprs(parse(lex(src.to_string())).unwrap().body));
+    let types = infer_types(&stmts, &envs);
+
+    (stmts, envs, types)
+}
+
+/// The top level function `name` of `stmts`
+pub fn find_func<'a>(stmts: &'a [Stmt], name: &str) -> &'a FuncDeclBody {
+    stmts
+        .iter()
+        .find_map(|stmt| match stmt {
+            Stmt::FuncDecl(func) if func.func_name == name => Some(func),
+            _ => None,
+        })
+        .unwrap_or_else(|| panic!("expected a function `{}`", name))
+}
@@ -0,0 +1,445 @@
+//! Type inference.
+//!
+//! Every parameter, local and function result gets the WASM type of the values that flow
+//! into it: the literals and variables it's assigned, the arguments it's called with and the
+//! values it returns. Whole numbers are `i32`, or `i64` when they don't fit, and decimals are
+//! `f64`. Where a value mixes types it widens to `f64`, a JavaScript number.
+//!
+//! Types only ever widen, so the constraints are applied until nothing changes. Runs on the
+//! closure converted program, a captured variable has the type of the function it's declared
+//! in since every closure reads the same environment slot.
+
+use std::collections::HashMap;
+
+use crate::{
+    analysis::closure::FuncEnv,
+    lexer::Token,
+    parser::{
+        BinaryStmtBody, FuncCallStmt, FuncDeclBody, Operand, Param, ReturnStmt, Stmt, is_number,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+impl ValType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F64 => "f64",
+        }
+    }
+
+    /// The type a number literal needs
+    pub fn of_number(text: &str) -> ValType {
+        if text.contains('.') {
+            ValType::F64
+        } else if text.parse::<i32>().is_ok() {
+            ValType::I32
+        } else if text.parse::<i64>().is_ok() {
+            ValType::I64
+        } else {
+            ValType::F64
+        }
+    }
+
+    /// The type both values fit in
+    pub fn join(self, other: ValType) -> ValType {
+        if self == other { self } else { ValType::F64 }
+    }
+}
+
+/// `None` is a value we don't know the type of yet
+fn join(a: Option<ValType>, b: Option<ValType>) -> Option<ValType> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.join(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Comparisons give a boolean whatever they compare
+pub fn is_comparison(op: &Token) -> bool {
+    matches!(
+        op,
+        Token::GreaterThan | Token::LessThan | Token::DoubleEq | Token::NotEq
+    )
+}
+
+/// The inferred types, anything nothing flows into is an `i32`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Types {
+    // (function the variable is declared in, variable)
+    vars: HashMap<(String, String), ValType>,
+    // elements of rest parameters, the parameter itself is a pointer
+    elements: HashMap<(String, String), ValType>,
+    results: HashMap<String, ValType>,
+}
+
+impl Types {
+    pub fn var(&self, func: &str, env: &FuncEnv, var: &str) -> ValType {
+        self.vars
+            .get(&var_key(func, env, var))
+            .copied()
+            .unwrap_or(ValType::I32)
+    }
+
+    /// The type of the arguments passed as `param` of `func`, the elements for a rest
+    /// parameter
+    pub fn param(&self, func: &str, param: &Param) -> ValType {
+        let map = if param.rest {
+            &self.elements
+        } else {
+            &self.vars
+        };
+
+        map.get(&(func.to_string(), param.name.clone()))
+            .copied()
+            .unwrap_or(ValType::I32)
+    }
+
+    pub fn result(&self, func: &str) -> ValType {
+        self.results.get(func).copied().unwrap_or(ValType::I32)
+    }
+
+    pub fn operand(&self, func: &str, env: &FuncEnv, operand: &Operand) -> ValType {
+        self.operand_known(func, env, operand)
+            .unwrap_or(ValType::I32)
+    }
+
+    pub fn binary_stmt(&self, func: &str, env: &FuncEnv, stmt: &BinaryStmtBody) -> ValType {
+        self.binary_stmt_known(func, env, stmt)
+            .unwrap_or(ValType::I32)
+    }
+
+    /// The type both operands of a binary stmt are converted to before the operator
+    pub fn operands(&self, func: &str, env: &FuncEnv, stmt: &BinaryStmtBody) -> ValType {
+        let rhs = stmt
+            .rhs
+            .as_ref()
+            .and_then(|rhs| self.operand_known(func, env, rhs));
+
+        join(self.operand_known(func, env, &stmt.lhs), rhs).unwrap_or(ValType::I32)
+    }
+
+    fn operand_known(&self, func: &str, env: &FuncEnv, operand: &Operand) -> Option<ValType> {
+        match operand {
+            Operand::Literal(text, _) if is_number(text) => Some(ValType::of_number(text)),
+            Operand::Literal(var, _) => self.vars.get(&var_key(func, env, var)).copied(),
+            Operand::Index(array, _, _) => self.elements.get(&var_key(func, env, array)).copied(),
+            Operand::Call(call) => self.results.get(&call.function_name).copied(),
+            Operand::Length(..) | Operand::Bool(_) | Operand::Null | Operand::Undefined => {
+                Some(ValType::I32)
+            }
+        }
+    }
+
+    fn binary_stmt_known(
+        &self,
+        func: &str,
+        env: &FuncEnv,
+        stmt: &BinaryStmtBody,
+    ) -> Option<ValType> {
+        match (&stmt.op, &stmt.rhs) {
+            (Some(op), Some(_)) if is_comparison(op) => Some(ValType::I32),
+            (Some(_), Some(rhs)) => join(
+                self.operand_known(func, env, &stmt.lhs),
+                self.operand_known(func, env, rhs),
+            ),
+            _ => self.operand_known(func, env, &stmt.lhs),
+        }
+    }
+
+    fn return_known(&self, func: &str, env: &FuncEnv, rt_val: &ReturnStmt) -> Option<ValType> {
+        match rt_val {
+            ReturnStmt::BinaryStmtBody(stmt) => self.binary_stmt_known(func, env, stmt),
+            ReturnStmt::FuncCallStmt(call) => self.results.get(&call.function_name).copied(),
+        }
+    }
+}
+
+/// Variables are keyed by the function they're declared in
+fn var_key(func: &str, env: &FuncEnv, var: &str) -> (String, String) {
+    let owner = env.owners.get(var).map_or(func, String::as_str);
+
+    (owner.to_string(), var.to_string())
+}
+
+/// Infers the types of a closure converted program, top level calls are typed as if they're
+/// in a function called `_start`
+pub fn infer_types(stmts: &[Stmt], envs: &HashMap<String, FuncEnv>) -> Types {
+    let signatures: HashMap<&str, &[Param]> = stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::FuncDecl(func) => Some((func.func_name.as_str(), func.arguments.as_slice())),
+            _ => None,
+        })
+        .collect();
+
+    let no_env = FuncEnv::default();
+
+    let mut infer = Infer {
+        types: Types::default(),
+        signatures,
+        changed: true,
+    };
+
+    while infer.changed {
+        infer.changed = false;
+
+        for stmt in stmts {
+            match stmt {
+                Stmt::FuncDecl(func) => {
+                    let env = envs.get(&func.func_name).unwrap_or(&no_env);
+
+                    infer.func_decl(func, env);
+                }
+                Stmt::FuncCall(call) => infer.call("_start", &no_env, call),
+                _ => (),
+            }
+        }
+    }
+
+    infer.types
+}
+
+struct Infer<'a> {
+    types: Types,
+    signatures: HashMap<&'a str, &'a [Param]>,
+    changed: bool,
+}
+
+impl Infer<'_> {
+    fn widen(
+        map: &mut HashMap<(String, String), ValType>,
+        key: (String, String),
+        ty: Option<ValType>,
+        changed: &mut bool,
+    ) {
+        let old = map.get(&key).copied();
+
+        if let Some(new) = join(old, ty)
+            && Some(new) != old
+        {
+            map.insert(key, new);
+            *changed = true;
+        }
+    }
+
+    fn widen_var(&mut self, func: &str, env: &FuncEnv, var: &str, ty: Option<ValType>) {
+        Self::widen(
+            &mut self.types.vars,
+            var_key(func, env, var),
+            ty,
+            &mut self.changed,
+        );
+    }
+
+    fn widen_result(&mut self, func: &str, ty: Option<ValType>) {
+        let old = self.types.results.get(func).copied();
+
+        if let Some(new) = join(old, ty)
+            && Some(new) != old
+        {
+            self.types.results.insert(func.to_string(), new);
+            self.changed = true;
+        }
+    }
+
+    fn func_decl(&mut self, func: &FuncDeclBody, env: &FuncEnv) {
+        let name = func.func_name.as_str();
+
+        for param in func.arguments.iter() {
+            if let Some(default) = &param.default {
+                let ty = self.types.binary_stmt_known(name, env, default);
+
+                self.binary_stmt(name, env, default);
+                self.widen_var(name, env, &param.name, ty);
+            }
+        }
+
+        self.block(name, env, &func.block);
+
+        if let Some(rt_val) = &func.return_value {
+            self.return_stmt(name, env, rt_val);
+        }
+    }
+
+    fn block(&mut self, func: &str, env: &FuncEnv, block: &[Stmt]) {
+        for stmt in block {
+            match stmt {
+                Stmt::LetStmt(let_stmt) | Stmt::RassignStmt(let_stmt) => {
+                    let ty = self.types.binary_stmt_known(func, env, &let_stmt.value);
+
+                    self.binary_stmt(func, env, &let_stmt.value);
+                    self.widen_var(func, env, &let_stmt.var_name, ty);
+                }
+                Stmt::WhileStmt(while_stmt) => {
+                    self.binary_stmt(func, env, &while_stmt.condition);
+                    self.block(func, env, &while_stmt.block);
+                }
+                Stmt::IfStmt(if_stmt) => {
+                    self.binary_stmt(func, env, &if_stmt.condition);
+                    self.block(func, env, &if_stmt.if_block);
+
+                    if let Some(rt_val) = &if_stmt.if_block_rt_val {
+                        self.return_stmt(func, env, rt_val);
+                    }
+                }
+                Stmt::BinaryStmt(stmt) => self.binary_stmt(func, env, stmt),
+                Stmt::FuncCall(call) => self.call(func, env, call),
+                // nested functions are lifted before this runs
+                Stmt::FuncDecl(_) | Stmt::FuncExpr(_) => (),
+            }
+        }
+    }
+
+    fn return_stmt(&mut self, func: &str, env: &FuncEnv, rt_val: &ReturnStmt) {
+        let ty = self.types.return_known(func, env, rt_val);
+
+        match rt_val {
+            ReturnStmt::BinaryStmtBody(stmt) => self.binary_stmt(func, env, stmt),
+            ReturnStmt::FuncCallStmt(call) => self.call(func, env, call),
+        }
+
+        self.widen_result(func, ty);
+    }
+
+    /// Only calls nested in the stmt add constraints, the stmt itself doesn't flow anywhere
+    fn binary_stmt(&mut self, func: &str, env: &FuncEnv, stmt: &BinaryStmtBody) {
+        for operand in std::iter::once(&stmt.lhs).chain(stmt.rhs.as_ref()) {
+            match operand {
+                Operand::Call(call) => self.call(func, env, call),
+                Operand::Index(_, index, _) => self.binary_stmt(func, env, index),
+                _ => (),
+            }
+        }
+    }
+
+    /// Every argument flows into the parameter it's passed as
+    fn call(&mut self, func: &str, env: &FuncEnv, call: &FuncCallStmt) {
+        let params = self.signatures.get(call.function_name.as_str()).copied();
+
+        for (i, arg) in call.arguments.iter().enumerate() {
+            let ty = match arg {
+                Stmt::BinaryStmt(stmt) => {
+                    self.binary_stmt(func, env, stmt);
+                    self.types.binary_stmt_known(func, env, stmt)
+                }
+                Stmt::FuncCall(call) => {
+                    self.call(func, env, call);
+                    self.types.results.get(&call.function_name).copied()
+                }
+                _ => None,
+            };
+
+            let Some(params) = params else {
+                continue;
+            };
+
+            let callee = call.function_name.as_str();
+            let regular = params.iter().filter(|param| !param.rest).count();
+
+            if i < regular {
+                // the callee's own parameters are never captured from somewhere else
+                Self::widen(
+                    &mut self.types.vars,
+                    (callee.to_string(), params[i].name.clone()),
+                    ty,
+                    &mut self.changed,
+                );
+            } else if let Some(rest) = params.iter().find(|param| param.rest) {
+                Self::widen(
+                    &mut self.types.elements,
+                    (callee.to_string(), rest.name.clone()),
+                    ty,
+                    &mut self.changed,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_util::parse_resolved;
+
+    fn infer_src(src: &str) -> (Types, HashMap<String, FuncEnv>) {
+        let (_, envs, types) = parse_resolved(src);
+
+        (types, envs)
+    }
+
+    #[test]
+    fn types_number_literals() {
+        assert_eq!(ValType::of_number("42"), ValType::I32);
+        assert_eq!(ValType::of_number("2147483648"), ValType::I64);
+        assert_eq!(ValType::of_number("0.5"), ValType::F64);
+        assert_eq!(ValType::of_number("99999999999999999999"), ValType::F64);
+    }
+
+    #[test]
+    fn infers_from_literals_and_calls() {
+        let (types, _) = infer_src(
+            "function half(n) {\n\treturn n * 0.5;\n}\n\nfunction big(a) {\n\tlet b = a + 3000000000;\n\treturn b;\n}\n\nfunction count(n) {\n\tlet i = 0;\n\treturn i < n;\n}\n\nhalf(3);\nbig(3000000000);\ncount(2);",
+        );
+
+        let env = FuncEnv::default();
+
+        // 3 is an i32, 0.5 is a decimal
+        assert_eq!(types.var("half", &env, "n"), ValType::I32);
+        assert_eq!(types.result("half"), ValType::F64);
+
+        assert_eq!(types.var("big", &env, "a"), ValType::I64);
+        assert_eq!(types.var("big", &env, "b"), ValType::I64);
+        assert_eq!(types.result("big"), ValType::I64);
+
+        assert_eq!(types.var("count", &env, "i"), ValType::I32);
+        assert_eq!(types.result("count"), ValType::I32);
+    }
+
+    #[test]
+    fn mixed_values_widen_to_f64() {
+        let (types, _) = infer_src(
+            "function pick(x) {\n\tif (x == 0) return 3000000000;\n\treturn 1;\n}\n\nfunction id(v) {\n\treturn v;\n}\n\nfunction main() {\n\tlet a = id(1);\n\tlet b = id(2.5);\n\treturn pick(a);\n}\n\nmain();",
+        );
+
+        let env = FuncEnv::default();
+
+        assert_eq!(types.result("pick"), ValType::F64);
+        // id is called with an i32 and a decimal
+        assert_eq!(types.var("id", &env, "v"), ValType::F64);
+        assert_eq!(types.var("main", &env, "a"), ValType::F64);
+        // a flows into x
+        assert_eq!(types.var("pick", &env, "x"), ValType::F64);
+    }
+
+    #[test]
+    fn infers_through_closures_and_rest_params() {
+        let (types, envs) = infer_src(
+            "function total(...xs) {\n\tlet sum = 0;\n\tfunction add(i) {\n\t\tsum = sum + xs[i];\n\t}\n\tadd(0);\n\treturn sum;\n}\n\ntotal(1, 0.5);",
+        );
+
+        let env = FuncEnv::default();
+
+        let xs = Param {
+            rest: true,
+            ..Param::from("xs")
+        };
+
+        assert_eq!(types.param("total", &xs), ValType::F64);
+        // the closure widens a variable of the function it's nested in
+        assert_eq!(types.var("total", &env, "sum"), ValType::F64);
+        assert_eq!(
+            types.var("total.add", &envs["total.add"], "sum"),
+            ValType::F64
+        );
+        assert_eq!(types.result("total"), ValType::F64);
+    }
+}
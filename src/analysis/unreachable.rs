@@ -0,0 +1,261 @@
+//! Unreachable code.
+//!
+//! Finds code that can never run and removes it before codegen, every piece of it is
+//! reported as a warning. A condition is constant when it's made of numbers and keyword
+//! literals only, like `1 == 0` or `false`. It's evaluated like constant folding does, in the
+//! type of its literals with wrapping integers, so it agrees with the generated code:
+//!
+//! - a `while` whose condition is false never runs its body
+//! - an `if` whose condition is false never runs its block
+//! - an `if` whose condition is true and that returns makes the rest of its block unreachable,
+//!   its own block takes the place of the `if`
+//!
+//! Code after a `return` in the same block is dropped by the parser, it only shows up here as
+//! a warning.
+
+use std::fmt;
+
+use crate::{
+    analysis::const_fold::constant_condition,
+    lexer::Span,
+    parser::{
+        FuncDeclBody, IfStmtBody, Program, ReturnStmt, Stmt, WhileStmtBody,
+        visit::{VisitorMut, walk_func_decl_mut, walk_if_stmt_mut, walk_while_stmt_mut},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    AfterReturn,
+    LoopNeverRuns,
+    BranchNeverRuns,
+    AlwaysReturns,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub span: Span,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "warning: ")?;
+
+        match self.kind {
+            WarningKind::AfterReturn => write!(f, "unreachable code after `return`"),
+            WarningKind::LoopNeverRuns => {
+                write!(f, "the condition is always false, this loop never runs")
+            }
+            WarningKind::BranchNeverRuns => {
+                write!(f, "the condition is always false, this branch never runs")
+            }
+            WarningKind::AlwaysReturns => write!(
+                f,
+                "the condition is always true, the code after this `if` never runs"
+            ),
+        }
+    }
+}
+
+/// Removes the code of the program that can never run, returns a warning for each piece of
+/// it in source order
+pub fn remove_unreachable(program: &mut Program) -> Vec<Warning> {
+    let mut pass = Unreachable {
+        warnings: program
+            .unreachable
+            .iter()
+            .map(|span| Warning {
+                kind: WarningKind::AfterReturn,
+                span: *span,
+            })
+            .collect(),
+    };
+
+    pass.visit_program_mut(program);
+
+    pass.warnings
+        .sort_by_key(|warning| (warning.span.line, warning.span.col));
+
+    pass.warnings
+}
+
+struct Unreachable {
+    warnings: Vec<Warning>,
+}
+
+impl Unreachable {
+    fn warn(&mut self, kind: WarningKind, span: Span) {
+        self.warnings.push(Warning { kind, span });
+    }
+
+    /// Removes the loops and branches that never run from a block. `rt_val` is the return of
+    /// the block, blocks that can't return (loops) don't get one
+    fn prune(&mut self, block: &mut Vec<Stmt>, mut rt_val: Option<&mut Option<ReturnStmt>>) {
+        let mut kept = Vec::new();
+        let mut stmts = std::mem::take(block).into_iter();
+
+        while let Some(stmt) = stmts.next() {
+            match stmt {
+                Stmt::WhileStmt(WhileStmtBody {
+                    condition, span, ..
+                }) if constant_condition(&condition) == Some(false) => {
+                    self.warn(WarningKind::LoopNeverRuns, span);
+                }
+                Stmt::IfStmt(IfStmtBody {
+                    condition, span, ..
+                }) if constant_condition(&condition) == Some(false) => {
+                    self.warn(WarningKind::BranchNeverRuns, span);
+                }
+                Stmt::IfStmt(IfStmtBody {
+                    condition,
+                    if_block,
+                    if_block_rt_val: Some(if_rt_val),
+                    span,
+                }) if constant_condition(&condition) == Some(true) && rt_val.is_some() => {
+                    // UNWRAP: checked by the guard
+                    let rt_val = rt_val.as_mut().unwrap();
+
+                    if stmts.len() > 0 || rt_val.is_some() {
+                        self.warn(WarningKind::AlwaysReturns, span);
+                    }
+
+                    kept.extend(if_block);
+                    **rt_val = Some(if_rt_val);
+
+                    break;
+                }
+                stmt => kept.push(stmt),
+            }
+        }
+
+        *block = kept;
+    }
+}
+
+impl VisitorMut for Unreachable {
+    fn visit_func_decl_mut(&mut self, func: &mut FuncDeclBody) {
+        self.prune(&mut func.block, Some(&mut func.return_value));
+
+        walk_func_decl_mut(self, func);
+    }
+
+    fn visit_while_stmt_mut(&mut self, while_stmt: &mut WhileStmtBody) {
+        self.prune(&mut while_stmt.block, None);
+
+        walk_while_stmt_mut(self, while_stmt);
+    }
+
+    fn visit_if_stmt_mut(&mut self, if_stmt: &mut IfStmtBody) {
+        self.prune(&mut if_stmt.if_block, Some(&mut if_stmt.if_block_rt_val));
+
+        walk_if_stmt_mut(self, if_stmt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lexer::lex,
+        parser::{parse, printer::print_program},
+    };
+
+    fn remove(src: &str) -> (String, Vec<(WarningKind, usize, usize)>) {
+        let mut program = parse(lex(src.to_string())).unwrap();
+
+        let warnings = remove_unreachable(&mut program)
+            .iter()
+            .map(|warning| (warning.kind, warning.span.line, warning.span.col))
+            .collect();
+
+        (print_program(&program), warnings)
+    }
+
+    #[test]
+    fn reports_code_after_return() {
+        let (printed, warnings) = remove(
+            "function f(a) {\n\tif (a == 1) {\n\t\treturn 2;\n\t\t// comments don't count\n\t}\n\treturn a;\n\tlet b = 1;\n\tif (b == 1) {\n\t\tb = 2;\n\t}\n}\n",
+        );
+
+        assert_eq!(
+            printed,
+            "function f(a) {\n\tif (a == 1) {\n\t\treturn 2;\n\t}\n\treturn a;\n}\n"
+        );
+        assert_eq!(warnings, [(WarningKind::AfterReturn, 7, 2)]);
+        assert_eq!(
+            Warning {
+                kind: WarningKind::AfterReturn,
+                span: Span::default()
+            }
+            .to_string(),
+            "warning: unreachable code after `return`"
+        );
+    }
+
+    #[test]
+    fn removes_branches_that_never_run() {
+        let (printed, warnings) = remove(
+            "function f(a) {\n\twhile (false) {\n\t\ta = a + 1;\n\t}\n\tif (1 == 0) {\n\t\ta = 2;\n\t}\n\tif (a == 0) {\n\t\twhile (0) {\n\t\t\ta = 3;\n\t\t}\n\t}\n\twhile (1 < 2) {\n\t\ta = a - 1;\n\t}\n\treturn a;\n}\n",
+        );
+
+        assert_eq!(
+            printed,
+            "function f(a) {\n\tif (a == 0) {\n\t}\n\twhile (1 < 2) {\n\t\ta = a - 1;\n\t}\n\treturn a;\n}\n"
+        );
+        assert_eq!(
+            warnings,
+            [
+                (WarningKind::LoopNeverRuns, 2, 2),
+                (WarningKind::BranchNeverRuns, 5, 2),
+                (WarningKind::LoopNeverRuns, 9, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn inlines_branches_that_always_return() {
+        let (printed, warnings) = remove(
+            "function f(a) {\n\tlet b = a;\n\tif (true) {\n\t\tlet c = b + 1;\n\t\treturn c;\n\t}\n\tb = 2;\n\treturn b;\n}\n\nfunction g(a) {\n\tif (1 != 0) return a;\n}\n",
+        );
+
+        assert_eq!(
+            printed,
+            "function f(a) {\n\tlet b = a;\n\tlet c = b + 1;\n\treturn c;\n}\n\nfunction g(a) {\n\treturn a;\n}\n"
+        );
+        // nothing comes after the `if` in g
+        assert_eq!(warnings, [(WarningKind::AlwaysReturns, 3, 2)]);
+    }
+
+    #[test]
+    fn evaluates_constant_conditions() {
+        let condition = |src: &str| {
+            let program = parse(lex(format!(
+                "function f() {{\n\tif ({}) return 1;\n}}",
+                src
+            )))
+            .unwrap();
+
+            let Stmt::FuncDecl(func) = &program.body[0] else {
+                panic!("expected a function");
+            };
+
+            let Stmt::IfStmt(if_stmt) = &func.block[0] else {
+                panic!("expected an if");
+            };
+
+            constant_condition(&if_stmt.condition)
+        };
+
+        assert_eq!(condition("null == undefined"), Some(true));
+        assert_eq!(condition("2 > 3"), Some(false));
+        assert_eq!(condition("0.5"), Some(true));
+        assert_eq!(condition("4 % 2"), Some(false));
+        assert_eq!(condition("a == 0"), None);
+        // integers wrap in their type like in the generated code
+        assert_eq!(condition("65536 * 65536"), Some(false));
+        assert_eq!(condition("4294967296 * 65536"), Some(true));
+        // the remainder traps, the branch is left to the code
+        assert_eq!(condition("1 % 0"), None);
+    }
+}
@@ -1,141 +1,835 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap, HashSet},
+};
+
 use crate::{
-    analysis::dead_code_pass,
-    lexer::Token,
+    analysis::{
+        cfg::Cfg,
+        closure::{EnvRef, FuncEnv, convert_closures},
+        const_fold::const_fold_pass,
+        cse::cse_pass,
+        dead_code::dead_code_pass,
+        effects::{Effects, analyze_effects},
+        licm::licm_pass,
+        liveness::{Coalesced, Liveness, coalesce},
+        lower_func_exprs,
+        shadowing::rename_shadowed,
+        tail_calls::{self_tail_calls, tail_calls},
+        types::{Types, ValType, infer_types},
+    },
+    codegen::strength::{Reduced, reduce},
+    ir::{
+        Function,
+        build::{Context, Unsupported, build},
+        gvn::number_values,
+        inline::inline_calls,
+        tail_recursion::loop_tail_recursion,
+        verify::verify,
+    },
+    lexer::{Span, Token},
     parser::{
-        BinaryStmtBody, FuncCallStmt, FuncDeclBody, IfStmtBody, LetStmtBody, ReturnStmt, Stmt,
-        WhileStmtBody,
+        BinaryStmtBody, FuncCallStmt, FuncDeclBody, FuncExprBody, IfStmtBody, LetStmtBody, Operand,
+        Param, ReturnStmt, Stmt, WhileStmtBody, is_number, visit::Visitor,
     },
 };
 
-pub fn wat_gen(parsed: Vec<Stmt>) -> String {
+mod ssa;
+mod strength;
+
+/// Bump allocator for closure environments, memory is never freed
+const ALLOC_WAT: &str = "(memory 1)
+(export \"memory\" (memory 0))
+(global $__heap (mut i32) (i32.const 8))
+(func $__alloc (param $size i32) (result i32) (local $ptr i32)
+global.get $__heap
+local.set $ptr
+global.get $__heap
+local.get $size
+i32.add
+global.set $__heap
+block
+global.get $__heap
+memory.size
+i32.const 16
+i32.shl
+i32.le_u
+br_if 0
+global.get $__heap
+memory.size
+i32.const 16
+i32.shl
+i32.sub
+i32.const 16
+i32.shr_u
+i32.const 1
+i32.add
+memory.grow
+drop
+end
+local.get $ptr
+)
+";
+
+/// Size in bytes of the length header and of every element of a rest parameter array
+const ARRAY_SLOT_SIZE: usize = 8;
+
+/// `null` and `undefined` lower to this pointer, the heap starts at 8 so nothing allocated is
+/// ever at 0. They're the same value, `null == undefined` holds like in JavaScript
+const NULL_PTR: i32 = 0;
+
+/// Everything a function body needs to know about the module and the function it's in
+struct FuncCtx<'a> {
+    // the (mangled) name of the function, `_start` for top level calls
+    func: &'a str,
+    env: &'a FuncEnv,
+    types: &'a Types,
+    // functions that leave a value on the stack
+    returns_value: &'a HashSet<String>,
+    // parameters of every function in the module
+    signatures: &'a HashMap<String, Vec<Param>>,
+    // wrappers needed by calls with fewer arguments than parameters, (function, arguments)
+    wrappers: &'a RefCell<BTreeSet<(String, usize)>>,
+    // scratch locals used so far to build rest parameter arrays
+    scratch_locals: Cell<usize>,
+    // f64 scratch locals used so far for `%`
+    float_locals: Cell<usize>,
+    // the local of every variable that shares one with another variable
+    locals: HashMap<String, String>,
+    // spans of the calls made with `return_call`
+    tail_calls: HashSet<Span>,
+    // spans of the calls of the function to itself that start its body over instead
+    loop_calls: HashSet<Span>,
+    // variables read before they're assigned, they're zeroed when the body starts over
+    uninitialized: Vec<(String, ValType)>,
+    // integer operations with a constant operand use cheaper instructions
+    reduce_strength: bool,
+    // values read right after they're stored stay on the stack, copies of a local to itself
+    // are left out
+    tee_locals: bool,
+}
+
+impl FuncCtx<'_> {
+    fn var_type(&self, var: &str) -> ValType {
+        self.types.var(self.func, self.env, var)
+    }
+
+    fn type_of(&self, stmt: &BinaryStmtBody) -> ValType {
+        self.types.binary_stmt(self.func, self.env, stmt)
+    }
+
+    fn local<'b>(&'b self, var: &'b str) -> &'b str {
+        self.locals.get(var).map_or(var, String::as_str)
+    }
+
+    fn push_stmt_wat(&self, wat: &mut String, code: &str) {
+        if self.tee_locals {
+            push_teed(wat, code);
+        } else {
+            wat.push_str(code);
+        }
+    }
+}
+
+/// How to compile a program
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// compile functions from the SSA IR, the ones it can't express yet still go through the
+    /// syntax tree
+    pub ssa: bool,
+    /// return the result of calls in tail position with `return_call` from the tail-call
+    /// proposal
+    pub tail_calls: bool,
+    /// 0 compiles functions as they're written, 1 folds constants and removes dead code, 2
+    /// also turns the tail calls of a function to itself into loops, hoists loop invariants,
+    /// reduces the strength of integer operations, computes repeated expressions once and
+    /// inlines small functions, the IR ones
+    pub opt_level: u8,
+    /// the most instructions a function inlined at `opt_level` 2 can have
+    pub inline_threshold: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            ssa: false,
+            tail_calls: false,
+            opt_level: 1,
+            inline_threshold: 12,
+        }
+    }
+}
+
+/// The closure converted program and what every function needs to know about it
+struct Module {
+    stmts: Vec<Stmt>,
+    envs: HashMap<String, FuncEnv>,
+    types: Types,
+    // functions that leave a value on the stack
+    returns_value: HashSet<String>,
+    // parameters of every function in the module
+    signatures: HashMap<String, Vec<Param>>,
+    // what every function does besides computing its result
+    effects: Effects,
+}
+
+impl Module {
+    fn new(parsed: Vec<Stmt>) -> Module {
+        let (stmts, envs) = convert_closures(rename_shadowed(lower_func_exprs(parsed)));
+        let types = infer_types(&stmts, &envs);
+
+        let returns_value: HashSet<String> = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::FuncDecl(FuncDeclBody {
+                    func_name,
+                    return_value: Some(_),
+                    ..
+                }) => Some(func_name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let signatures: HashMap<String, Vec<Param>> = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::FuncDecl(FuncDeclBody {
+                    func_name,
+                    arguments,
+                    ..
+                }) => Some((func_name.clone(), arguments.clone())),
+                _ => None,
+            })
+            .collect();
+
+        let effects = analyze_effects(&stmts, &envs);
+
+        Module {
+            stmts,
+            envs,
+            types,
+            returns_value,
+            signatures,
+            effects,
+        }
+    }
+}
+
+/// Folds the constants of a function and drops the assignments nothing reads, `-O2` also
+/// moves loop invariants out of loops and reuses the expressions it already computed
+fn optimize(func: FuncDeclBody, env: &FuncEnv, types: &Types, opt_level: u8) -> FuncDeclBody {
+    // folding first leaves variables that only held constants unused
+    let (mut block, mut return_value) = const_fold_pass(
+        &func.block,
+        func.return_value.as_ref(),
+        &func.func_name,
+        env,
+        types,
+    );
+    block = dead_code_pass(&block, return_value.as_ref(), env);
+
+    if opt_level >= 2 {
+        block = licm_pass(&block, &func.arguments, &func.func_name, env, types);
+        (block, return_value) =
+            cse_pass(&block, return_value.as_ref(), &func.func_name, env, types);
+    }
+
+    FuncDeclBody {
+        block,
+        return_value,
+        ..func
+    }
+}
+
+/// Whether every function of a program is pure, read-only or side-effecting
+pub fn func_effects(parsed: Vec<Stmt>) -> Effects {
+    Module::new(parsed).effects
+}
+
+/// The SSA form of every function of a program, or why it has none. The syntax tree is
+/// optimized at `options.opt_level` first, like it is for codegen
+pub fn ir_functions(
+    parsed: Vec<Stmt>,
+    options: &Options,
+) -> Vec<(String, Result<Function, Unsupported>)> {
+    let module = Module::new(parsed);
+    let no_env = FuncEnv::default();
+
+    let ctx = Context {
+        types: &module.types,
+        returns_value: &module.returns_value,
+        signatures: &module.signatures,
+    };
+
+    module
+        .stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::FuncDecl(func) => Some(func),
+            _ => None,
+        })
+        .map(|func| {
+            let env = module.envs.get(&func.func_name).unwrap_or(&no_env);
+            let function = build(
+                &optimize(func.clone(), env, &module.types, options.opt_level),
+                env,
+                &ctx,
+            );
+
+            (func.func_name.clone(), function)
+        })
+        .collect()
+}
+
+/// The WAT of a program and how many locals coalescing saved in every function
+pub fn wat_gen(parsed: Vec<Stmt>, options: &Options) -> (String, Vec<Coalesced>) {
     let mut wat = String::new();
+    let mut coalesced = Vec::new();
 
     wat.push_str("(module\n");
 
-    for stmt in parsed {
+    let Module {
+        stmts,
+        envs,
+        types,
+        returns_value,
+        signatures,
+        effects,
+    } = Module::new(parsed);
+
+    let ir_ctx = Context {
+        types: &types,
+        returns_value: &returns_value,
+        signatures: &signatures,
+    };
+
+    let wrappers = RefCell::new(BTreeSet::new());
+
+    if envs.values().any(|env| env.has_env) || signatures.values().flatten().any(|param| param.rest)
+    {
+        wat.push_str(ALLOC_WAT);
+    }
+
+    let no_env = FuncEnv::default();
+
+    let stmts: Vec<Stmt> = stmts
+        .into_iter()
+        .map(|stmt| match stmt {
+            Stmt::FuncDecl(func) if options.opt_level >= 1 => {
+                let env = envs.get(&func.func_name).unwrap_or(&no_env);
+                Stmt::FuncDecl(optimize(func, env, &types, options.opt_level))
+            }
+            stmt => stmt,
+        })
+        .collect();
+
+    // the IR of every function it can express, they're inlined into each other
+    let mut functions: Vec<Function> = Vec::new();
+
+    if options.ssa {
+        for stmt in &stmts {
+            if let Stmt::FuncDecl(func) = stmt
+                && let Ok(mut function) =
+                    build(func, envs.get(&func.func_name).unwrap_or(&no_env), &ir_ctx)
+            {
+                if options.opt_level >= 2 {
+                    loop_tail_recursion(&mut function);
+                }
+
+                functions.push(function);
+            }
+        }
+
+        if options.opt_level >= 2 {
+            inline_calls(&mut functions, options.inline_threshold);
+
+            for function in &mut functions {
+                number_values(function, &effects);
+            }
+        }
+    }
+
+    for stmt in stmts {
         match stmt {
-            Stmt::FuncDecl(FuncDeclBody {
-                func_name,
-                arguments,
-                return_value,
-                block,
-            }) => {
-                let dead_code_pass = dead_code_pass(&block, return_value.as_ref());
-                let local_vars = local_var_wat(extract_local_variables(&dead_code_pass));
-                let args = argument_var_wat(arguments);
+            Stmt::FuncDecl(func) => {
+                let env = envs.get(&func.func_name).unwrap_or(&no_env);
+
+                if let Some(function) = functions
+                    .iter()
+                    .find(|function| function.name == func.func_name)
+                {
+                    if let Err(errors) = verify(function) {
+                        panic!(
+                            "invalid IR for `{}`:\n{}{}",
+                            function.name,
+                            errors
+                                .iter()
+                                .map(|error| format!("{}\n", error))
+                                .collect::<String>(),
+                            function
+                        );
+                    }
+
+                    let (func_wat, saved) = ssa::func_wat(function, env, options);
+                    wat.push_str(&func_wat);
+                    coalesced.push(saved);
+                    continue;
+                }
+
+                let (locals, local_names, saved) = coalesce_locals(&func, env, &types);
+                coalesced.push(saved);
+
+                // a closure calling itself passes its environment along, it keeps the call
+                let loop_calls =
+                    if options.opt_level >= 2 && !env.closure_calls.contains_key(&func.func_name) {
+                        self_tail_calls(&func, &types, &returns_value)
+                    } else {
+                        HashSet::new()
+                    };
+
+                let uninitialized = if loop_calls.is_empty() {
+                    Vec::new()
+                } else {
+                    uninitialized_vars(&func, env, &types)
+                };
+
+                let tail_calls = if options.tail_calls {
+                    tail_calls(&func, &types, &returns_value)
+                } else {
+                    HashSet::new()
+                };
+
+                let FuncDeclBody {
+                    func_name,
+                    arguments,
+                    return_value,
+                    block,
+                    ..
+                } = func;
+
+                let ctx = FuncCtx {
+                    func: &func_name,
+                    env,
+                    types: &types,
+                    returns_value: &returns_value,
+                    signatures: &signatures,
+                    wrappers: &wrappers,
+                    scratch_locals: Cell::new(0),
+                    float_locals: Cell::new(0),
+                    locals,
+                    tail_calls,
+                    loop_calls,
+                    uninitialized,
+                    reduce_strength: options.opt_level >= 2,
+                    tee_locals: options.opt_level >= 2,
+                };
+
+                let prologue = env_prologue_wat(&arguments, &ctx);
+                let args = argument_var_wat(&arguments, &ctx);
 
                 let mut is_in_else_stmt = 0;
 
-                let fn_body = block_wat(dead_code_pass, &mut is_in_else_stmt);
+                let mut fn_body = block_wat(block, &mut is_in_else_stmt, &ctx);
+                fn_body.push('\n');
+
+                let rt_type = if let Some(x) = return_value {
+                    let rt_val = return_val_wat(x, &mut is_in_else_stmt, &ctx);
+                    ctx.push_stmt_wat(&mut fn_body, &rt_val);
 
-                let (rt_val, rt_type) = if let Some(x) = return_value {
-                    (return_val_wat(x, &mut is_in_else_stmt), "(result i32)")
+                    format!("(result {})", types.result(&func_name).as_str())
                 } else {
-                    (String::new(), "")
+                    String::new()
                 };
 
-                let wat_template = format!(
-                    "(func ${} {} {} {}\n {}\n{})\n(export \"{}\" (func ${}))\n",
-                    func_name, args, rt_type, local_vars, fn_body, rt_val, func_name, func_name,
+                let local_vars = local_var_wat(local_names, &ctx);
+
+                let mut body = format!("{} {}", prologue, fn_body);
+
+                // the calls that start the body over branch back to this loop
+                if !ctx.loop_calls.is_empty() {
+                    body = format!("loop $__recur {}\n{}\nend\n", rt_type, body);
+                }
+
+                let mut wat_template = format!(
+                    "(func ${} {} {} {}\n{})\n",
+                    func_name, args, rt_type, local_vars, body,
                 );
 
+                // lifted nested functions stay private to the module
+                if !ctx.env.lifted {
+                    wat_template.push_str(&format!(
+                        "(export \"{}\" (func ${}))\n",
+                        func_name, func_name
+                    ));
+                }
+
                 wat.push_str(&wat_template);
             }
             Stmt::FuncCall(call_stmt) => {
+                let ctx = FuncCtx {
+                    func: "_start",
+                    env: &no_env,
+                    types: &types,
+                    returns_value: &returns_value,
+                    signatures: &signatures,
+                    wrappers: &wrappers,
+                    scratch_locals: Cell::new(0),
+                    float_locals: Cell::new(0),
+                    locals: HashMap::new(),
+                    tail_calls: HashSet::new(),
+                    loop_calls: HashSet::new(),
+                    uninitialized: Vec::new(),
+                    reduce_strength: options.opt_level >= 2,
+                    tee_locals: options.opt_level >= 2,
+                };
+
+                let rt_type = types.result(&call_stmt.function_name);
+                let call = func_call_wat(call_stmt, &ctx);
+
                 wat.push_str(
                     format!(
-                        "(func $_start (result i32)\n{})\n(export \"_start\" (func $_start))",
-                        func_call_wat(call_stmt)
+                        "(func $_start (result {}) {}\n{})\n(export \"_start\" (func $_start))",
+                        rt_type.as_str(),
+                        local_var_wat(Vec::new(), &ctx),
+                        call
                     )
                     .as_str(),
                 );
             }
-            _ => break,
+            Stmt::FuncExpr(_) => unreachable!("function expressions are lowered before codegen"),
+            Stmt::WhileStmt(_)
+            | Stmt::IfStmt(_)
+            | Stmt::LetStmt(_)
+            | Stmt::RassignStmt(_)
+            | Stmt::BinaryStmt(_) => {
+                unreachable!("the parser only accepts functions and calls at the top level")
+            }
         }
     }
 
+    // a default value can call a function with too few arguments too
+    while let Some((func_name, given)) = wrappers.borrow_mut().pop_first() {
+        // the parameters of a wrapper are plain locals, even if the function moves them into
+        // its environment, the defaults still see the environments around the function
+        let env = FuncEnv {
+            captured: Vec::new(),
+            has_env: false,
+            ..envs.get(&func_name).unwrap_or(&no_env).clone()
+        };
+
+        let ctx = FuncCtx {
+            func: &func_name,
+            env: &env,
+            types: &types,
+            returns_value: &returns_value,
+            signatures: &signatures,
+            wrappers: &wrappers,
+            scratch_locals: Cell::new(0),
+            float_locals: Cell::new(0),
+            locals: HashMap::new(),
+            tail_calls: HashSet::new(),
+            loop_calls: HashSet::new(),
+            uninitialized: Vec::new(),
+            reduce_strength: options.opt_level >= 2,
+            tee_locals: options.opt_level >= 2,
+        };
+
+        wat.push_str(&wrapper_wat(&func_name, given, &ctx));
+    }
+
     wat.push(')');
 
-    wat
+    (wat, coalesced)
 }
 
-fn block_wat(stmts: Vec<Stmt>, is_in_else_stmt: &mut u8) -> String {
+/// Appends the code of a statement. When `wat` ends by storing a local that `code` starts by
+/// reading, the value stays on the stack with `local.tee` instead of being read back
+fn push_teed(wat: &mut String, code: &str) {
+    let end = wat.trim_end().len();
+    let line = wat[..end].rfind('\n').map_or(0, |idx| idx + 1);
+
+    if let Some(local) = wat[line..end].trim().strip_prefix("local.set ")
+        && let Some(rest) = code
+            .trim_start()
+            .strip_prefix(format!("local.get {}\n", local).as_str())
+    {
+        let teed = format!("local.tee {}\n", local);
+
+        wat.truncate(line);
+        wat.push_str(&teed);
+        wat.push_str(rest);
+    } else {
+        wat.push_str(code);
+    }
+}
+
+/// A function that takes only the first `given` parameters of `func_name`, fills in the
+/// default values of the rest and calls it
+fn wrapper_wat(func_name: &str, given: usize, ctx: &FuncCtx) -> String {
+    let params = &ctx.signatures[func_name];
+
+    let mut body = String::new();
+
+    for param in params.iter().skip(given) {
+        if param.rest {
+            body.push_str(&format!(
+                "i32.const {}\ncall $__alloc\nlocal.set ${}\n",
+                ARRAY_SLOT_SIZE, param.name
+            ));
+        } else if let Some(default) = &param.default {
+            let value = value_wat(default.clone(), ctx.var_type(&param.name), ctx);
+
+            ctx.push_stmt_wat(&mut body, &value);
+            body.push_str(&format!("local.set ${}\n", param.name));
+        }
+    }
+
+    if ctx.env.parent_env {
+        body.push_str("local.get $__penv\n");
+    }
+
+    for param in params {
+        ctx.push_stmt_wat(&mut body, &format!("local.get ${}\n", param.name));
+    }
+
+    body.push_str(&format!("call ${}\n", func_name));
+
+    let rt_type = if ctx.returns_value.contains(func_name) {
+        format!("(result {})", ctx.types.result(func_name).as_str())
+    } else {
+        String::new()
+    };
+
+    let locals = local_var_wat(
+        params[given..]
+            .iter()
+            .map(|param| param.name.clone())
+            .collect(),
+        ctx,
+    );
+
+    format!(
+        "(func ${}@{} {} {} {}\n{})\n",
+        func_name,
+        given,
+        argument_var_wat(&params[..given], ctx),
+        rt_type,
+        locals,
+        body
+    )
+}
+
+fn block_wat(stmts: Vec<Stmt>, is_in_else_stmt: &mut u8, ctx: &FuncCtx) -> String {
     let mut wat = String::new();
 
     for stmt in stmts {
+        let mut code = String::new();
+
         match stmt {
-            Stmt::RassignStmt(LetStmtBody { var_name, value })
-            | Stmt::LetStmt(LetStmtBody { var_name, value }) => {
-                let binary_stmt = binary_stmt_wat(value);
+            Stmt::RassignStmt(LetStmtBody {
+                var_name, value, ..
+            })
+            | Stmt::LetStmt(LetStmtBody {
+                var_name, value, ..
+            }) => {
+                let ty = ctx.var_type(&var_name);
+                let binary_stmt = value_wat(value, ty, ctx);
 
-                wat.push_str(&binary_stmt);
+                if let Some((env_ref, offset)) = ctx.env.lookup(&var_name) {
+                    code.push_str(&env_ptr_wat(env_ref));
+                    code.push_str(&binary_stmt);
+                    code.push_str(format!("{}.store offset={}\n", ty.as_str(), offset).as_str());
+                } else {
+                    let local = ctx.local(&var_name);
+
+                    // variables that share a local copy it to itself
+                    if ctx.tee_locals && binary_stmt == format!("local.get ${}\n", local) {
+                        continue;
+                    }
 
-                wat.push_str(format!("local.set ${}\n", var_name).as_str());
+                    code.push_str(&binary_stmt);
+                    code.push_str(format!("local.set ${}\n", local).as_str());
+                }
             }
 
-            Stmt::WhileStmt(WhileStmtBody { condition, block }) => {
-                let cond = binary_stmt_wat(condition);
+            Stmt::WhileStmt(WhileStmtBody {
+                condition, block, ..
+            }) => {
+                let cond = condition_wat(condition, ctx);
 
-                let wat_block = block_wat(block, is_in_else_stmt);
+                let mut wat_block = block_wat(block, is_in_else_stmt, ctx);
+                wat_block.push('\n');
+                ctx.push_stmt_wat(&mut wat_block, &cond);
 
-                let loop_stmt = format!("loop\n{}\n{} br_if 0 \n end\n", wat_block, cond);
-                wat.push_str(&loop_stmt);
+                let loop_stmt = format!("loop\n{} br_if 0 \n end\n", wat_block);
+                code.push_str(&loop_stmt);
             }
             Stmt::IfStmt(IfStmtBody {
                 condition,
                 if_block,
                 if_block_rt_val,
+                ..
             }) => {
-                let cond = binary_stmt_wat(condition);
+                let cond = condition_wat(condition, ctx);
 
                 // dont exit the else statement because we're about to enter another one
                 // we'll increase the counter below
-                let code_block = block_wat(if_block, &mut 0);
+                let code_block = block_wat(if_block, &mut 0, ctx);
                 let block_rt_val = if let Some(return_stmt) = if_block_rt_val {
-                    return_val_wat(return_stmt, &mut 0)
+                    return_val_wat(return_stmt, &mut 0, ctx)
                 } else {
                     String::new()
                 };
 
                 let if_stmt = format!(
-                    "{}\nif (result i32)\n {}\n{}\n else\n ",
-                    cond, code_block, block_rt_val,
+                    "{}\nif (result {})\n {}\n{}\n else\n ",
+                    cond,
+                    ctx.types.result(ctx.func).as_str(),
+                    code_block,
+                    block_rt_val,
                 );
 
                 *is_in_else_stmt += 1;
 
-                wat.push_str(if_stmt.as_str());
+                code.push_str(if_stmt.as_str());
+            }
+            Stmt::FuncCall(func_call) => {
+                let returns_value = ctx.returns_value.contains(&func_call.function_name);
+
+                code.push_str(&func_call_wat(func_call, ctx));
+
+                // a call used as a statement throws its result away
+                if returns_value {
+                    code.push_str("drop\n");
+                }
+            }
+            Stmt::BinaryStmt(binary_stmt) => {
+                code.push_str(&binary_stmt_wat(binary_stmt, ctx));
+                code.push_str("drop\n");
+            }
+            Stmt::FuncDecl(_) | Stmt::FuncExpr(_) => {
+                unreachable!("nested functions are lifted out before codegen")
             }
-            _ => break,
         }
+
+        ctx.push_stmt_wat(&mut wat, &code);
     }
 
     wat
 }
 
-fn extract_local_variables(stmts: &[Stmt]) -> Vec<String> {
-    let mut vars = Vec::new();
-    for stmt in stmts {
-        match stmt {
-            Stmt::LetStmt(LetStmtBody { var_name, .. }) => {
-                vars.push(var_name.to_string());
-            }
-            Stmt::WhileStmt(WhileStmtBody { block, .. }) => {
-                let nested_extracted = extract_local_variables(block);
+/// The variables of a function read before they're assigned, the parameters aside
+fn uninitialized_vars(func: &FuncDeclBody, env: &FuncEnv, types: &Types) -> Vec<(String, ValType)> {
+    let cfg = Cfg::build(&func.func_name, func);
 
-                vars.extend(nested_extracted);
-            }
-            _ => break,
+    Liveness::new(&cfg).live_in[cfg.entry]
+        .iter()
+        .filter(|var| {
+            env.lookup(var).is_none() && !func.arguments.iter().any(|param| param.name == **var)
+        })
+        .map(|var| (var.clone(), types.var(&func.func_name, env, var)))
+        .collect()
+}
+
+/// Shares locals between the variables of a function that are never live at the same time,
+/// returns the local of every variable, the locals to declare and how many were saved
+fn coalesce_locals(
+    func: &FuncDeclBody,
+    env: &FuncEnv,
+    types: &Types,
+) -> (HashMap<String, String>, Vec<String>, Coalesced) {
+    let var_type = |var: &str| types.var(&func.func_name, env, var);
+
+    // parameters moved into the environment keep a local nothing else uses
+    let params: Vec<(String, ValType)> = func
+        .arguments
+        .iter()
+        .filter(|param| env.lookup(&param.name).is_none())
+        .map(|param| {
+            let ty = if param.rest {
+                ValType::I32
+            } else {
+                var_type(&param.name)
+            };
+
+            (param.name.clone(), ty)
+        })
+        .collect();
+
+    let mut vars: Vec<(String, ValType)> = Vec::new();
+
+    for var in extract_local_variables(&func.block) {
+        if env.lookup(&var).is_none() && !params.iter().chain(&vars).any(|(other, _)| *other == var)
+        {
+            let ty = var_type(&var);
+            vars.push((var, ty));
         }
     }
 
-    vars
+    let cfg = Cfg::build(&func.func_name, func);
+    let param_names: Vec<String> = params.iter().map(|(param, _)| param.clone()).collect();
+    let interference = Liveness::new(&cfg).interference(&cfg, &param_names);
+
+    let count = vars.len();
+    let slots = coalesce(params, vars, &interference);
+
+    let mut locals = HashMap::new();
+
+    for slot in &slots {
+        for var in &slot.vars[1..] {
+            locals.insert(var.clone(), slot.vars[0].clone());
+        }
+    }
+
+    let names: Vec<String> = slots[param_names.len()..]
+        .iter()
+        .map(|slot| slot.vars[0].clone())
+        .collect();
+
+    let coalesced = Coalesced {
+        func: func.func_name.clone(),
+        locals: count,
+        slots: names.len(),
+    };
+
+    (locals, names, coalesced)
+}
+
+fn extract_local_variables(stmts: &[Stmt]) -> Vec<String> {
+    let mut vars = LocalVars(Vec::new());
+
+    vars.visit_block(stmts);
+
+    vars.0
+}
+
+/// Variables declared with `let` anywhere in a function body
+struct LocalVars(Vec<String>);
+
+impl Visitor for LocalVars {
+    fn visit_let_stmt(&mut self, let_stmt: &LetStmtBody) {
+        self.0.push(let_stmt.var_name.clone());
+    }
+
+    // nested functions have their own locals
+    fn visit_func_decl(&mut self, _: &FuncDeclBody) {}
+
+    fn visit_func_expr(&mut self, _: &FuncExprBody) {}
 }
 
-fn return_val_wat(stmt: ReturnStmt, is_in_else_stmt: &mut u8) -> String {
+fn return_val_wat(stmt: ReturnStmt, is_in_else_stmt: &mut u8, ctx: &FuncCtx) -> String {
+    let ty = ctx.types.result(ctx.func);
+
     let mut val = match stmt {
-        ReturnStmt::BinaryStmtBody(binary_stmt) => binary_stmt_wat(binary_stmt),
-        ReturnStmt::FuncCallStmt(func_call) => func_call_wat(func_call),
+        ReturnStmt::BinaryStmtBody(binary_stmt) => value_wat(binary_stmt, ty, ctx),
+        ReturnStmt::FuncCallStmt(func_call) if ctx.loop_calls.contains(&func_call.span) => {
+            recur_wat(func_call, ctx)
+        }
+        // the callee returns for the caller, its result is already of the right type
+        ReturnStmt::FuncCallStmt(func_call) if ctx.tail_calls.contains(&func_call.span) => {
+            call_op_wat(func_call, "return_call", ctx)
+        }
+        ReturnStmt::FuncCallStmt(func_call) => call_value_wat(func_call, ty, ctx),
     };
 
     for _ in 0..(*is_in_else_stmt) {
@@ -145,108 +839,500 @@ fn return_val_wat(stmt: ReturnStmt, is_in_else_stmt: &mut u8) -> String {
     val
 }
 
-fn func_call_wat(stmt: FuncCallStmt) -> String {
+fn func_call_wat(stmt: FuncCallStmt, ctx: &FuncCtx) -> String {
+    call_op_wat(stmt, "call", ctx)
+}
+
+/// The arguments of a call followed by `op`, `call` or `return_call`, of the function
+fn call_op_wat(stmt: FuncCallStmt, op: &str, ctx: &FuncCtx) -> String {
+    let (mut call, target) = call_args_wat(stmt, ctx);
+    call.push_str(format!("{} ${}\n", op, target).as_str());
+
+    call
+}
+
+/// A call of the function to itself in tail position, the arguments become the parameters
+/// and the body starts over
+fn recur_wat(stmt: FuncCallStmt, ctx: &FuncCtx) -> String {
+    let (mut wat, _) = call_args_wat(stmt, ctx);
+
+    // the arguments are on the stack in order, the last one is set first
+    for param in ctx.signatures[ctx.func].iter().rev() {
+        wat.push_str(&format!("local.set ${}\n", param.name));
+    }
+
+    for (var, ty) in &ctx.uninitialized {
+        wat.push_str(&format!(
+            "{}.const 0\nlocal.set ${}\n",
+            ty.as_str(),
+            ctx.local(var)
+        ));
+    }
+
+    wat.push_str("br $__recur\n");
+
+    wat
+}
+
+/// The arguments of a call and the function that takes them, a wrapper when default
+/// arguments are left out
+fn call_args_wat(stmt: FuncCallStmt, ctx: &FuncCtx) -> (String, String) {
     let FuncCallStmt {
         function_name,
         arguments,
+        ..
     } = stmt;
 
     let mut call = String::new();
 
-    for arg in arguments {
-        match arg {
-            Stmt::BinaryStmt(binary_stmt) => call.push_str(binary_stmt_wat(binary_stmt).as_str()),
-            Stmt::FuncCall(func_call) => {
-                call.push_str(func_call_wat(func_call).as_str());
-            }
-            _ => break,
+    // closures get the environment they were declared in as a hidden first argument
+    if let Some(env_ref) = ctx.env.closure_calls.get(&function_name) {
+        call.push_str(&env_ptr_wat(*env_ref));
+    }
+
+    let Some(params) = ctx.signatures.get(&function_name) else {
+        for arg in arguments {
+            call.push_str(&argument_wat(arg, ValType::I32, ctx));
         }
+
+        return (call, function_name);
+    };
+
+    let regular = params.iter().filter(|param| !param.rest).count();
+
+    let mut arguments = arguments.into_iter();
+    let mut given = 0;
+
+    // parameters go first so no argument is taken past the last one
+    for (param, arg) in params
+        .iter()
+        .filter(|param| !param.rest)
+        .zip(arguments.by_ref())
+    {
+        call.push_str(&argument_wat(
+            arg,
+            ctx.types.param(&function_name, param),
+            ctx,
+        ));
+        given += 1;
     }
 
-    call.push_str(format!("call ${}\n", function_name).as_str());
+    if given < regular {
+        // the wrapper fills in the defaults and the rest array
+        ctx.wrappers
+            .borrow_mut()
+            .insert((function_name.clone(), given));
 
-    call
+        return (call, format!("{}@{}", function_name, given));
+    }
+
+    let extra: Vec<Stmt> = arguments.collect();
+
+    if let Some(rest) = params.iter().find(|param| param.rest) {
+        call.push_str(&rest_array_wat(
+            extra,
+            ctx.types.param(&function_name, rest),
+            ctx,
+        ));
+    } else {
+        // extra arguments are still evaluated, the function just never sees them
+        for arg in extra {
+            call.push_str(&argument_wat(arg, ValType::I32, ctx));
+            call.push_str("drop\n");
+        }
+    }
+
+    (call, function_name)
 }
 
-fn binary_stmt_wat(stmt: BinaryStmtBody) -> String {
+/// An argument converted to the type of the parameter it's passed as
+fn argument_wat(arg: Stmt, ty: ValType, ctx: &FuncCtx) -> String {
+    match arg {
+        Stmt::BinaryStmt(binary_stmt) => value_wat(binary_stmt, ty, ctx),
+        Stmt::FuncCall(func_call) => call_value_wat(func_call, ty, ctx),
+        _ => String::new(),
+    }
+}
+
+/// The result of a call converted to `ty`
+fn call_value_wat(call: FuncCallStmt, ty: ValType, ctx: &FuncCtx) -> String {
+    let result = ctx.types.result(&call.function_name);
+    let returns_value = ctx.returns_value.contains(&call.function_name);
+
+    let mut wat = func_call_wat(call, ctx);
+
+    if returns_value {
+        wat.push_str(convert_wat(result, ty));
+    } else {
+        // a function without a return value gives `undefined`
+        wat.push_str(&format!("{}.const {}\n", ty.as_str(), NULL_PTR));
+    }
+
+    wat
+}
+
+/// Allocates an array for a rest parameter and pushes its address, the array is a length
+/// followed by the elements
+fn rest_array_wat(elements: Vec<Stmt>, ty: ValType, ctx: &FuncCtx) -> String {
+    // every array gets its own scratch local, an element can build an array of its own
+    let scratch = ctx.scratch_locals.get();
+    ctx.scratch_locals.set(scratch + 1);
+
+    let mut wat = format!(
+        "i32.const {}\ncall $__alloc\nlocal.tee $__rest{}\ni32.const {}\ni32.store\n",
+        (elements.len() + 1) * ARRAY_SLOT_SIZE,
+        scratch,
+        elements.len()
+    );
+
+    for (i, element) in elements.into_iter().enumerate() {
+        wat.push_str(&format!("local.get $__rest{}\n", scratch));
+        wat.push_str(&argument_wat(element, ty, ctx));
+        wat.push_str(&format!(
+            "{}.store offset={}\n",
+            ty.as_str(),
+            (i + 1) * ARRAY_SLOT_SIZE
+        ));
+    }
+
+    wat.push_str(&format!("local.get $__rest{}\n", scratch));
+
+    wat
+}
+
+/// Pushes the value of a binary stmt with the type inferred for it, both operands are
+/// converted to a common type first
+fn binary_stmt_wat(stmt: BinaryStmtBody, ctx: &FuncCtx) -> String {
     let mut temp = String::new();
 
+    let ty = ctx.types.operands(ctx.func, ctx.env, &stmt);
     let BinaryStmtBody { lhs, rhs, op } = stmt;
 
-    temp.push_str(&local_or_const_wat(&lhs));
+    let lhs_operand = lhs.clone();
+    let lhs = operand_wat(lhs, ty, ctx);
+
+    match (rhs, op) {
+        // wasm has no f64 remainder, its `a - trunc(a / b) * b`
+        (Some(right_hand), Some(Token::Percent)) if ty == ValType::F64 => {
+            let scratch = ctx.float_locals.get();
+            ctx.float_locals.set(scratch + 2);
 
-    if let Some(right_hand) = rhs {
-        temp.push_str(&local_or_const_wat(&right_hand));
-        if let Some(operation) = op {
-            let wat_op = op_wat(operation);
-            temp.push_str(wat_op);
+            temp.push_str(&format!(
+                "{}local.set $__f{}\n{}local.set $__f{}\n",
+                lhs,
+                scratch,
+                operand_wat(right_hand, ty, ctx),
+                scratch + 1
+            ));
+            temp.push_str(&format!(
+                "local.get $__f{0}\nlocal.get $__f{0}\nlocal.get $__f{1}\nf64.div\nf64.trunc\nlocal.get $__f{1}\nf64.mul\nf64.sub\n",
+                scratch,
+                scratch + 1
+            ));
         }
+        (Some(right_hand), Some(operation))
+            if ctx.reduce_strength
+                && let Some(wat) =
+                    reduced_wat(&lhs, &lhs_operand, &right_hand, &operation, ty, ctx) =>
+        {
+            temp.push_str(&wat);
+        }
+        (Some(right_hand), op) => {
+            temp.push_str(&lhs);
+            temp.push_str(&operand_wat(right_hand, ty, ctx));
+
+            if let Some(operation) = op {
+                temp.push_str(&op_wat(operation, ty));
+            }
+        }
+        (None, _) => temp.push_str(&lhs),
     }
 
     temp
 }
 
-fn argument_var_wat(stmts: Vec<Stmt>) -> String {
+/// Pushes the value of `lhs op rhs` with cheaper instructions when one of them is a constant,
+/// `lhs` is already compiled
+fn reduced_wat(
+    lhs: &str,
+    lhs_operand: &Operand,
+    rhs: &Operand,
+    op: &Token,
+    ty: ValType,
+    ctx: &FuncCtx,
+) -> Option<String> {
+    let constant = |operand: &Operand| match operand {
+        Operand::Literal(text, _) if is_number(text) && ValType::of_number(text).join(ty) == ty => {
+            Some(text.clone())
+        }
+        _ => None,
+    };
+
+    // the operand that's kept, compiled, and whether it has effects
+    let (reduced, other, effects) = if let Some(text) = constant(rhs) {
+        (
+            reduce(op, ty, &text, true)?,
+            lhs.to_string(),
+            !matches!(lhs_operand, Operand::Literal(..)),
+        )
+    } else {
+        let text = constant(lhs_operand)?;
+
+        (
+            reduce(op, ty, &text, false)?,
+            operand_wat(rhs.clone(), ty, ctx),
+            !matches!(rhs, Operand::Literal(..)),
+        )
+    };
+
+    let wat = match reduced {
+        Reduced::Operand => other,
+        Reduced::Then(instructions) => other + &instructions,
+        // a call still has to be made
+        Reduced::Const(constant) if effects => other + "drop\n" + &constant,
+        Reduced::Const(constant) => constant,
+    };
+
+    Some(wat)
+}
+
+/// Pushes the value of a binary stmt converted to `ty`
+fn value_wat(stmt: BinaryStmtBody, ty: ValType, ctx: &FuncCtx) -> String {
+    // a single operand can be pushed as `ty` directly, `f64.const 1` instead of converting
+    if stmt.op.is_none() {
+        return operand_wat(stmt.lhs, ty, ctx);
+    }
+
+    let natural = ctx.type_of(&stmt);
+
+    let mut wat = binary_stmt_wat(stmt, ctx);
+    wat.push_str(convert_wat(natural, ty));
+
+    wat
+}
+
+/// Pushes a condition as an i32, a number other than 0 is true
+fn condition_wat(stmt: BinaryStmtBody, ctx: &FuncCtx) -> String {
+    let ty = ctx.type_of(&stmt);
+
+    let mut wat = binary_stmt_wat(stmt, ctx);
+
+    if ty != ValType::I32 {
+        wat.push_str(&format!("{0}.const 0\n{0}.ne\n", ty.as_str()));
+    }
+
+    wat
+}
+
+/// Converts the value on top of the stack, numbers are signed like in JavaScript
+fn convert_wat(from: ValType, to: ValType) -> &'static str {
+    match (from, to) {
+        (ValType::I32, ValType::I64) => "i64.extend_i32_s\n",
+        (ValType::I32, ValType::F64) => "f64.convert_i32_s\n",
+        (ValType::I64, ValType::I32) => "i32.wrap_i64\n",
+        (ValType::I64, ValType::F64) => "f64.convert_i64_s\n",
+        (ValType::F64, ValType::I32) => "i32.trunc_f64_s\n",
+        (ValType::F64, ValType::I64) => "i64.trunc_f64_s\n",
+        _ => "",
+    }
+}
+
+fn argument_var_wat(params: &[Param], ctx: &FuncCtx) -> String {
     let mut wat = String::new();
 
-    for stmt in stmts {
-        if let Stmt::BinaryStmt(BinaryStmtBody { lhs, .. }) = stmt {
-            wat.push_str(format!("(param ${} i32) ", lhs).as_str());
-        }
+    if ctx.env.parent_env {
+        wat.push_str("(param $__penv i32) ");
+    }
+
+    for param in params {
+        wat.push_str(
+            format!(
+                "(param ${} {}) ",
+                param.name,
+                param_type(param, ctx).as_str()
+            )
+            .as_str(),
+        );
     }
 
     wat
 }
 
-fn local_var_wat(vars: Vec<String>) -> String {
+fn local_var_wat(vars: Vec<String>, ctx: &FuncCtx) -> String {
     let mut wat = String::new();
 
+    if ctx.env.has_env {
+        wat.push_str("(local $__env i32) ");
+    }
+
     for var in vars {
-        wat.push_str(format!("(local ${} i32) ", var).as_str())
+        wat.push_str(format!("(local ${} {}) ", var, ctx.var_type(&var).as_str()).as_str())
+    }
+
+    for scratch in 0..ctx.scratch_locals.get() {
+        wat.push_str(format!("(local $__rest{} i32) ", scratch).as_str())
+    }
+
+    for scratch in 0..ctx.float_locals.get() {
+        wat.push_str(format!("(local $__f{} f64) ", scratch).as_str())
     }
 
     wat
 }
 
-fn check_if_literal_i32(text: &str) -> bool {
-    text.chars().all(char::is_numeric)
+/// A rest parameter is a pointer to its array
+fn param_type(param: &Param, ctx: &FuncCtx) -> ValType {
+    if param.rest {
+        ValType::I32
+    } else {
+        ctx.var_type(&param.name)
+    }
 }
 
-fn local_or_const_wat(text: &str) -> String {
-    if check_if_literal_i32(text) {
-        format!("i32.const {}\n", text)
+/// Allocates the environment of a function, links it to the parent environment and moves
+/// the captured parameters into it
+fn env_prologue_wat(arguments: &[Param], ctx: &FuncCtx) -> String {
+    let mut wat = String::new();
+    let env = ctx.env;
+
+    if !env.has_env {
+        return wat;
+    }
+
+    wat.push_str(&format!(
+        "i32.const {}\ncall $__alloc\nlocal.tee $__env\n",
+        env.env_size()
+    ));
+
+    if env.parent_env {
+        wat.push_str("local.get $__penv\n");
     } else {
-        format!("local.get ${}\n", text)
+        wat.push_str("i32.const 0\n");
+    }
+
+    wat.push_str("i32.store\n");
+
+    for param in arguments {
+        if let Some((_, offset)) = env.lookup(&param.name) {
+            wat.push_str(&format!(
+                "local.get $__env\nlocal.get ${}\n{}.store offset={}\n",
+                param.name,
+                param_type(param, ctx).as_str(),
+                offset
+            ));
+        }
+    }
+
+    wat
+}
+
+/// Pushes the address of an environment
+fn env_ptr_wat(env_ref: EnvRef) -> String {
+    match env_ref {
+        EnvRef::Own => "local.get $__env\n".to_string(),
+        EnvRef::Outer(hops) => {
+            let mut wat = "local.get $__penv\n".to_string();
+
+            for _ in 0..hops {
+                wat.push_str("i32.load\n");
+            }
+
+            wat
+        }
     }
 }
 
-fn op_wat(token: Token) -> &'static str {
-    match token {
-        Token::Add => "i32.add\n",
-        Token::Mul => "i32.mul\n",
-        Token::GreaterThan => "i32.lt_u\n",
-        Token::LessThan => "i32.gt_u\n",
-        Token::Sub => "i32.sub\n",
-        Token::DoubleEq => "i32.eq\n",
-        Token::Percent => "i32.rem_u\n",
-        Token::NotEq => "i32.ne\n",
-        _ => "",
+/// Pushes an operand converted to `ty`
+fn operand_wat(operand: Operand, ty: ValType, ctx: &FuncCtx) -> String {
+    let natural = ctx.types.operand(ctx.func, ctx.env, &operand);
+
+    let mut wat = match operand {
+        // a number that fits in `ty` doesn't need converting
+        Operand::Literal(text, _)
+            if is_number(&text) && ValType::of_number(&text).join(ty) == ty =>
+        {
+            return format!("{}.const {}\n", ty.as_str(), text);
+        }
+        Operand::Literal(text, _) => local_or_const_wat(&text, natural, ctx),
+        Operand::Index(array, index, _) => format!(
+            "{}{}i32.const {}\ni32.shl\ni32.add\n{}.load offset={}\n",
+            local_or_const_wat(&array, ValType::I32, ctx),
+            value_wat(*index, ValType::I32, ctx),
+            ARRAY_SLOT_SIZE.trailing_zeros(),
+            natural.as_str(),
+            ARRAY_SLOT_SIZE
+        ),
+        Operand::Length(array, _) => format!(
+            "{}i32.load\n",
+            local_or_const_wat(&array, ValType::I32, ctx)
+        ),
+        Operand::Call(call) => return call_value_wat(call, ty, ctx),
+        Operand::Bool(value) => return format!("{}.const {}\n", ty.as_str(), value as i32),
+        Operand::Null | Operand::Undefined => {
+            return format!("{}.const {}\n", ty.as_str(), NULL_PTR);
+        }
+    };
+
+    wat.push_str(convert_wat(natural, ty));
+
+    wat
+}
+
+/// Pushes a variable, or a number that's already known to be a `ty`
+fn local_or_const_wat(text: &str, ty: ValType, ctx: &FuncCtx) -> String {
+    if is_number(text) {
+        format!("{}.const {}\n", ty.as_str(), text)
+    } else if let Some((env_ref, offset)) = ctx.env.lookup(text) {
+        format!(
+            "{}{}.load offset={}\n",
+            env_ptr_wat(env_ref),
+            ty.as_str(),
+            offset
+        )
+    } else {
+        format!("local.get ${}\n", ctx.local(text))
     }
 }
 
+/// Integers keep the unsigned comparisons and remainder
+fn op_wat(token: Token, ty: ValType) -> String {
+    let op = match (token, ty) {
+        (Token::Add, _) => "add",
+        (Token::Mul, _) => "mul",
+        (Token::Sub, _) => "sub",
+        (Token::DoubleEq, _) => "eq",
+        (Token::NotEq, _) => "ne",
+        (Token::GreaterThan, ValType::F64) => "lt",
+        (Token::LessThan, ValType::F64) => "gt",
+        (Token::GreaterThan, _) => "lt_u",
+        (Token::LessThan, _) => "gt_u",
+        (Token::Percent, _) => "rem_u",
+        _ => return String::new(),
+    };
+
+    format!("{}.{}\n", ty.as_str(), op)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::Token;
+    use crate::lexer::{Span, Token, lex};
     use crate::parser::{
-        BinaryStmtBody, FuncCallStmt, FuncDeclBody, IfStmtBody, LetStmtBody, ReturnStmt, Stmt,
-        WhileStmtBody,
+        BinaryStmtBody, FuncCallStmt, FuncDeclBody, FuncExprBody, IfStmtBody, LetStmtBody,
+        ReturnStmt, Stmt, WhileStmtBody, parse,
     };
 
+    /// The WAT of `src`
+    fn compile(src: &str, opts: &Options) -> String {
+        wat_gen(parse(lex(src.to_string())).unwrap().body, opts).0
+    }
+
     #[test]
     fn gen_simple_return_function() {
         let ast = vec![Stmt::FuncDecl(FuncDeclBody {
             func_name: "main".into(),
+            span: Span::default(),
+            exported: false,
             arguments: vec![],
             block: vec![],
             return_value: Some(ReturnStmt::BinaryStmtBody(BinaryStmtBody {
@@ -256,7 +1342,7 @@ mod tests {
             })),
         })];
 
-        let wat = wat_gen(ast);
+        let wat = wat_gen(ast, &Options::default()).0;
 
         assert!(wat.contains("(func $main"));
         assert!(wat.contains("i32.const 1"));
@@ -267,6 +1353,8 @@ mod tests {
     fn gen_binary_addition() {
         let ast = vec![Stmt::FuncDecl(FuncDeclBody {
             func_name: "add".into(),
+            span: Span::default(),
+            exported: false,
             arguments: vec![],
             block: vec![],
             return_value: Some(ReturnStmt::BinaryStmtBody(BinaryStmtBody {
@@ -276,7 +1364,14 @@ mod tests {
             })),
         })];
 
-        let wat = wat_gen(ast);
+        let wat = wat_gen(
+            ast,
+            &Options {
+                opt_level: 0,
+                ..Options::default()
+            },
+        )
+        .0;
 
         assert!(wat.contains("i32.const 1"));
         assert!(wat.contains("i32.const 2"));
@@ -287,9 +1382,12 @@ mod tests {
     fn gen_local_variable_assignment() {
         let ast = vec![Stmt::FuncDecl(FuncDeclBody {
             func_name: "main".into(),
+            span: Span::default(),
+            exported: false,
             arguments: vec![],
             block: vec![Stmt::LetStmt(LetStmtBody {
                 var_name: "x".into(),
+                span: Span::default(),
                 value: BinaryStmtBody {
                     lhs: "10".into(),
                     rhs: None,
@@ -297,13 +1395,20 @@ mod tests {
                 },
             })],
             return_value: Some(ReturnStmt::BinaryStmtBody(BinaryStmtBody {
-                lhs: String::from("x"),
+                lhs: "x".into(),
                 rhs: None,
                 op: None,
             })),
         })];
 
-        let wat = wat_gen(ast);
+        let wat = wat_gen(
+            ast,
+            &Options {
+                opt_level: 0,
+                ..Options::default()
+            },
+        )
+        .0;
 
         assert!(wat.contains("(local $x i32)"));
         assert!(wat.contains("i32.const 10"));
@@ -314,6 +1419,8 @@ mod tests {
     fn gen_while_loop() {
         let ast = vec![Stmt::FuncDecl(FuncDeclBody {
             func_name: "loop_fn".into(),
+            span: Span::default(),
+            exported: false,
             arguments: vec![],
             block: vec![Stmt::WhileStmt(WhileStmtBody {
                 condition: BinaryStmtBody {
@@ -322,11 +1429,12 @@ mod tests {
                     op: Some(Token::LessThan),
                 },
                 block: vec![],
+                span: Span::default(),
             })],
             return_value: None,
         })];
 
-        let wat = wat_gen(ast);
+        let wat = wat_gen(ast, &Options::default()).0;
 
         assert!(wat.contains("loop"));
         assert!(wat.contains("i32.gt_u"));
@@ -337,6 +1445,8 @@ mod tests {
     fn gen_if_statement_with_return() {
         let ast = vec![Stmt::FuncDecl(FuncDeclBody {
             func_name: "cond".into(),
+            span: Span::default(),
+            exported: false,
             arguments: vec![],
             block: vec![Stmt::IfStmt(IfStmtBody {
                 condition: BinaryStmtBody {
@@ -350,6 +1460,7 @@ mod tests {
                     rhs: None,
                     op: None,
                 })),
+                span: Span::default(),
             })],
             return_value: Some(ReturnStmt::BinaryStmtBody(BinaryStmtBody {
                 lhs: "42".into(),
@@ -358,11 +1469,532 @@ mod tests {
             })),
         })];
 
-        let wat = wat_gen(ast);
+        let wat = wat_gen(
+            ast,
+            &Options {
+                opt_level: 0,
+                ..Options::default()
+            },
+        )
+        .0;
 
         assert!(wat.contains("if (result i32)"));
         assert!(wat.contains("i32.eq"));
         assert!(wat.contains("i32.const 42"));
         assert!(wat.contains("end"));
     }
+
+    #[test]
+    fn gen_function_expression_as_function() {
+        let ast = vec![
+            Stmt::FuncExpr(FuncExprBody {
+                var_name: "add".into(),
+                decl: Some(Token::Const),
+                func: FuncDeclBody {
+                    func_name: "add".into(),
+                    span: Span::default(),
+                    exported: false,
+                    arguments: vec!["a".into(), "b".into()],
+                    block: vec![],
+                    return_value: Some(ReturnStmt::BinaryStmtBody(BinaryStmtBody {
+                        lhs: "a".into(),
+                        rhs: Some("b".into()),
+                        op: Some(Token::Add),
+                    })),
+                },
+            }),
+            Stmt::FuncCall(FuncCallStmt {
+                function_name: "add".into(),
+                span: Span::default(),
+                arguments: vec![],
+            }),
+        ];
+
+        let wat = wat_gen(ast, &Options::default()).0;
+
+        assert!(wat.contains("(func $add (param $a i32) (param $b i32)"));
+        assert!(wat.contains("(export \"add\""));
+        assert!(wat.contains("call $add"));
+    }
+
+    #[test]
+    fn gen_closure_environment() {
+        let wat = compile(
+            "function counter(n) {\n\tlet count = 0;\n\tfunction inc() {\n\t\tcount = count + n;\n\t}\n\tinc();\n\treturn count;\n}",
+            &Options::default(),
+        );
+
+        assert!(wat.contains("(func $__alloc"));
+        assert!(wat.contains("(func $counter.inc (param $__penv i32)"));
+        assert!(!wat.contains("(export \"counter.inc\""));
+        // count and n live in the environment, not in locals
+        assert!(!wat.contains("(local $count i32)"));
+        assert!(wat.contains("local.get $__env\nlocal.get $n\ni32.store offset=16"));
+        // inc doesn't return anything so there is nothing to drop
+        assert!(wat.contains("local.get $__env\ncall $counter.inc\n"));
+        assert!(!wat.contains("call $counter.inc\ndrop"));
+        assert!(wat.contains("local.get $__penv\ni32.load offset=8"));
+    }
+
+    #[test]
+    fn gen_wrapper_for_missing_arguments() {
+        let wat = compile(
+            "function scale(a, factor = 10) {\n\treturn a * factor;\n}\n\nscale(4);",
+            &Options::default(),
+        );
+
+        assert!(wat.contains("call $scale@1"));
+        assert!(wat.contains("(func $scale@1 (param $a i32)  (result i32) (local $factor i32)"));
+        assert!(wat.contains(
+            "i32.const 10\nlocal.set $factor\nlocal.get $a\nlocal.get $factor\ncall $scale\n"
+        ));
+    }
+
+    #[test]
+    fn gen_rest_array() {
+        let wat = compile(
+            "function count(...xs) {\n\treturn xs.length;\n}\n\ncount(1, 2);",
+            &Options::default(),
+        );
+
+        assert!(wat.contains("(func $__alloc"));
+        assert!(wat.contains("(local $__rest0 i32)"));
+        // a length of 2 followed by the two elements
+        assert!(
+            wat.contains(
+                "i32.const 24\ncall $__alloc\nlocal.tee $__rest0\ni32.const 2\ni32.store\n"
+            )
+        );
+        assert!(wat.contains("i32.const 2\ni32.store offset=16\n"));
+        assert!(wat.contains("local.get $xs\ni32.load\n"));
+    }
+
+    #[test]
+    fn gen_calls_in_expressions() {
+        let wat = compile(
+            "function fib(n) {\n\tif (n < 2) return n;\n\treturn fib(n - 1) + fib(n - 2);\n}",
+            &Options::default(),
+        );
+
+        // the left call runs first
+        assert!(wat.contains(
+            "local.get $n\ni32.const 1\ni32.sub\ncall $fib\nlocal.get $n\ni32.const 2\ni32.sub\ncall $fib\ni32.add\n"
+        ));
+    }
+
+    #[test]
+    fn gen_value_keywords() {
+        let wat = compile(
+            "function f(n) {\n\tif (false) return null;\n\treturn true + undefined;\n}",
+            &Options {
+                opt_level: 0,
+                ..Options::default()
+            },
+        );
+
+        assert!(wat.contains(" i32.const 0\n\nif (result i32)"));
+        assert!(wat.contains("i32.const 1\ni32.const 0\ni32.add"));
+        assert!(!wat.contains("$true"));
+    }
+
+    #[test]
+    fn gen_call_without_value_in_expression() {
+        let src = "function log(n) {\n\tlet x = n;\n}\n\nfunction k(v) {\n\treturn v;\n}\n\nfunction f(n) {\n\tlet y = k(log(n));\n\treturn log(n) + y;\n}\n\nf(1);";
+
+        for (ssa, opt_level) in [(false, 0), (false, 1), (false, 2), (true, 2)] {
+            let options = Options {
+                ssa,
+                opt_level,
+                ..Options::default()
+            };
+
+            let wat = compile(src, &options);
+
+            // `log` returns nothing, its result is `undefined`
+            assert_eq!(wat.matches("call $log\ni32.const 0\n").count(), 2);
+        }
+    }
+
+    #[test]
+    fn gen_inferred_types() {
+        let wat = compile(
+            "function half(n) {\n\tlet h = n * 0.5;\n\twhile (h) {\n\t\th = h - 1;\n\t}\n\treturn h;\n}\n\nfunction big() {\n\treturn 3000000000;\n}\n\nhalf(3);",
+            &Options::default(),
+        );
+
+        assert!(wat.contains("(func $half (param $n i32)  (result f64) (local $h f64)"));
+        assert!(wat.contains("local.get $n\nf64.convert_i32_s\nf64.const 0.5\nf64.mul\n"));
+        // the literal is pushed as an f64 directly
+        assert!(wat.contains("local.get $h\nf64.const 1\nf64.sub\n"));
+        // a number is true when it isn't 0
+        assert!(wat.contains("local.get $h\nf64.const 0\nf64.ne\n br_if 0"));
+        assert!(wat.contains("(func $big  (result i64)"));
+        assert!(wat.contains("i64.const 3000000000"));
+        assert!(wat.contains("(func $_start (result f64)"));
+    }
+
+    #[test]
+    fn gen_folded_constants() {
+        let wat = compile(
+            "function f() {\n\tlet x = 2;\n\tlet y = x * 21;\n\treturn y;\n}",
+            &Options::default(),
+        );
+
+        assert!(wat.contains("(func $f  (result i32) \n \ni32.const 42\n)"));
+        assert!(!wat.contains("i32.mul"));
+    }
+
+    #[test]
+    fn gen_folded_constant_operands() {
+        let wat = |src: &str| compile(src, &Options::default());
+
+        let add = wat("function add() {\n\treturn 1 + 2;\n}");
+        assert!(add.contains("i32.const 3\n"));
+        assert!(!add.contains("i32.add"));
+
+        // `x` is only ever 10, it's returned directly and its local is gone
+        let local = wat("function main() {\n\tlet x = 10;\n\treturn x;\n}");
+        assert!(local.contains("i32.const 10\n"));
+        assert!(!local.contains("$x"));
+
+        let cond = wat("function cond() {\n\tif (1 == 1) return 42;\n\treturn 42;\n}");
+        assert!(cond.contains("i32.const 1\n\nif (result i32)"));
+        assert!(!cond.contains("i32.eq"));
+
+        let keywords =
+            wat("function f(n) {\n\tif (false) return null;\n\treturn true + undefined;\n}");
+        assert!(keywords.contains("i32.const 1\n"));
+        assert!(!keywords.contains("i32.add"));
+    }
+
+    #[test]
+    fn gen_f64_remainder() {
+        let wat = compile(
+            "function frac(x) {\n\treturn x % 2;\n}\n\nfrac(7.5);",
+            &Options::default(),
+        );
+
+        assert!(wat.contains("(local $__f0 f64) (local $__f1 f64)"));
+        assert!(wat.contains(
+            "local.get $x\nlocal.set $__f0\nf64.const 2\nlocal.set $__f1\nlocal.get $__f0\nlocal.get $__f0\nlocal.get $__f1\nf64.div\nf64.trunc\n"
+        ));
+    }
+
+    #[test]
+    fn gen_coalesced_locals() {
+        let src = "function f(a) {\n\tlet x = a * 2;\n\tlet y = x + 1;\n\tlet z = y * 3;\n\treturn z + a;\n}\n\nf(1);";
+
+        let (wat, coalesced) = wat_gen(parse(lex(src.into())).unwrap().body, &Options::default());
+
+        // `a` is live to the end, the others each die where the next one is assigned
+        assert!(wat.contains("(func $f (param $a i32)  (result i32) (local $x i32) \n"));
+        assert!(
+            wat.contains(
+                "i32.mul\nlocal.set $x\nlocal.get $x\ni32.const 1\ni32.add\nlocal.set $x\n"
+            )
+        );
+        assert_eq!(
+            coalesced[0],
+            Coalesced {
+                func: "f".into(),
+                locals: 3,
+                slots: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn gen_tail_calls() {
+        let src = "function ack(m, n) {\n\tif (m == 0) return n + 1;\n\tif (n == 0) return ack(m - 1, 1);\n\n\treturn ack(m - 1, ack(m, n - 1));\n}\n\nack(3, 4);";
+
+        let wat = compile(src, &Options::default());
+
+        assert!(!wat.contains("return_call"));
+
+        for ssa in [false, true] {
+            let options = Options {
+                ssa,
+                tail_calls: true,
+                ..Options::default()
+            };
+            let wat = compile(src, &options);
+
+            // the inner call is an argument of the outer one
+            assert_eq!(wat.matches("return_call $ack\n").count(), 2);
+            assert_eq!(wat.matches("\ncall $ack\n").count(), 2);
+        }
+    }
+
+    #[test]
+    fn gen_self_tail_calls_as_loops() {
+        let src = "function ack(m, n) {\n\tif (m == 0) return n + 1;\n\tif (n == 0) return ack(m - 1, 1);\n\n\treturn ack(m - 1, ack(m, n - 1));\n}\n\nack(3, 4);";
+
+        let options = Options {
+            opt_level: 2,
+            ..Options::default()
+        };
+        let wat = compile(src, &options);
+
+        assert!(wat.contains(
+            "(func $ack (param $m i32) (param $n i32)  (result i32) \nloop $__recur (result i32)\n"
+        ));
+        assert!(wat.contains("i32.const 1\nlocal.set $n\nlocal.set $m\nbr $__recur\n"));
+        // the inner call isn't in tail position, the outer one still starts the body over
+        assert!(wat.contains("i32.sub\ncall $ack\nlocal.set $n\nlocal.set $m\nbr $__recur\n"));
+
+        let wat = compile(
+            src,
+            &Options {
+                ssa: true,
+                ..options
+            },
+        );
+
+        assert_eq!(wat.matches("call $ack\n").count(), 2);
+        assert!(wat.contains("loop\n"));
+    }
+
+    #[test]
+    fn gen_inlined_calls() {
+        let src = "function sq(x) {\n\treturn x * x;\n}\n\nfunction fact(n) {\n\tif (n == 0) return 1;\n\treturn n * fact(n - 1);\n}\n\nfunction f(a) {\n\tlet b = sq(a);\n\treturn fact(b);\n}\n\nf(2);";
+
+        let options = Options {
+            ssa: true,
+            opt_level: 2,
+            ..Options::default()
+        };
+
+        let wat = compile(src, &options);
+
+        assert!(!wat.contains("call $sq\n"));
+        assert!(wat.contains(
+            "(func $f (param $a i32)  (result i32) \nlocal.get $a\nlocal.get $a\ni32.mul\n"
+        ));
+        // recursive functions are never inlined
+        assert_eq!(wat.matches("call $fact\n").count(), 2);
+
+        let wat = compile(
+            src,
+            &Options {
+                inline_threshold: 0,
+                ..options
+            },
+        );
+
+        assert!(wat.contains("call $sq\n"));
+    }
+
+    #[test]
+    fn gen_hoisted_invariants() {
+        let src = "function f(a, b, n) {\n\tlet s = 0;\n\twhile (n > s) {\n\t\tlet k = a * b;\n\t\ts = s + k;\n\t}\n\treturn s;\n}\n\nf(1, 2, 3);";
+
+        for ssa in [false, true] {
+            let options = Options {
+                ssa,
+                opt_level: 2,
+                ..Options::default()
+            };
+
+            let wat = compile(src, &options);
+            let (preheader, _) = wat.split_once("loop\n").unwrap();
+
+            assert!(preheader.contains("i32.mul\n"));
+        }
+
+        // -O1 leaves the loop alone
+        let wat = compile(src, &Options::default());
+        let (preheader, _) = wat.split_once("loop\n").unwrap();
+
+        assert!(!preheader.contains("i32.mul\n"));
+    }
+
+    #[test]
+    fn gen_strength_reduction() {
+        let src = "function g(x) {\n\treturn x;\n}\n\nfunction f(a) {\n\tlet p = a * 8;\n\tlet r = p % 16;\n\tlet s = 0 + r;\n\tlet z = g(s) * 0;\n\tlet e = z == 0;\n\treturn e;\n}\n\nf(1);";
+
+        let options = Options {
+            opt_level: 2,
+            ..Options::default()
+        };
+
+        let wat = compile(src, &options);
+
+        // the variables all share the local of `a`, the value stays on the stack
+        assert!(wat.contains(
+            "local.get $a\ni32.const 3\ni32.shl\nlocal.tee $a\ni32.const 15\ni32.and\nlocal.tee $a\ncall $g\n"
+        ));
+        // the call is still made
+        assert!(wat.contains("call $g\ndrop\ni32.const 0\nlocal.tee $a\ni32.eqz\n"));
+
+        let wat = compile(
+            src,
+            &Options {
+                ssa: true,
+                ..options
+            },
+        );
+
+        assert!(wat.contains("i32.shl\n") && wat.contains("i32.and\n"));
+        assert!(!wat.contains("i32.mul\n") && !wat.contains("i32.rem_u\n"));
+
+        // -O1 keeps the instructions as they're written
+        let wat = compile(src, &Options::default());
+
+        assert!(wat.contains("i32.mul\n") && !wat.contains("i32.shl\n"));
+    }
+
+    #[test]
+    fn gen_repeated_expressions() {
+        let src = "function g(n) {\n\treturn n;\n}\n\nfunction f(a, b) {\n\tlet x = a % b;\n\tlet y = g(a % b);\n\tlet z = y + x;\n\tlet w = a % b;\n\treturn w + z;\n}\n\nf(7, 3);";
+
+        for ssa in [false, true] {
+            let options = Options {
+                ssa,
+                opt_level: 2,
+                ..Options::default()
+            };
+
+            let wat = compile(src, &options);
+
+            // the remainder is kept on the stack for its first use
+            assert_eq!(wat.matches("i32.rem_u\n").count(), 1);
+            assert!(wat.contains("i32.rem_u\nlocal.tee $a\n"));
+        }
+
+        let wat = compile(src, &Options::default());
+
+        assert_eq!(wat.matches("i32.rem_u\n").count(), 3);
+    }
+
+    #[test]
+    fn gen_calls_of_pure_functions_once() {
+        let src = "function fact(n) {\n\tif (n == 0) return 1;\n\tlet m = n - 1;\n\tlet r = fact(m);\n\treturn n * r;\n}\n\nfunction f(a) {\n\tlet x = fact(a);\n\tlet y = fact(a);\n\treturn x + y;\n}\n\nf(4);";
+
+        assert_eq!(
+            func_effects(parse(lex(src.into())).unwrap().body)
+                .into_iter()
+                .map(|(name, effect)| format!("{}: {}", name, effect))
+                .collect::<Vec<_>>(),
+            ["f: pure", "fact: pure"]
+        );
+
+        let options = Options {
+            ssa: true,
+            opt_level: 2,
+            ..Options::default()
+        };
+
+        // the recursive call and one of the calls in `f`
+        assert_eq!(compile(src, &options).matches("call $fact\n").count(), 2);
+        assert_eq!(
+            compile(src, &Options::default())
+                .matches("call $fact\n")
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn gen_tee_of_stored_values() {
+        let src = "function f(a, n) {\n\tlet x = a + 1;\n\tlet y = x * x;\n\twhile (n > y) {\n\t\ty = y + x;\n\t}\n\treturn y;\n}\n\nf(1, 20);";
+
+        for ssa in [false, true] {
+            let wat = compile(
+                src,
+                &Options {
+                    ssa,
+                    opt_level: 2,
+                    ..Options::default()
+                },
+            );
+
+            // `x` shares the local of `a`, the sum is read right after it's stored
+            assert!(wat.contains("i32.add\nlocal.tee $a\nlocal.get $a\ni32.mul\n"));
+        }
+
+        // the `y` of the loop is read after its `end`, it's stored
+        let wat = compile(
+            src,
+            &Options {
+                opt_level: 2,
+                ..Options::default()
+            },
+        );
+        assert!(wat.contains("i32.add\nlocal.set $y\n"));
+        assert!(!wat.contains("local.tee $y"));
+
+        // -O1 stores and reads back
+        let wat = compile(src, &Options::default());
+        assert!(!wat.contains("local.tee"));
+    }
+
+    #[test]
+    fn gen_shadowed_let_in_loop() {
+        let src = "function f(n) {\n\tlet x = 100;\n\tlet i = 0;\n\twhile (i < n) {\n\t\tlet x = i + 1;\n\t\ti = x;\n\t}\n\treturn x + i;\n}\n\nf(8);";
+        let wat = |opts: &Options| compile(src, opts);
+
+        // the `x` of the loop shares a slot with `i`, never with the outer `x`
+        let wat_o0 = wat(&Options {
+            opt_level: 0,
+            ..Options::default()
+        });
+        assert!(
+            wat_o0.contains("i32.const 1\ni32.add\nlocal.set $i\nlocal.get $i\nlocal.set $i\n")
+        );
+        assert!(wat_o0.contains("local.get $x\nlocal.get $i\ni32.add\n)"));
+
+        let wat_ssa = wat(&Options {
+            ssa: true,
+            ..Options::default()
+        });
+        assert!(wat_ssa.contains("else\ni32.const 100\n"));
+    }
+
+    #[test]
+    fn ir_functions_follow_the_opt_level() {
+        let ir = |opt_level: u8| {
+            let ast = parse(lex(
+                "function f(a, n) {\n\tlet i = 0;\n\tlet s = 0;\n\twhile (i < n) {\n\t\tlet k = a * 3;\n\t\ts = s + k;\n\t\ti = i + 1;\n\t}\n\treturn s;\n}\n".into(),
+            ))
+            .unwrap()
+            .body;
+
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+
+            ir_functions(ast, &options).remove(0).1.unwrap().to_string()
+        };
+
+        // the invariant `a * 3` is only hoisted out of the loop at 2
+        let entry = |printed: &str| printed.split("bb1:").next().unwrap().to_string();
+
+        assert!(!entry(&ir(0)).contains("%0 * "));
+        assert!(entry(&ir(2)).contains("%0 * "));
+    }
+
+    #[test]
+    fn gen_from_ssa() {
+        let wat = compile(
+            "function gcd(a, b) {\n\twhile (b != 0) {\n\t\tlet t = b;\n\t\tb = a % b;\n\t\ta = t;\n\t}\n\treturn a;\n}\n\nfunction counter(n) {\n\tlet count = 0;\n\tfunction inc() {\n\t\tcount = count + n;\n\t}\n\tinc();\n\treturn count;\n}",
+            &Options {
+                ssa: true,
+                ..Options::default()
+            },
+        );
+
+        // the phis reuse the locals of the parameters, the compare is the only other value
+        assert!(wat.contains(
+            "(func $gcd (param $a i32) (param $b i32)  (result i32) (local $__v4 i32) \n"
+        ));
+        // both phis of the loop header are read before either is set
+        assert!(wat.contains("local.get $b\nlocal.get $a\nlocal.set $b\nlocal.set $a\nloop\n"));
+        assert!(wat.contains("local.get $b\nlocal.get $a\ni32.rem_u\nlocal.set $b\n"));
+        assert!(
+            wat.contains("local.set $a\nbr 1\nelse\nlocal.get $b\nreturn\nend\nend\nunreachable\n")
+        );
+        // closures still go through the syntax tree
+        assert!(wat.contains("(func $counter.inc (param $__penv i32)"));
+    }
 }
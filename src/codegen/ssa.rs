@@ -0,0 +1,344 @@
+//! Lowers a function from the IR instead of the syntax tree.
+//!
+//! Values live in locals, `$__vN` after the first value in them, and parameters keep their
+//! names. Values that are never live at the same time share a local. The structured tree of
+//! the function gives the blocks, loops and ifs, the instructions of each block store their
+//! result in its local and the phis are set by the copies on the edges into their block.
+//! Every path ends in a `return`, or in a `return_call` for the calls in tail position when
+//! they're enabled.
+
+use crate::{
+    analysis::{
+        closure::FuncEnv,
+        liveness::{Coalesced, coalesce},
+        types::ValType,
+    },
+    codegen::{
+        ARRAY_SLOT_SIZE, Options, convert_wat, op_wat, push_teed,
+        strength::{Reduced, reduce},
+    },
+    ir::{
+        Function, InstKind, ValueId,
+        liveness::interference,
+        structure::{Structured, structure},
+    },
+    lexer::Token,
+};
+
+/// The WAT of a verified function and how many locals it saved
+pub fn func_wat(function: &Function, env: &FuncEnv, options: &Options) -> (String, Coalesced) {
+    let typed = |value: ValueId| function.values[value].ty.map(|ty| (value, ty));
+
+    let params: Vec<(ValueId, ValType)> = function
+        .params
+        .iter()
+        .filter_map(|param| typed(*param))
+        .collect();
+    let values: Vec<(ValueId, ValType)> = (0..function.values.len())
+        .filter(|value| !function.params.contains(value))
+        .filter_map(typed)
+        .collect();
+
+    let locals = values.len();
+    let slots = coalesce(params, values, &interference(function));
+
+    let mut lowering = Lowering {
+        function,
+        names: vec![String::new(); function.values.len()],
+        tail_calls: if options.tail_calls {
+            function.tail_calls()
+        } else {
+            Vec::new()
+        },
+        reduce_strength: options.opt_level >= 2,
+        tee_locals: options.opt_level >= 2,
+    };
+
+    for slot in &slots {
+        let name = match &function.values[slot.vars[0]].kind {
+            InstKind::Param(name) => name.clone(),
+            _ => format!("__v{}", slot.vars[0]),
+        };
+
+        for value in &slot.vars {
+            lowering.names[*value] = name.clone();
+        }
+    }
+
+    // parameters always have a type, they're the first locals
+    let params: String = slots[..function.params.len()]
+        .iter()
+        .map(|slot| {
+            format!(
+                "(param ${} {}) ",
+                lowering.names[slot.vars[0]],
+                slot.ty.as_str()
+            )
+        })
+        .collect();
+
+    let local_vars: String = slots[function.params.len()..]
+        .iter()
+        .map(|slot| {
+            format!(
+                "(local ${} {}) ",
+                lowering.names[slot.vars[0]],
+                slot.ty.as_str()
+            )
+        })
+        .collect();
+
+    let rt_type = function
+        .result
+        .map_or(String::new(), |ty| format!("(result {})", ty.as_str()));
+
+    let mut body = String::new();
+    lowering.tree_wat(&structure(function), &mut body);
+
+    // every path returns, the end of the body is never reached
+    if function.result.is_some() {
+        body.push_str("unreachable\n");
+    }
+
+    let mut wat = format!(
+        "(func ${} {} {} {}\n{})\n",
+        function.name, params, rt_type, local_vars, body
+    );
+
+    // lifted nested functions stay private to the module
+    if !env.lifted {
+        wat.push_str(&format!(
+            "(export \"{}\" (func ${}))\n",
+            function.name, function.name
+        ));
+    }
+
+    let coalesced = Coalesced {
+        func: function.name.clone(),
+        locals,
+        slots: slots.len() - function.params.len(),
+    };
+
+    (wat, coalesced)
+}
+
+struct Lowering<'a> {
+    function: &'a Function,
+    // the local of every value
+    names: Vec<String>,
+    // calls made with `return_call`, the return after them is left out
+    tail_calls: Vec<ValueId>,
+    // integer operations with a constant operand use cheaper instructions
+    reduce_strength: bool,
+    // values read right after they're stored stay on the stack, copies of a local to itself
+    // are left out
+    tee_locals: bool,
+}
+
+impl Lowering<'_> {
+    fn get(&self, value: ValueId) -> String {
+        format!("local.get ${}\n", self.names[value])
+    }
+
+    fn push_wat(&self, wat: &mut String, code: &str) {
+        if self.tee_locals {
+            push_teed(wat, code);
+        } else {
+            wat.push_str(code);
+        }
+    }
+
+    fn tree_wat(&self, tree: &[Structured], wat: &mut String) {
+        for node in tree {
+            match node {
+                Structured::Block(body) => {
+                    wat.push_str("block\n");
+                    self.tree_wat(body, wat);
+                    wat.push_str("end\n");
+                }
+                Structured::Loop(body) => {
+                    wat.push_str("loop\n");
+                    self.tree_wat(body, wat);
+                    wat.push_str("end\n");
+                }
+                Structured::If {
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    self.push_wat(wat, &self.get(*cond));
+                    wat.push_str("if\n");
+                    self.tree_wat(then, wat);
+                    wat.push_str("else\n");
+                    self.tree_wat(otherwise, wat);
+                    wat.push_str("end\n");
+                }
+                Structured::Code(block) => {
+                    for value in &self.function.blocks[*block].insts {
+                        self.push_wat(wat, &self.inst_wat(*value));
+                    }
+                }
+                Structured::Copies { from, to } => {
+                    // all the values are read before any phi is set, a phi can be the value
+                    // of another one
+                    let copies: Vec<(ValueId, ValueId)> = self
+                        .function
+                        .phis(*to)
+                        .filter_map(|phi| match &self.function.values[phi].kind {
+                            InstKind::Phi(operands) => operands
+                                .iter()
+                                .find(|(pred, _)| pred == from)
+                                .map(|(_, value)| (phi, *value)),
+                            _ => None,
+                        })
+                        .filter(|(phi, value)| self.names[*phi] != self.names[*value])
+                        .collect();
+
+                    for (_, value) in &copies {
+                        self.push_wat(wat, &self.get(*value));
+                    }
+
+                    for (phi, _) in copies.iter().rev() {
+                        wat.push_str(&format!("local.set ${}\n", self.names[*phi]));
+                    }
+                }
+                Structured::Br(depth) => wat.push_str(&format!("br {}\n", depth)),
+                Structured::Return(Some(value)) if self.tail_calls.contains(value) => (),
+                Structured::Return(value) => {
+                    if let Some(value) = value {
+                        self.push_wat(wat, &self.get(*value));
+                    }
+
+                    wat.push_str("return\n");
+                }
+            }
+        }
+    }
+
+    /// Computes `lhs op rhs` with cheaper instructions when one of them is a constant
+    fn reduced_wat(&self, op: &Token, lhs: ValueId, rhs: ValueId, ty: ValType) -> Option<String> {
+        if !self.reduce_strength {
+            return None;
+        }
+
+        let constant = |value: ValueId| match &self.function.values[value].kind {
+            InstKind::Const(text) => Some(text.as_str()),
+            _ => None,
+        };
+
+        let (reduced, other) = match (constant(lhs), constant(rhs)) {
+            (_, Some(text)) => (reduce(op, ty, text, true)?, lhs),
+            (Some(text), None) => (reduce(op, ty, text, false)?, rhs),
+            (None, None) => return None,
+        };
+
+        let wat = match reduced {
+            Reduced::Operand => self.get(other),
+            Reduced::Then(instructions) => self.get(other) + &instructions,
+            Reduced::Const(constant) => constant,
+        };
+
+        Some(wat)
+    }
+
+    /// Computes a value and stores it in its local
+    fn inst_wat(&self, value: ValueId) -> String {
+        let function = self.function;
+        let inst = &function.values[value];
+        let result_ty = inst.ty.unwrap_or(ValType::I32);
+
+        let mut wat = match &inst.kind {
+            // parameters are already in their locals, phis are set by the copies
+            InstKind::Param(_) | InstKind::Phi(_) => return String::new(),
+            InstKind::Const(text) => format!("{}.const {}\n", result_ty.as_str(), text),
+            // wasm has no f64 remainder, its `a - trunc(a / b) * b`
+            InstKind::Binary {
+                op: Token::Percent,
+                lhs,
+                rhs,
+            } if result_ty == ValType::F64 => format!(
+                "{0}{0}{1}f64.div\nf64.trunc\n{1}f64.mul\nf64.sub\n",
+                self.get(*lhs),
+                self.get(*rhs)
+            ),
+            InstKind::Binary { op, lhs, rhs } => {
+                // UNWRAP: the verifier checks the operands have a type
+                let operand_ty = function.values[*lhs].ty.unwrap();
+
+                if let Some(wat) = self.reduced_wat(op, *lhs, *rhs, operand_ty) {
+                    wat
+                } else {
+                    format!(
+                        "{}{}{}",
+                        self.get(*lhs),
+                        self.get(*rhs),
+                        op_wat(op.clone(), operand_ty)
+                    )
+                }
+            }
+            InstKind::Convert(operand) => format!(
+                "{}{}",
+                self.get(*operand),
+                // UNWRAP: the verifier checks the operands have a type
+                convert_wat(function.values[*operand].ty.unwrap(), result_ty)
+            ),
+            InstKind::Index { array, index } => format!(
+                "{}{}i32.const {}\ni32.shl\ni32.add\n{}.load offset={}\n",
+                self.get(*array),
+                self.get(*index),
+                ARRAY_SLOT_SIZE.trailing_zeros(),
+                result_ty.as_str(),
+                ARRAY_SLOT_SIZE
+            ),
+            InstKind::Length(array) => format!("{}i32.load\n", self.get(*array)),
+            InstKind::Call { func, args } => {
+                let mut wat: String = args.iter().map(|arg| self.get(*arg)).collect();
+
+                // the callee returns for this function, there's nothing to store
+                if self.tail_calls.contains(&value) {
+                    wat.push_str(&format!("return_call ${}\n", func));
+                    return wat;
+                }
+
+                wat.push_str(&format!("call ${}\n", func));
+
+                // nothing to store for a function without a result
+                if inst.ty.is_none() {
+                    return wat;
+                }
+
+                wat
+            }
+            // the array is a length followed by the elements
+            InstKind::Array { elements, element } => {
+                let mut wat = format!(
+                    "i32.const {}\ncall $__alloc\nlocal.tee ${}\ni32.const {}\ni32.store\n",
+                    (elements.len() + 1) * ARRAY_SLOT_SIZE,
+                    self.names[value],
+                    elements.len()
+                );
+
+                for (i, element_value) in elements.iter().enumerate() {
+                    wat.push_str(&self.get(value));
+                    wat.push_str(&self.get(*element_value));
+                    wat.push_str(&format!(
+                        "{}.store offset={}\n",
+                        element.as_str(),
+                        (i + 1) * ARRAY_SLOT_SIZE
+                    ));
+                }
+
+                return wat;
+            }
+        };
+
+        // a value that shares the local of the one it copies is already there
+        if self.tee_locals && wat == self.get(value) {
+            return String::new();
+        }
+
+        wat.push_str(&format!("local.set ${}\n", self.names[value]));
+
+        wat
+    }
+}
@@ -0,0 +1,112 @@
+//! Strength reduction.
+//!
+//! An integer operation with a constant operand can often be done with a cheaper instruction
+//! or none at all: `x * 8` is `x << 3`, `x % 8` is `x & 7` since the remainder is unsigned,
+//! `x + 0`, `x - 0` and `x * 1` are `x`, `x * 0` is `0` and `x == 0` is `eqz`. Integers wrap,
+//! so every rewrite gives the same bits as the instruction it replaces. Floats are left alone,
+//! `x + 0` is `0` for `-0` and `x * 0` is `NaN` for infinities.
+
+use crate::{analysis::types::ValType, lexer::Token};
+
+/// What a binary operation with a constant operand becomes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reduced {
+    /// the other operand as it is
+    Operand,
+    /// the other operand followed by these instructions instead of the constant and the
+    /// operation
+    Then(String),
+    /// this constant, the other operand isn't needed
+    Const(String),
+}
+
+/// How `op` can be done when the operand on the `rhs` side, or the other one, is `constant`
+pub fn reduce(op: &Token, ty: ValType, constant: &str, rhs: bool) -> Option<Reduced> {
+    let bits = match ty {
+        ValType::I32 => 32,
+        ValType::I64 => 64,
+        ValType::F64 => return None,
+    };
+
+    // the bits of the constant in the type, `-1` is all ones
+    let value = constant.parse::<i64>().ok()? as u64 & (u64::MAX >> (64 - bits));
+    let ty = ty.as_str();
+
+    let reduced = match op {
+        Token::Add if value == 0 => Reduced::Operand,
+        Token::Sub if value == 0 && rhs => Reduced::Operand,
+        Token::Mul if value == 0 => Reduced::Const(format!("{}.const 0\n", ty)),
+        Token::Mul if value == 1 => Reduced::Operand,
+        Token::Mul if value.is_power_of_two() => Reduced::Then(format!(
+            "{}.const {}\n{}.shl\n",
+            ty,
+            value.trailing_zeros(),
+            ty
+        )),
+        Token::Percent if value.is_power_of_two() && rhs => {
+            Reduced::Then(format!("{}.const {}\n{}.and\n", ty, value - 1, ty))
+        }
+        Token::DoubleEq if value == 0 => Reduced::Then(format!("{}.eqz\n", ty)),
+        _ => return None,
+    };
+
+    Some(reduced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn powers_of_two_become_shifts_and_masks() {
+        assert_eq!(
+            reduce(&Token::Mul, ValType::I32, "8", true),
+            Some(Reduced::Then("i32.const 3\ni32.shl\n".to_string()))
+        );
+        assert_eq!(
+            reduce(&Token::Mul, ValType::I64, "4", false),
+            Some(Reduced::Then("i64.const 2\ni64.shl\n".to_string()))
+        );
+        assert_eq!(
+            reduce(&Token::Percent, ValType::I32, "16", true),
+            Some(Reduced::Then("i32.const 15\ni32.and\n".to_string()))
+        );
+        // `16 % x` isn't a mask
+        assert_eq!(reduce(&Token::Percent, ValType::I32, "16", false), None);
+        assert_eq!(reduce(&Token::Mul, ValType::I32, "6", true), None);
+        // 2^31 is the sign bit of an i32, the shift still gives the same bits
+        assert_eq!(
+            reduce(&Token::Mul, ValType::I32, "-2147483648", true),
+            Some(Reduced::Then("i32.const 31\ni32.shl\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn identities_and_zero() {
+        assert_eq!(
+            reduce(&Token::Add, ValType::I32, "0", false),
+            Some(Reduced::Operand)
+        );
+        assert_eq!(
+            reduce(&Token::Sub, ValType::I32, "0", true),
+            Some(Reduced::Operand)
+        );
+        // `0 - x` is the negation
+        assert_eq!(reduce(&Token::Sub, ValType::I32, "0", false), None);
+        assert_eq!(
+            reduce(&Token::Mul, ValType::I64, "1", true),
+            Some(Reduced::Operand)
+        );
+        assert_eq!(
+            reduce(&Token::Mul, ValType::I32, "0", true),
+            Some(Reduced::Const("i32.const 0\n".to_string()))
+        );
+        assert_eq!(
+            reduce(&Token::DoubleEq, ValType::I64, "0", false),
+            Some(Reduced::Then("i64.eqz\n".to_string()))
+        );
+        // -0 + 0 is 0, NaN * 0 is NaN
+        assert_eq!(reduce(&Token::Add, ValType::F64, "0", true), None);
+        assert_eq!(reduce(&Token::Mul, ValType::F64, "0", true), None);
+    }
+}
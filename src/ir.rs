@@ -0,0 +1,299 @@
+//! Mid-level IR in SSA form.
+//!
+//! A function is a list of basic blocks, every block holds instructions and ends in a
+//! terminator. Every instruction defines one value, numbered `%n` across the function, and a
+//! value is never assigned again: a variable that's assigned on several paths gets a phi at
+//! the block where the paths meet, which picks the value of the predecessor control came
+//! from. Values are typed with the inferred WASM types, conversions are explicit.
+//!
+//! The IR is built from the control-flow graph of a closure converted function
+//! ([`build`]), checked by [`verify`], printed by [`printer`] and turned back into structured
+//! WASM control flow, blocks, loops and ifs, by [`structure`].
+
+use crate::{
+    analysis::{
+        dominators::DomTree,
+        types::{ValType, is_comparison},
+    },
+    lexer::Token,
+};
+
+pub mod build;
+pub mod gvn;
+pub mod inline;
+pub mod liveness;
+pub mod printer;
+pub mod structure;
+pub mod tail_recursion;
+pub mod verify;
+
+pub type ValueId = usize;
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InstKind {
+    /// the parameter with this name, parameters are the first values of the entry block
+    Param(String),
+    /// a number literal of the instruction's type
+    Const(String),
+    /// both operands have the same type, comparisons give an `i32`
+    Binary {
+        op: Token,
+        lhs: ValueId,
+        rhs: ValueId,
+    },
+    /// converts to the instruction's type
+    Convert(ValueId),
+    /// an element of a rest parameter array
+    Index {
+        array: ValueId,
+        index: ValueId,
+    },
+    /// the length of a rest parameter array
+    Length(ValueId),
+    Call {
+        func: String,
+        args: Vec<ValueId>,
+    },
+    /// allocates a rest parameter array with the elements, it's a pointer
+    Array {
+        elements: Vec<ValueId>,
+        element: ValType,
+    },
+    /// the value coming from each predecessor, phis come first in their block
+    Phi(Vec<(BlockId, ValueId)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Inst {
+    pub kind: InstKind,
+    // `None` for calls of functions without a return value
+    pub ty: Option<ValType>,
+}
+
+impl Inst {
+    /// The values the instruction reads
+    pub fn operands(&self) -> Vec<ValueId> {
+        match &self.kind {
+            InstKind::Param(_) | InstKind::Const(_) => Vec::new(),
+            InstKind::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            InstKind::Convert(value) | InstKind::Length(value) => vec![*value],
+            InstKind::Index { array, index } => vec![*array, *index],
+            InstKind::Call { args, .. } => args.clone(),
+            InstKind::Array { elements, .. } => elements.clone(),
+            InstKind::Phi(operands) => operands.iter().map(|(_, value)| *value).collect(),
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match &mut self.kind {
+            InstKind::Param(_) | InstKind::Const(_) => Vec::new(),
+            InstKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            InstKind::Convert(value) | InstKind::Length(value) => vec![value],
+            InstKind::Index { array, index } => vec![array, index],
+            InstKind::Call { args, .. } => args.iter_mut().collect(),
+            InstKind::Array { elements, .. } => elements.iter_mut().collect(),
+            InstKind::Phi(operands) => operands.iter_mut().map(|(_, value)| value).collect(),
+        }
+    }
+
+    pub fn is_phi(&self) -> bool {
+        matches!(self.kind, InstKind::Phi(_))
+    }
+
+    /// The type a binary instruction gives for operands of type `operand`
+    pub fn binary_type(op: &Token, operand: ValType) -> ValType {
+        if is_comparison(op) {
+            ValType::I32
+        } else {
+            operand
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// goes to `then` when `cond`, an `i32`, isn't 0
+    Branch {
+        cond: ValueId,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return(Option<ValueId>),
+}
+
+impl Terminator {
+    pub fn succs(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<ValueId> {
+        match self {
+            Terminator::Jump(_) | Terminator::Return(None) => Vec::new(),
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::Return(Some(value)) => vec![*value],
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match self {
+            Terminator::Jump(_) | Terminator::Return(None) => Vec::new(),
+            Terminator::Branch { cond, .. } => vec![cond],
+            Terminator::Return(Some(value)) => vec![value],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub insts: Vec<ValueId>,
+    pub terminator: Terminator,
+    pub preds: Vec<BlockId>,
+    pub succs: Vec<BlockId>,
+}
+
+/// A function in SSA form, block 0 is the entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// the `Param` values in parameter order
+    pub params: Vec<ValueId>,
+    pub result: Option<ValType>,
+    pub values: Vec<Inst>,
+    pub blocks: Vec<Block>,
+}
+
+impl Function {
+    pub fn dominators(&self) -> DomTree {
+        DomTree::new(
+            0,
+            self.blocks.len(),
+            |block| &self.blocks[block].succs,
+            |block| &self.blocks[block].preds,
+        )
+    }
+
+    /// The phis at the start of a block
+    pub fn phis(&self, block: BlockId) -> impl Iterator<Item = ValueId> + '_ {
+        self.blocks[block]
+            .insts
+            .iter()
+            .copied()
+            .take_while(|value| self.values[*value].is_phi())
+    }
+
+    /// The calls whose result is returned right after them, the IR converts results
+    /// explicitly so the types always match
+    pub fn tail_calls(&self) -> Vec<ValueId> {
+        self.blocks
+            .iter()
+            .filter_map(|block| match (&block.terminator, block.insts.last()) {
+                (Terminator::Return(Some(value)), Some(last))
+                    if value == last
+                        && matches!(self.values[*value].kind, InstKind::Call { .. }) =>
+                {
+                    Some(*value)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Fills in the predecessors and successors from the terminators
+    fn link(&mut self) {
+        for block in &mut self.blocks {
+            block.preds.clear();
+        }
+
+        for block in 0..self.blocks.len() {
+            let succs = self.blocks[block].terminator.succs();
+
+            for succ in &succs {
+                self.blocks[*succ].preds.push(block);
+            }
+
+            self.blocks[block].succs = succs;
+        }
+    }
+
+    /// Replaces the phis that pick the same value from every predecessor, or themselves, by
+    /// that value until there are none left
+    fn remove_trivial_phis(&mut self) {
+        loop {
+            let trivial = self.blocks.iter().enumerate().find_map(|(block, _)| {
+                self.phis(block).find_map(|phi| {
+                    let InstKind::Phi(operands) = &self.values[phi].kind else {
+                        return None;
+                    };
+
+                    let mut same = operands
+                        .iter()
+                        .map(|(_, value)| *value)
+                        .filter(|value| *value != phi);
+
+                    let first = same.next()?;
+
+                    same.all(|value| value == first)
+                        .then_some((block, phi, first))
+                })
+            });
+
+            let Some((block, phi, value)) = trivial else {
+                break;
+            };
+
+            self.blocks[block].insts.retain(|inst| *inst != phi);
+            self.replace_uses(|operand| if operand == phi { value } else { operand });
+        }
+    }
+
+    /// Numbers the values in the order they're defined and drops the ones no block holds
+    fn renumber(&mut self) {
+        let mut numbers = vec![None; self.values.len()];
+        let mut values = Vec::new();
+
+        for block in &self.blocks {
+            for value in &block.insts {
+                numbers[*value] = Some(values.len());
+                values.push(self.values[*value].clone());
+            }
+        }
+
+        self.values = values;
+
+        // UNWRAP: every use is of a value some block holds
+        self.replace_uses(|value| numbers[value].unwrap());
+
+        for block in &mut self.blocks {
+            for value in &mut block.insts {
+                *value = numbers[*value].unwrap();
+            }
+        }
+
+        for param in &mut self.params {
+            *param = numbers[*param].unwrap();
+        }
+    }
+
+    /// Rewrites every use of a value through `map`
+    fn replace_uses(&mut self, map: impl Fn(ValueId) -> ValueId) {
+        for inst in &mut self.values {
+            for operand in inst.operands_mut() {
+                *operand = map(*operand);
+            }
+        }
+
+        for block in &mut self.blocks {
+            for operand in block.terminator.operands_mut() {
+                *operand = map(*operand);
+            }
+        }
+    }
+}
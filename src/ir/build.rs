@@ -0,0 +1,673 @@
+//! SSA construction.
+//!
+//! Follows "Simple and Efficient Construction of Static Single Assignment Form" by Braun et
+//! al.: the blocks of the control-flow graph are filled in reverse postorder and a variable
+//! read in a block that doesn't assign it asks the predecessors for their value. A block is
+//! sealed once all of its predecessors are filled in, reads in a loop header before that get
+//! a phi whose operands are added when the back edge is done. Phis that only ever pick one
+//! value are replaced by it at the end.
+//!
+//! Expressions are lowered the way codegen pushes them: operands are converted to the type of
+//! their binary stmt, values to the type of the variable they're assigned and arguments to
+//! the type of their parameter.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::{
+    analysis::{
+        cfg::{self, Cfg},
+        closure::FuncEnv,
+        dominators::postorder,
+        types::{Types, ValType},
+    },
+    ir::{Block, BlockId, Function, Inst, InstKind, Terminator, ValueId},
+    lexer::Token,
+    parser::{
+        BinaryStmtBody, FuncCallStmt, FuncDeclBody, Operand, Param, ReturnStmt, Stmt, is_number,
+    },
+};
+
+/// What the builder needs to know about the rest of the module
+pub struct Context<'a> {
+    pub types: &'a Types,
+    // functions that leave a value on the stack
+    pub returns_value: &'a HashSet<String>,
+    // parameters of every function in the module
+    pub signatures: &'a HashMap<String, Vec<Param>>,
+}
+
+/// A function the IR can't express yet, codegen compiles it from the syntax tree instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported(pub String);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The SSA form of a closure converted function
+pub fn build(func: &FuncDeclBody, env: &FuncEnv, ctx: &Context) -> Result<Function, Unsupported> {
+    if env.has_env || env.parent_env || !env.outer.is_empty() || !env.closure_calls.is_empty() {
+        return Err(Unsupported("uses a closure environment".to_string()));
+    }
+
+    let cfg = Cfg::build(&func.func_name, func);
+
+    let mut builder = Builder::new(&cfg, func, env, ctx);
+    builder.fill()?;
+
+    let mut function = builder.function;
+    function.link();
+    function.remove_trivial_phis();
+    function.renumber();
+
+    Ok(function)
+}
+
+struct Builder<'a> {
+    ctx: &'a Context<'a>,
+    env: &'a FuncEnv,
+    cfg: &'a Cfg,
+    function: Function,
+    // IR block of every reachable CFG block, the exit isn't one, returns end their own block
+    ids: Vec<Option<BlockId>>,
+    // the CFG block of every IR block
+    cfg_ids: Vec<cfg::BlockId>,
+    // value of every variable at the end of a block, as far as it's filled in
+    defs: Vec<HashMap<String, ValueId>>,
+    filled: Vec<bool>,
+    sealed: Vec<bool>,
+    // phis of unsealed blocks that still need their operands
+    incomplete: Vec<Vec<(String, ValueId)>>,
+    // the zero read from a variable before it's assigned, one per type
+    zeros: HashMap<ValType, ValueId>,
+    // the block instructions are added to
+    current: BlockId,
+}
+
+impl<'a> Builder<'a> {
+    fn new(cfg: &'a Cfg, func: &FuncDeclBody, env: &'a FuncEnv, ctx: &'a Context) -> Builder<'a> {
+        // the last successor is visited first, so a branch target comes before the code
+        // after the branch
+        let reversed: Vec<Vec<cfg::BlockId>> = cfg
+            .blocks
+            .iter()
+            .map(|block| block.succs.iter().rev().copied().collect())
+            .collect();
+
+        let mut order = postorder(cfg.entry, cfg.blocks.len(), &|block| {
+            reversed[block].as_slice()
+        });
+        order.reverse();
+
+        let mut ids = vec![None; cfg.blocks.len()];
+        let mut cfg_ids = Vec::new();
+
+        // reverse postorder keeps the entry first, the exit is left out
+        for block in order.into_iter().filter(|block| *block != cfg.exit) {
+            ids[block] = Some(cfg_ids.len());
+            cfg_ids.push(block);
+        }
+
+        let result = func
+            .return_value
+            .as_ref()
+            .map(|_| ctx.types.result(&func.func_name));
+
+        let blocks = cfg_ids
+            .iter()
+            .map(|_| Block {
+                insts: Vec::new(),
+                terminator: Terminator::Return(None),
+                preds: Vec::new(),
+                succs: Vec::new(),
+            })
+            .collect();
+
+        let len = cfg_ids.len();
+
+        let mut builder = Builder {
+            ctx,
+            env,
+            cfg,
+            function: Function {
+                name: func.func_name.clone(),
+                params: Vec::new(),
+                result,
+                values: Vec::new(),
+                blocks,
+            },
+            ids,
+            cfg_ids,
+            defs: vec![HashMap::new(); len],
+            filled: vec![false; len],
+            sealed: vec![false; len],
+            incomplete: vec![Vec::new(); len],
+            zeros: HashMap::new(),
+            current: 0,
+        };
+
+        // the edges are known up front, only the terminators are missing, the predecessors
+        // are in the order `Function::link` puts them in
+        for block in 0..len {
+            for succ in &cfg.blocks[builder.cfg_ids[block]].succs {
+                if let Some(succ) = builder.ids[*succ] {
+                    builder.function.blocks[succ].preds.push(block);
+                }
+            }
+        }
+
+        for param in &func.arguments {
+            let ty = if param.rest {
+                ValType::I32
+            } else {
+                builder.var_type(&param.name)
+            };
+
+            let value = builder.push(InstKind::Param(param.name.clone()), Some(ty));
+            builder.function.params.push(value);
+            builder.defs[0].insert(param.name.clone(), value);
+        }
+
+        builder
+    }
+
+    /// Fills in every block in reverse postorder
+    fn fill(&mut self) -> Result<(), Unsupported> {
+        for block in 0..self.function.blocks.len() {
+            self.current = block;
+            self.seal_ready();
+
+            let cfg_block = &self.cfg.blocks[self.cfg_ids[block]];
+
+            for stmt in &cfg_block.stmts {
+                self.stmt(stmt)?;
+            }
+
+            let terminator = match &cfg_block.terminator {
+                cfg::Terminator::Goto(target) => Terminator::Jump(self.id(*target)),
+                cfg::Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => Terminator::Branch {
+                    cond: self.condition(condition)?,
+                    then: self.id(*then),
+                    otherwise: self.id(*otherwise),
+                },
+                cfg::Terminator::Return(rt_val) => Terminator::Return(self.return_value(rt_val)?),
+                cfg::Terminator::Exit => unreachable!("the exit block isn't part of the IR"),
+            };
+
+            self.function.blocks[block].terminator = terminator;
+            self.filled[block] = true;
+        }
+
+        self.seal_ready();
+
+        Ok(())
+    }
+
+    fn id(&self, block: cfg::BlockId) -> BlockId {
+        // UNWRAP: a successor of a reachable block is reachable
+        self.ids[block].unwrap()
+    }
+
+    /// Seals the blocks whose predecessors are all filled in
+    fn seal_ready(&mut self) {
+        for block in 0..self.function.blocks.len() {
+            if !self.sealed[block]
+                && self.function.blocks[block]
+                    .preds
+                    .iter()
+                    .all(|pred| self.filled[*pred])
+            {
+                self.sealed[block] = true;
+
+                for (var, phi) in std::mem::take(&mut self.incomplete[block]) {
+                    self.add_phi_operands(&var, phi, block);
+                }
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), Unsupported> {
+        match stmt {
+            Stmt::LetStmt(let_stmt) | Stmt::RassignStmt(let_stmt) => {
+                let value = self.value(&let_stmt.value, self.var_type(&let_stmt.var_name))?;
+                self.defs[self.current].insert(let_stmt.var_name.clone(), value);
+            }
+            Stmt::FuncCall(call) => {
+                self.call(call)?;
+            }
+            Stmt::BinaryStmt(binary_stmt) => {
+                self.binary(binary_stmt)?;
+            }
+            _ => unreachable!("control flow is in the terminators"),
+        }
+
+        Ok(())
+    }
+
+    fn return_value(
+        &mut self,
+        rt_val: &Option<ReturnStmt>,
+    ) -> Result<Option<ValueId>, Unsupported> {
+        let Some(rt_val) = rt_val else {
+            return Ok(None);
+        };
+
+        let Some(ty) = self.function.result else {
+            return Err(Unsupported(
+                "returns a value from a function without a result".to_string(),
+            ));
+        };
+
+        let value = match rt_val {
+            ReturnStmt::BinaryStmtBody(binary_stmt) => self.value(binary_stmt, ty)?,
+            ReturnStmt::FuncCallStmt(call) => {
+                let value = self.call_value(call)?;
+                self.convert(value, ty)
+            }
+        };
+
+        Ok(Some(value))
+    }
+
+    fn var_type(&self, var: &str) -> ValType {
+        self.ctx.types.var(&self.function.name, self.env, var)
+    }
+
+    /// Adds an instruction to the current block
+    fn push(&mut self, kind: InstKind, ty: Option<ValType>) -> ValueId {
+        let value = self.new_value(kind, ty);
+        self.function.blocks[self.current].insts.push(value);
+
+        value
+    }
+
+    fn new_value(&mut self, kind: InstKind, ty: Option<ValType>) -> ValueId {
+        self.function.values.push(Inst { kind, ty });
+
+        self.function.values.len() - 1
+    }
+
+    fn ty(&self, value: ValueId) -> ValType {
+        // UNWRAP: only calls have no type and those are checked before they're used
+        self.function.values[value].ty.unwrap()
+    }
+
+    /// A binary stmt converted to `ty`
+    fn value(&mut self, stmt: &BinaryStmtBody, ty: ValType) -> Result<ValueId, Unsupported> {
+        // a single operand is made a `ty` directly, like codegen pushes `f64.const 1`
+        if stmt.op.is_none() {
+            return self.operand(&stmt.lhs, ty);
+        }
+
+        let value = self.binary(stmt)?;
+
+        Ok(self.convert(value, ty))
+    }
+
+    /// A binary stmt with the type inferred for it, both operands are converted to a common
+    /// type first
+    fn binary(&mut self, stmt: &BinaryStmtBody) -> Result<ValueId, Unsupported> {
+        let ty = self.ctx.types.operands(&self.function.name, self.env, stmt);
+
+        let lhs = self.operand(&stmt.lhs, ty)?;
+
+        let (Some(rhs), Some(op)) = (&stmt.rhs, &stmt.op) else {
+            return Ok(lhs);
+        };
+
+        let rhs = self.operand(rhs, ty)?;
+
+        Ok(self.push(
+            InstKind::Binary {
+                op: op.clone(),
+                lhs,
+                rhs,
+            },
+            Some(Inst::binary_type(op, ty)),
+        ))
+    }
+
+    /// A condition as an `i32`, a number other than 0 is true
+    fn condition(&mut self, stmt: &BinaryStmtBody) -> Result<ValueId, Unsupported> {
+        let value = self.binary(stmt)?;
+        let ty = self.ty(value);
+
+        if ty == ValType::I32 {
+            return Ok(value);
+        }
+
+        let zero = self.push(InstKind::Const("0".to_string()), Some(ty));
+
+        Ok(self.push(
+            InstKind::Binary {
+                op: Token::NotEq,
+                lhs: value,
+                rhs: zero,
+            },
+            Some(ValType::I32),
+        ))
+    }
+
+    fn convert(&mut self, value: ValueId, ty: ValType) -> ValueId {
+        if self.ty(value) == ty {
+            value
+        } else {
+            self.push(InstKind::Convert(value), Some(ty))
+        }
+    }
+
+    /// An operand converted to `ty`
+    fn operand(&mut self, operand: &Operand, ty: ValType) -> Result<ValueId, Unsupported> {
+        let value = match operand {
+            Operand::Literal(text, _) if is_number(text) => {
+                let natural = ValType::of_number(text);
+                // a number that fits in `ty` doesn't need converting
+                let const_ty = if natural.join(ty) == ty { ty } else { natural };
+
+                self.push(InstKind::Const(text.clone()), Some(const_ty))
+            }
+            Operand::Literal(var, _) => self.read(var, self.current),
+            Operand::Index(array, index, _) => {
+                let natural = self
+                    .ctx
+                    .types
+                    .operand(&self.function.name, self.env, operand);
+                let array = self.read(array, self.current);
+                let index = self.value(index, ValType::I32)?;
+
+                self.push(InstKind::Index { array, index }, Some(natural))
+            }
+            Operand::Length(array, _) => {
+                let array = self.read(array, self.current);
+
+                self.push(InstKind::Length(array), Some(ValType::I32))
+            }
+            Operand::Call(call) => self.call_value(call)?,
+            Operand::Bool(value) => {
+                self.push(InstKind::Const((*value as i32).to_string()), Some(ty))
+            }
+            Operand::Null | Operand::Undefined => {
+                self.push(InstKind::Const("0".to_string()), Some(ty))
+            }
+        };
+
+        Ok(self.convert(value, ty))
+    }
+
+    /// A call whose result is used
+    fn call_value(&mut self, call: &FuncCallStmt) -> Result<ValueId, Unsupported> {
+        if !self.ctx.returns_value.contains(&call.function_name) {
+            return Err(Unsupported(format!(
+                "uses the result of `{}`, which doesn't return a value",
+                call.function_name
+            )));
+        }
+
+        self.call(call)
+    }
+
+    fn call(&mut self, call: &FuncCallStmt) -> Result<ValueId, Unsupported> {
+        let FuncCallStmt {
+            function_name,
+            arguments,
+            ..
+        } = call;
+
+        let ty = self
+            .ctx
+            .returns_value
+            .contains(function_name)
+            .then(|| self.ctx.types.result(function_name));
+
+        let Some(params) = self.ctx.signatures.get(function_name) else {
+            let args = arguments
+                .iter()
+                .map(|arg| self.argument(arg, ValType::I32))
+                .collect::<Result<_, _>>()?;
+
+            return Ok(self.push(
+                InstKind::Call {
+                    func: function_name.clone(),
+                    args,
+                },
+                ty,
+            ));
+        };
+
+        let regular: Vec<&Param> = params.iter().filter(|param| !param.rest).collect();
+
+        if arguments.len() < regular.len() {
+            return Err(Unsupported(format!(
+                "calls `{}` with default arguments",
+                function_name
+            )));
+        }
+
+        let mut args = Vec::new();
+
+        for (param, arg) in regular.iter().zip(arguments) {
+            let param_ty = self.ctx.types.param(function_name, param);
+            args.push(self.argument(arg, param_ty)?);
+        }
+
+        let extra = &arguments[regular.len()..];
+
+        if let Some(rest) = params.iter().find(|param| param.rest) {
+            let element = self.ctx.types.param(function_name, rest);
+
+            let elements = extra
+                .iter()
+                .map(|arg| self.argument(arg, element))
+                .collect::<Result<_, _>>()?;
+
+            args.push(self.push(InstKind::Array { elements, element }, Some(ValType::I32)));
+        } else {
+            // extra arguments are still evaluated, the function just never sees them
+            for arg in extra {
+                self.argument(arg, ValType::I32)?;
+            }
+        }
+
+        Ok(self.push(
+            InstKind::Call {
+                func: function_name.clone(),
+                args,
+            },
+            ty,
+        ))
+    }
+
+    /// An argument converted to the type of the parameter it's passed as
+    fn argument(&mut self, arg: &Stmt, ty: ValType) -> Result<ValueId, Unsupported> {
+        match arg {
+            Stmt::BinaryStmt(binary_stmt) => self.value(binary_stmt, ty),
+            Stmt::FuncCall(call) => {
+                let value = self.call_value(call)?;
+                Ok(self.convert(value, ty))
+            }
+            _ => unreachable!("arguments are expressions"),
+        }
+    }
+
+    /// The value of a variable at the end of `block`, or where the block is filled up to
+    fn read(&mut self, var: &str, block: BlockId) -> ValueId {
+        if let Some(value) = self.defs[block].get(var) {
+            return *value;
+        }
+
+        let preds = self.function.blocks[block].preds.clone();
+
+        let value = if !self.sealed[block] {
+            // a loop header, the back edge isn't filled in yet
+            let phi = self.new_phi(var, block);
+            self.incomplete[block].push((var.to_string(), phi));
+
+            phi
+        } else if preds.is_empty() {
+            // locals start out as 0
+            self.zero(self.var_type(var))
+        } else if let [pred] = preds[..] {
+            self.read(var, pred)
+        } else {
+            // the phi is the value while the predecessors are read, that ends loops
+            let phi = self.new_phi(var, block);
+            self.defs[block].insert(var.to_string(), phi);
+            self.add_phi_operands(var, phi, block);
+
+            phi
+        };
+
+        self.defs[block].insert(var.to_string(), value);
+
+        value
+    }
+
+    fn new_phi(&mut self, var: &str, block: BlockId) -> ValueId {
+        let phi = self.new_value(InstKind::Phi(Vec::new()), Some(self.var_type(var)));
+
+        let at = self.function.phis(block).count();
+        self.function.blocks[block].insts.insert(at, phi);
+
+        phi
+    }
+
+    fn add_phi_operands(&mut self, var: &str, phi: ValueId, block: BlockId) {
+        let mut operands = Vec::new();
+
+        for pred in self.function.blocks[block].preds.clone() {
+            operands.push((pred, self.read(var, pred)));
+        }
+
+        self.function.values[phi].kind = InstKind::Phi(operands);
+    }
+
+    /// A zero constant at the start of the function, after the parameters
+    fn zero(&mut self, ty: ValType) -> ValueId {
+        if let Some(zero) = self.zeros.get(&ty) {
+            return *zero;
+        }
+
+        let zero = self.new_value(InstKind::Const("0".to_string()), Some(ty));
+
+        let at = self.function.params.len() + self.zeros.len();
+        self.function.blocks[0].insts.insert(at, zero);
+        self.zeros.insert(ty, zero);
+
+        zero
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::{Options, ir_functions},
+        lexer::lex,
+        parser::parse,
+    };
+
+    fn ir(src: &str) -> Function {
+        let body = parse(lex(src.to_string())).unwrap().body;
+
+        ir_functions(body, &Options::default()).remove(0).1.unwrap()
+    }
+
+    fn phi(function: &Function, block: BlockId) -> Vec<(BlockId, ValueId)> {
+        let phis: Vec<ValueId> = function.phis(block).collect();
+
+        let [phi] = phis[..] else {
+            panic!("expected one phi in bb{}", block);
+        };
+
+        let InstKind::Phi(operands) = &function.values[phi].kind else {
+            unreachable!()
+        };
+
+        operands.clone()
+    }
+
+    #[test]
+    fn phi_where_an_if_rejoins() {
+        let function =
+            ir("function f(a) {\n\tlet x = 1;\n\tif (a == 0) {\n\t\tx = 2;\n\t}\n\treturn x;\n}\n");
+
+        // bb1 is the if block, bb2 the code after it
+        assert_eq!(function.blocks[2].preds, [0, 1]);
+        assert_eq!(phi(&function, 2), [(0, 1), (1, 4)]);
+        assert_eq!(function.values[1].kind, InstKind::Const("1".to_string()));
+        assert_eq!(function.values[4].kind, InstKind::Const("2".to_string()));
+        assert_eq!(function.blocks[2].terminator, Terminator::Return(Some(5)));
+    }
+
+    #[test]
+    fn phi_at_loop_header_with_the_variable_type() {
+        let function = ir(
+            "function g(n) {\n\tlet s = 0;\n\twhile (s < n) {\n\t\ts = s + 1.5;\n\t}\n\treturn s;\n}\n",
+        );
+
+        // bb1 is the header, bb2 the body
+        assert_eq!(phi(&function, 1), [(0, 1), (2, 6)]);
+        assert_eq!(function.values[1].ty, Some(ValType::F64));
+        assert_eq!(function.values[2].ty, Some(ValType::F64));
+        // the parameter is converted to compare it with the f64
+        assert_eq!(function.values[3].kind, InstKind::Convert(0));
+    }
+
+    #[test]
+    fn no_phi_for_variables_a_loop_doesnt_assign() {
+        let function = ir(
+            "function f(a, n) {\n\tlet i = 0;\n\twhile (i < n) {\n\t\ti = i + a;\n\t}\n\treturn i + a;\n}\n",
+        );
+
+        assert_eq!(function.phis(1).count(), 1);
+        assert_eq!(
+            function.values[function.values.len() - 1].kind,
+            InstKind::Binary {
+                op: Token::Add,
+                lhs: function.phis(1).next().unwrap(),
+                rhs: 0
+            }
+        );
+    }
+
+    #[test]
+    fn unsupported_functions() {
+        let body = parse(lex(
+            "function f(a, b = 1) {\n\treturn a + b;\n}\n\nfunction g(a) {\n\tfunction h() {\n\t\treturn a;\n\t}\n\treturn h();\n}\n\nfunction k(a) {\n\treturn f(a);\n}\n".to_string(),
+        ))
+        .unwrap()
+        .body;
+
+        let functions: Vec<(String, Result<(), Unsupported>)> =
+            ir_functions(body, &Options::default())
+                .into_iter()
+                .map(|(name, function)| (name, function.map(|_| ())))
+                .collect();
+
+        assert_eq!(
+            functions,
+            [
+                ("f".to_string(), Ok(())),
+                (
+                    "g".to_string(),
+                    Err(Unsupported("uses a closure environment".to_string()))
+                ),
+                (
+                    "g.h".to_string(),
+                    Err(Unsupported("uses a closure environment".to_string()))
+                ),
+                (
+                    "k".to_string(),
+                    Err(Unsupported("calls `f` with default arguments".to_string()))
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Global value numbering.
+//!
+//! Two instructions that do the same thing with the same operands give the same value, so the
+//! second one can use the value of the first instead when the first one dominates it, it has
+//! always run by then. The blocks are walked down the dominator tree with a table of the
+//! instructions seen on the way, the table of a block is what its dominators computed.
+//! Operands are replaced by the value they were numbered with before an instruction is looked
+//! up, so `a % b` and the `a % b` computed from copies of `a` and `b` match too, and the
+//! operands of `a + b` and `b + a` are put in order.
+//!
+//! Only instructions without effects are numbered. One that can trap, a remainder by 0 or a
+//! read past the end of an array, has already trapped at the instruction it's replaced with.
+//! A call is numbered when the function is pure, the first call returned and the second one
+//! would return the same. Other calls and array allocations are always made.
+
+use std::collections::HashMap;
+
+use crate::{
+    analysis::{
+        dominators::DomTree,
+        effects::{Effect, Effects},
+    },
+    ir::{BlockId, Function, Inst, InstKind, ValueId},
+    lexer::Token,
+};
+
+/// Replaces the instructions that compute a value a dominating instruction already has
+pub fn number_values(function: &mut Function, effects: &Effects) {
+    let mut numbering = Numbering {
+        effects,
+        leaders: (0..function.values.len()).collect(),
+        table: HashMap::new(),
+    };
+
+    numbering.block(function, &function.dominators(), 0);
+
+    let leaders = numbering.leaders;
+
+    for block in &mut function.blocks {
+        block.insts.retain(|value| leaders[*value] == *value);
+    }
+
+    function.replace_uses(|value| leaders[value]);
+    function.renumber();
+}
+
+struct Numbering<'a> {
+    effects: &'a Effects,
+    // the value every value is replaced by, itself when it stays
+    leaders: Vec<ValueId>,
+    // the instructions of the dominating blocks, with their operands numbered
+    table: HashMap<Inst, ValueId>,
+}
+
+impl Numbering<'_> {
+    fn block(&mut self, function: &Function, doms: &DomTree, block: BlockId) {
+        let mut added = Vec::new();
+
+        for value in &function.blocks[block].insts {
+            let Some(key) = self.key(&function.values[*value]) else {
+                continue;
+            };
+
+            match self.table.get(&key) {
+                Some(leader) => self.leaders[*value] = *leader,
+                None => {
+                    self.table.insert(key.clone(), *value);
+                    added.push(key);
+                }
+            }
+        }
+
+        for child in doms.children(block) {
+            self.block(function, doms, child);
+        }
+
+        // the blocks that aren't dominated by this one don't see its values
+        for key in added {
+            self.table.remove(&key);
+        }
+    }
+
+    /// The instruction with its operands numbered, `None` when it has an effect
+    fn key(&self, inst: &Inst) -> Option<Inst> {
+        let numbered = match &inst.kind {
+            InstKind::Const(_)
+            | InstKind::Binary { .. }
+            | InstKind::Convert(_)
+            | InstKind::Index { .. }
+            | InstKind::Length(_) => true,
+            InstKind::Call { func, .. } => self
+                .effects
+                .get(func)
+                .is_some_and(|effect| effect.effect == Effect::Pure),
+            InstKind::Param(_) | InstKind::Array { .. } | InstKind::Phi(_) => false,
+        };
+
+        if !numbered {
+            return None;
+        }
+
+        let mut key = inst.clone();
+
+        for operand in key.operands_mut() {
+            *operand = self.leaders[*operand];
+        }
+
+        if let InstKind::Binary { op, lhs, rhs } = &mut key.kind
+            && matches!(op, Token::Add | Token::Mul | Token::DoubleEq | Token::NotEq)
+            && lhs > rhs
+        {
+            std::mem::swap(lhs, rhs);
+        }
+
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::effects::FuncEffect,
+        codegen::{Options, ir_functions},
+        ir::verify::verify,
+        lexer::lex,
+        parser::parse,
+    };
+
+    fn numbered(src: &str) -> Function {
+        numbered_with(src, &Effects::new())
+    }
+
+    fn numbered_with(src: &str, effects: &Effects) -> Function {
+        let body = parse(lex(src.to_string())).unwrap().body;
+        let mut function = ir_functions(body, &Options::default()).remove(0).1.unwrap();
+
+        number_values(&mut function, effects);
+        assert_eq!(verify(&function), Ok(()));
+
+        function
+    }
+
+    #[test]
+    fn repeated_expressions_are_computed_once() {
+        let function = numbered(
+            "function f(a, b) {\n\tlet x = a % b;\n\tlet y = a % b;\n\tlet z = b * a;\n\tlet w = a * b;\n\tlet s = x + y;\n\tlet t = z + w;\n\treturn s + t;\n}\n",
+        );
+
+        assert_eq!(
+            function.to_string(),
+            "function f(a: i32, b: i32) -> i32
+bb0: preds []
+\t%0: i32 = param a
+\t%1: i32 = param b
+\t%2: i32 = %0 % %1
+\t%3: i32 = %1 * %0
+\t%4: i32 = %2 + %2
+\t%5: i32 = %3 + %3
+\t%6: i32 = %4 + %5
+\treturn %6
+"
+        );
+    }
+
+    #[test]
+    fn dominating_values_are_reused_in_other_blocks() {
+        let function = numbered(
+            "function f(a, b) {\n\tlet x = a * b;\n\tlet y = 0;\n\tif (a == 1) {\n\t\ty = a * b;\n\t\treturn y;\n\t}\n\tif (b == 1) {\n\t\tlet z = a + b;\n\t\treturn z;\n\t}\n\tlet w = a + b;\n\treturn w + x;\n}\n",
+        );
+
+        let binary = |op: Token| {
+            function
+                .values
+                .iter()
+                .filter(|inst| matches!(&inst.kind, InstKind::Binary { op: inst_op, .. } if *inst_op == op))
+                .count()
+        };
+
+        // the `a * b` of the entry dominates the one in the if, the `a + b` of the if
+        // doesn't dominate the one after it
+        assert_eq!(binary(Token::Mul), 1);
+        assert_eq!(binary(Token::Add), 3);
+        // `1` is only there once
+        assert_eq!(
+            function
+                .values
+                .iter()
+                .filter(|inst| inst.kind == InstKind::Const("1".to_string()))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn only_calls_of_pure_functions_are_shared() {
+        let src = "function f(a) {\n\tlet x = g(a);\n\tlet y = g(a);\n\tlet z = h(a);\n\tlet w = h(a);\n\tlet s = x + y;\n\tlet t = z + w;\n\treturn s + t;\n}\n\nfunction g(a) {\n\treturn a;\n}\n\nfunction h(a) {\n\treturn a;\n}\n";
+        let effect = |effect| FuncEffect {
+            effect,
+            reason: None,
+        };
+        let effects = Effects::from([
+            ("g".to_string(), effect(Effect::Pure)),
+            ("h".to_string(), effect(Effect::ReadOnly)),
+        ]);
+
+        let function = numbered_with(src, &effects);
+
+        let calls: Vec<&str> = function
+            .values
+            .iter()
+            .filter_map(|inst| match &inst.kind {
+                InstKind::Call { func, .. } => Some(func.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(calls, ["g", "h", "h"]);
+    }
+}
@@ -0,0 +1,367 @@
+//! Inlining.
+//!
+//! A call of a small function is replaced by a copy of its body: the block of the call is
+//! split after it, the blocks of the callee are added with new numbers for their values, the
+//! parameters become the arguments and every return jumps to the code after the call, where
+//! a phi picks the returned value when there are several returns. A callee is small when it
+//! has at most `threshold` instructions, phis and parameters aside.
+//!
+//! Functions are inlined into their callers from the bottom of the call graph up, so what's
+//! copied is already inlined itself. A function that can call itself, directly or through
+//! other functions, is never inlined, the copies would never end.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::ir::{Block, BlockId, Function, Inst, InstKind, Terminator, ValueId};
+
+/// Inlines the calls between the functions of a module whose callee is small and not
+/// recursive
+pub fn inline_calls(functions: &mut [Function], threshold: usize) {
+    let calls: BTreeMap<String, BTreeSet<String>> = functions
+        .iter()
+        .map(|function| (function.name.clone(), callees(function)))
+        .collect();
+
+    let recursive: HashSet<&str> = calls
+        .keys()
+        .filter(|name| reaches(&calls, name, name))
+        .map(String::as_str)
+        .collect();
+
+    let mut done = HashSet::new();
+    let mut order = Vec::new();
+
+    for name in calls.keys() {
+        postorder(&calls, name, &mut done, &mut order);
+    }
+
+    // callees come before their callers
+    for name in order {
+        let Some(caller) = functions.iter().position(|function| function.name == name) else {
+            continue;
+        };
+
+        while let Some((block, position, callee)) = functions[caller]
+            .blocks
+            .iter()
+            .enumerate()
+            .find_map(|(block, contents)| {
+                contents
+                    .insts
+                    .iter()
+                    .enumerate()
+                    .find_map(|(position, value)| {
+                        let InstKind::Call { func, .. } = &functions[caller].values[*value].kind
+                        else {
+                            return None;
+                        };
+
+                        functions
+                            .iter()
+                            .position(|callee| callee.name == *func)
+                            .filter(|callee| {
+                                !recursive.contains(func.as_str())
+                                    && size(&functions[*callee]) <= threshold
+                            })
+                            .map(|callee| (block, position, callee))
+                    })
+            })
+        {
+            let callee = functions[callee].clone();
+            inline_call(&mut functions[caller], block, position, &callee);
+        }
+    }
+}
+
+/// The functions a function calls
+fn callees(function: &Function) -> BTreeSet<String> {
+    function
+        .values
+        .iter()
+        .filter_map(|inst| match &inst.kind {
+            InstKind::Call { func, .. } => Some(func.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a chain of calls goes from `from` to `to`
+fn reaches(calls: &BTreeMap<String, BTreeSet<String>>, from: &str, to: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![from];
+
+    while let Some(func) = stack.pop() {
+        for callee in calls.get(func).into_iter().flatten() {
+            if callee == to {
+                return true;
+            }
+
+            if seen.insert(callee.as_str()) {
+                stack.push(callee);
+            }
+        }
+    }
+
+    false
+}
+
+fn postorder(
+    calls: &BTreeMap<String, BTreeSet<String>>,
+    name: &str,
+    done: &mut HashSet<String>,
+    order: &mut Vec<String>,
+) {
+    if !done.insert(name.to_string()) {
+        return;
+    }
+
+    for callee in calls.get(name).into_iter().flatten() {
+        postorder(calls, callee, done, order);
+    }
+
+    order.push(name.to_string());
+}
+
+/// The instructions of a function that do some work, phis and parameters aside
+fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter(|value| {
+            !matches!(
+                function.values[**value].kind,
+                InstKind::Param(_) | InstKind::Phi(_)
+            )
+        })
+        .count()
+}
+
+/// Replaces the call at `position` in `block` by the body of `callee`
+fn inline_call(caller: &mut Function, block: BlockId, position: usize, callee: &Function) {
+    let call = caller.blocks[block].insts[position];
+
+    let InstKind::Call { args, .. } = caller.values[call].kind.clone() else {
+        return;
+    };
+
+    // the blocks of the callee come next, then the code after the call, which they return to
+    let start = caller.blocks.len();
+    let after = start + callee.blocks.len();
+
+    let insts = caller.blocks[block].insts.split_off(position + 1);
+    caller.blocks[block].insts.pop();
+
+    let terminator = std::mem::replace(
+        &mut caller.blocks[block].terminator,
+        Terminator::Jump(start),
+    );
+
+    // the successors of the block are now the successors of the code after the call
+    for inst in &mut caller.values {
+        if let InstKind::Phi(operands) = &mut inst.kind {
+            for (pred, _) in operands.iter_mut().filter(|(pred, _)| *pred == block) {
+                *pred = after;
+            }
+        }
+    }
+
+    // the values of the callee get new numbers, its parameters are the arguments
+    let mut numbers: Vec<ValueId> = Vec::new();
+    let mut next = caller.values.len();
+
+    for value in 0..callee.values.len() {
+        match callee.params.iter().position(|param| *param == value) {
+            Some(i) => numbers.push(args[i]),
+            None => {
+                numbers.push(next);
+                next += 1;
+            }
+        }
+    }
+
+    for (value, inst) in callee.values.iter().enumerate() {
+        if callee.params.contains(&value) {
+            continue;
+        }
+
+        let mut inst = inst.clone();
+
+        for operand in inst.operands_mut() {
+            *operand = numbers[*operand];
+        }
+
+        if let InstKind::Phi(operands) = &mut inst.kind {
+            for (pred, _) in operands {
+                *pred += start;
+            }
+        }
+
+        caller.values.push(inst);
+    }
+
+    let mut returns: Vec<(BlockId, Option<ValueId>)> = Vec::new();
+
+    for (id, callee_block) in callee.blocks.iter().enumerate() {
+        let terminator = match &callee_block.terminator {
+            Terminator::Jump(target) => Terminator::Jump(target + start),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => Terminator::Branch {
+                cond: numbers[*cond],
+                then: then + start,
+                otherwise: otherwise + start,
+            },
+            Terminator::Return(value) => {
+                returns.push((id + start, value.map(|value| numbers[value])));
+                Terminator::Jump(after)
+            }
+        };
+
+        caller.blocks.push(Block {
+            insts: callee_block
+                .insts
+                .iter()
+                .filter(|value| !callee.params.contains(value))
+                .map(|value| numbers[*value])
+                .collect(),
+            terminator,
+            preds: Vec::new(),
+            succs: Vec::new(),
+        });
+    }
+
+    caller.blocks.push(Block {
+        insts,
+        terminator,
+        preds: Vec::new(),
+        succs: Vec::new(),
+    });
+
+    if caller.values[call].ty.is_some() {
+        // a single return gives the result as it is, several meet in a phi
+        let result = match returns[..] {
+            [(_, Some(value))] => value,
+            _ => {
+                let phi = caller.values.len();
+
+                caller.values.push(Inst {
+                    kind: InstKind::Phi(
+                        returns
+                            .iter()
+                            .filter_map(|(pred, value)| value.map(|value| (*pred, value)))
+                            .collect(),
+                    ),
+                    ty: caller.values[call].ty,
+                });
+                caller.blocks[after].insts.insert(0, phi);
+
+                phi
+            }
+        };
+
+        caller.replace_uses(|value| if value == call { result } else { value });
+    }
+
+    caller.link();
+    caller.remove_trivial_phis();
+    caller.renumber();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::{Options, ir_functions},
+        ir::verify::verify,
+        lexer::lex,
+        parser::parse,
+    };
+
+    fn inlined(src: &str, threshold: usize) -> Vec<Function> {
+        let body = parse(lex(src.to_string())).unwrap().body;
+        let mut functions: Vec<Function> = ir_functions(body, &Options::default())
+            .into_iter()
+            .map(|(_, function)| function.unwrap())
+            .collect();
+
+        inline_calls(&mut functions, threshold);
+
+        for function in &functions {
+            assert_eq!(verify(function), Ok(()));
+        }
+
+        functions
+    }
+
+    fn calls(function: &Function) -> Vec<String> {
+        function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter_map(|value| match &function.values[*value].kind {
+                InstKind::Call { func, .. } => Some(func.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_functions_are_copied_into_their_callers() {
+        let functions = inlined(
+            "function sq(x) {\n\treturn x * x;\n}\n\nfunction f(a) {\n\tlet b = sq(a);\n\treturn sq(b + 1);\n}\n",
+            4,
+        );
+
+        assert_eq!(
+            functions[1].to_string(),
+            "function f(a: i32) -> i32
+bb0: preds []
+\t%0: i32 = param a
+\tjump bb1
+bb1: preds [bb0]
+\t%1: i32 = %0 * %0
+\tjump bb2
+bb2: preds [bb1]
+\t%2: i32 = const 1
+\t%3: i32 = %1 + %2
+\tjump bb3
+bb3: preds [bb2]
+\t%4: i32 = %3 * %3
+\tjump bb4
+bb4: preds [bb3]
+\treturn %4
+"
+        );
+    }
+
+    #[test]
+    fn returns_meet_in_a_phi() {
+        let functions = inlined(
+            "function abs(x) {\n\tif (x < 0) return 0 - x;\n\treturn x;\n}\n\nfunction f(a) {\n\treturn abs(a) + 1;\n}\n",
+            8,
+        );
+
+        assert_eq!(calls(&functions[1]), Vec::<String>::new());
+        assert!(
+            functions[1]
+                .values
+                .iter()
+                .any(|inst| matches!(&inst.kind, InstKind::Phi(operands) if operands.len() == 2))
+        );
+    }
+
+    #[test]
+    fn big_and_recursive_functions_stay_calls() {
+        let functions = inlined(
+            "function fact(n) {\n\tif (n == 0) return 1;\n\treturn n * fact(n - 1);\n}\n\nfunction ping(n) {\n\tif (n == 0) return 0;\n\treturn pong(n - 1);\n}\n\nfunction pong(n) {\n\treturn ping(n);\n}\n\nfunction add(a, b) {\n\tlet c = a + b;\n\tlet d = c + b;\n\treturn d + a;\n}\n\nfunction f(a) {\n\tlet x = fact(a);\n\tlet y = pong(x);\n\treturn add(y, 1);\n}\n",
+            2,
+        );
+
+        assert_eq!(calls(&functions[4]), ["fact", "pong", "add"]);
+        // `pong` only calls `ping`, but they call each other
+        assert_eq!(calls(&functions[2]), ["ping"]);
+    }
+}
@@ -0,0 +1,143 @@
+//! Liveness of SSA values.
+//!
+//! A value is live from its definition to its last use. A phi reads each operand at the end
+//! of the predecessor it comes from and is assigned on the edge into its block, together
+//! with the other phis of the block, so phis of the same block always interfere.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    analysis::liveness::Interference,
+    ir::{BlockId, Function, InstKind, ValueId},
+};
+
+/// Every value interferes with the values live right after its definition
+pub fn interference(function: &Function) -> Interference<ValueId> {
+    let mut live_in = vec![BTreeSet::new(); function.blocks.len()];
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for block in (0..function.blocks.len()).rev() {
+            let live = live_before(function, block, &live_in, &mut Interference::default());
+
+            if live != live_in[block] {
+                live_in[block] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut interference = Interference::default();
+
+    for block in 0..function.blocks.len() {
+        live_before(function, block, &live_in, &mut interference);
+    }
+
+    interference
+}
+
+/// The values live at the end of a block, the phi operands it passes on included
+fn live_out(
+    function: &Function,
+    block: BlockId,
+    live_in: &[BTreeSet<ValueId>],
+) -> BTreeSet<ValueId> {
+    let mut live = BTreeSet::new();
+
+    for succ in &function.blocks[block].succs {
+        let phis: Vec<ValueId> = function.phis(*succ).collect();
+
+        live.extend(live_in[*succ].iter().filter(|value| !phis.contains(value)));
+
+        for phi in phis {
+            if let InstKind::Phi(operands) = &function.values[phi].kind {
+                live.extend(
+                    operands
+                        .iter()
+                        .filter(|(pred, _)| *pred == block)
+                        .map(|(_, value)| *value),
+                );
+            }
+        }
+    }
+
+    live
+}
+
+/// Walks a block backwards, adds the interference of every definition in it and returns
+/// the values live at its start
+fn live_before(
+    function: &Function,
+    block: BlockId,
+    live_in: &[BTreeSet<ValueId>],
+    interference: &mut Interference<ValueId>,
+) -> BTreeSet<ValueId> {
+    let mut live = live_out(function, block, live_in);
+    live.extend(function.blocks[block].terminator.operands());
+
+    let phis: Vec<ValueId> = function.phis(block).collect();
+
+    for value in function.blocks[block].insts[phis.len()..].iter().rev() {
+        let inst = &function.values[*value];
+
+        for other in &live {
+            interference.add(value, other);
+        }
+
+        // the array is stored in its local before the elements are read
+        if let InstKind::Array { elements, .. } = &inst.kind {
+            for element in elements {
+                interference.add(value, element);
+            }
+        }
+
+        live.remove(value);
+        live.extend(inst.operands());
+    }
+
+    for phi in &phis {
+        for other in live.iter().chain(&phis) {
+            interference.add(phi, other);
+        }
+    }
+
+    for phi in &phis {
+        live.remove(phi);
+    }
+
+    live
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::{Options, ir_functions},
+        lexer::lex,
+        parser::parse,
+    };
+
+    #[test]
+    fn phis_of_a_block_interfere() {
+        // bb1 is the loop header with the phis %2 for b and %3 for a, bb2 computes %6 = %3 % %2
+        let body = parse(lex(
+            "function gcd(a, b) {\n\twhile (b != 0) {\n\t\tlet t = b;\n\t\tb = a % b;\n\t\ta = t;\n\t}\n\treturn a;\n}\n"
+                .to_string(),
+        ))
+        .unwrap()
+        .body;
+        let function = ir_functions(body, &Options::default()).remove(0).1.unwrap();
+        let interference = interference(&function);
+
+        assert!(interference.interferes(&2, &3));
+        // the remainder is passed to the header with %2, which is still live
+        assert!(interference.interferes(&6, &2));
+        // `a` isn't needed once the remainder is computed
+        assert!(!interference.interferes(&6, &3));
+        // the parameters are dead once the phis have their values
+        assert!(!interference.interferes(&0, &2));
+        assert!(!interference.interferes(&1, &3));
+    }
+}
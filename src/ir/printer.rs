@@ -0,0 +1,145 @@
+//! Prints the IR, one instruction per line under the block it's in.
+
+use std::fmt;
+
+use crate::ir::{Function, InstKind, Terminator};
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params = self
+            .params
+            .iter()
+            .map(|param| match &self.values[*param].kind {
+                InstKind::Param(name) => format!("{}: {}", name, type_text(self, *param)),
+                _ => format!("%{}", param),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        write!(f, "function {}({})", self.name, params)?;
+
+        if let Some(result) = self.result {
+            write!(f, " -> {}", result.as_str())?;
+        }
+
+        writeln!(f)?;
+
+        for (id, block) in self.blocks.iter().enumerate() {
+            let preds = block
+                .preds
+                .iter()
+                .map(|pred| format!("bb{}", pred))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            writeln!(f, "bb{}: preds [{}]", id, preds)?;
+
+            for value in &block.insts {
+                let inst = match &self.values[*value].kind {
+                    InstKind::Param(name) => format!("param {}", name),
+                    InstKind::Const(text) => format!("const {}", text),
+                    InstKind::Binary { op, lhs, rhs } => format!("%{} {} %{}", lhs, op, rhs),
+                    InstKind::Convert(operand) => format!("convert %{}", operand),
+                    InstKind::Index { array, index } => format!("%{}[%{}]", array, index),
+                    InstKind::Length(array) => format!("%{}.length", array),
+                    InstKind::Call { func, args } => format!("call {}({})", func, values(args)),
+                    InstKind::Array { elements, .. } => format!("array [{}]", values(elements)),
+                    InstKind::Phi(operands) => format!(
+                        "phi [{}]",
+                        operands
+                            .iter()
+                            .map(|(pred, value)| format!("bb{}: %{}", pred, value))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                };
+
+                if self.values[*value].ty.is_some() {
+                    writeln!(f, "\t%{}: {} = {}", value, type_text(self, *value), inst)?;
+                } else {
+                    writeln!(f, "\t%{} = {}", value, inst)?;
+                }
+            }
+
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "\tjump bb{}", target)?,
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                } => writeln!(f, "\tbranch %{} bb{} bb{}", cond, then, otherwise)?,
+                Terminator::Return(Some(value)) => writeln!(f, "\treturn %{}", value)?,
+                Terminator::Return(None) => writeln!(f, "\treturn")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn type_text(function: &Function, value: usize) -> &'static str {
+    function.values[value].ty.map_or("void", |ty| ty.as_str())
+}
+
+fn values(values: &[usize]) -> String {
+    values
+        .iter()
+        .map(|value| format!("%{}", value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        codegen::{Options, ir_functions},
+        lexer::lex,
+        parser::parse,
+    };
+
+    #[test]
+    fn prints_blocks_phis_and_calls() {
+        let body = parse(lex(
+            "function f(a, ...xs) {\n\tlet x = xs.length;\n\tif (a == 0) {\n\t\tx = xs[a] * 0.5;\n\t}\n\tg(x, 1);\n\treturn x;\n}\n\nfunction g(a, ...ys) {\n\tlet y = a;\n}\n"
+                .to_string(),
+        ))
+        .unwrap()
+        .body;
+
+        let printed: String = ir_functions(body, &Options::default())
+            .into_iter()
+            .map(|(_, function)| function.unwrap().to_string())
+            .collect();
+
+        assert_eq!(
+            printed,
+            "function f(a: i32, xs: i32) -> f64
+bb0: preds []
+\t%0: i32 = param a
+\t%1: i32 = param xs
+\t%2: i32 = %1.length
+\t%3: f64 = convert %2
+\t%4: i32 = const 0
+\t%5: i32 = %0 == %4
+\tbranch %5 bb1 bb2
+bb1: preds [bb0]
+\t%6: i32 = %1[%0]
+\t%7: f64 = convert %6
+\t%8: f64 = const 0.5
+\t%9: f64 = %7 * %8
+\tjump bb2
+bb2: preds [bb0, bb1]
+\t%10: f64 = phi [bb0: %3, bb1: %9]
+\t%11: i32 = const 1
+\t%12: i32 = array [%11]
+\t%13 = call g(%10, %12)
+\treturn %10
+function g(a: f64, ys: i32)
+bb0: preds []
+\t%0: f64 = param a
+\t%1: i32 = param ys
+\treturn
+"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Structured control flow.
+//!
+//! WASM has no gotos, only blocks and loops to branch out of, so the blocks of a function are
+//! nested back into a tree the way "Beyond Relooper" by Ramsey does it. A block is placed
+//! under its immediate dominator. A loop header gets a `loop` that a back edge branches to
+//! the start of. A block with more than one forward predecessor, a merge block, comes right
+//! after a `block` its predecessors branch to the end of. Any other block has a single
+//! predecessor and is placed where that predecessor goes to it.
+//!
+//! The phis of a block become copies on the edges into it, made before the branch.
+
+use crate::{
+    analysis::dominators::postorder,
+    ir::{BlockId, Function, Terminator, ValueId},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Structured {
+    /// a branch to it goes to the end
+    Block(Vec<Structured>),
+    /// a branch to it goes to the start
+    Loop(Vec<Structured>),
+    If {
+        cond: ValueId,
+        then: Vec<Structured>,
+        otherwise: Vec<Structured>,
+    },
+    /// the instructions of a block, without the terminator
+    Code(BlockId),
+    /// sets the phis of `to` to the values they take when coming from `from`
+    Copies {
+        from: BlockId,
+        to: BlockId,
+    },
+    /// branches to the `n`th enclosing block, loop or if, 0 is the innermost
+    Br(usize),
+    Return(Option<ValueId>),
+}
+
+/// What a label of the enclosing code belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    IfThenElse,
+    LoopHeadedBy(BlockId),
+    BlockFollowedBy(BlockId),
+}
+
+/// The body of a function as nested blocks, loops and ifs
+pub fn structure(function: &Function) -> Vec<Structured> {
+    let doms = function.dominators();
+
+    let mut rpo = postorder(0, function.blocks.len(), &|block| {
+        function.blocks[block].succs.as_slice()
+    });
+    rpo.reverse();
+
+    let mut number = vec![usize::MAX; function.blocks.len()];
+    for (i, block) in rpo.iter().enumerate() {
+        number[*block] = i;
+    }
+
+    let structurer = Structurer {
+        function,
+        number,
+        children: (0..function.blocks.len())
+            .map(|block| doms.children(block))
+            .collect(),
+    };
+
+    structurer.tree(0, &mut Vec::new())
+}
+
+struct Structurer<'a> {
+    function: &'a Function,
+    // reverse postorder number of every block
+    number: Vec<usize>,
+    // children of every block in the dominator tree
+    children: Vec<Vec<BlockId>>,
+}
+
+impl Structurer<'_> {
+    fn is_backward(&self, from: BlockId, to: BlockId) -> bool {
+        self.number[to] <= self.number[from]
+    }
+
+    fn is_loop_header(&self, block: BlockId) -> bool {
+        self.function.blocks[block]
+            .preds
+            .iter()
+            .any(|pred| self.is_backward(*pred, block))
+    }
+
+    fn is_merge(&self, block: BlockId) -> bool {
+        self.function.blocks[block]
+            .preds
+            .iter()
+            .filter(|pred| !self.is_backward(**pred, block))
+            .count()
+            >= 2
+    }
+
+    /// A block and the blocks it dominates
+    fn tree(&self, block: BlockId, context: &mut Vec<Context>) -> Vec<Structured> {
+        // the merge children that come last are the outermost blocks, they're branched to
+        // from anywhere in the blocks nested in them
+        let mut merges: Vec<BlockId> = self.children[block]
+            .iter()
+            .copied()
+            .filter(|child| self.is_merge(*child))
+            .collect();
+        merges.sort_by_key(|child| std::cmp::Reverse(self.number[*child]));
+
+        if self.is_loop_header(block) {
+            context.push(Context::LoopHeadedBy(block));
+            let body = self.within(block, &merges, context);
+            context.pop();
+
+            vec![Structured::Loop(body)]
+        } else {
+            self.within(block, &merges, context)
+        }
+    }
+
+    /// A block followed by its merge children, each after a `block` the code before it can
+    /// branch out of
+    fn within(
+        &self,
+        block: BlockId,
+        merges: &[BlockId],
+        context: &mut Vec<Context>,
+    ) -> Vec<Structured> {
+        let Some((&merge, inner)) = merges.split_first() else {
+            return self.code(block, context);
+        };
+
+        context.push(Context::BlockFollowedBy(merge));
+        let mut code = vec![Structured::Block(self.within(block, inner, context))];
+        context.pop();
+
+        code.extend(self.tree(merge, context));
+
+        code
+    }
+
+    /// A block and the code its terminator goes to
+    fn code(&self, block: BlockId, context: &mut Vec<Context>) -> Vec<Structured> {
+        let mut code = vec![Structured::Code(block)];
+
+        match &self.function.blocks[block].terminator {
+            Terminator::Jump(target) => code.extend(self.branch(block, *target, context)),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                context.push(Context::IfThenElse);
+                let then = self.branch(block, *then, context);
+                let otherwise = self.branch(block, *otherwise, context);
+                context.pop();
+
+                code.push(Structured::If {
+                    cond: *cond,
+                    then,
+                    otherwise,
+                });
+            }
+            Terminator::Return(value) => code.push(Structured::Return(*value)),
+        }
+
+        code
+    }
+
+    /// Goes from `from` to `to`, by branching to the loop or block `to` is at or by placing
+    /// it right here when `from` is its only predecessor
+    fn branch(&self, from: BlockId, to: BlockId, context: &mut Vec<Context>) -> Vec<Structured> {
+        let mut code = Vec::new();
+
+        if self.function.phis(to).next().is_some() {
+            code.push(Structured::Copies { from, to });
+        }
+
+        let label = if self.is_backward(from, to) {
+            Context::LoopHeadedBy(to)
+        } else if self.is_merge(to) {
+            Context::BlockFollowedBy(to)
+        } else {
+            code.extend(self.tree(to, context));
+            return code;
+        };
+
+        // UNWRAP: loop headers and merge blocks are always around the blocks that go to them
+        let depth = context
+            .iter()
+            .rev()
+            .position(|enclosing| *enclosing == label)
+            .unwrap();
+
+        code.push(Structured::Br(depth));
+
+        code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::{Options, ir_functions},
+        lexer::lex,
+        parser::parse,
+    };
+
+    fn tree(src: &str) -> Vec<Structured> {
+        let body = parse(lex(src.to_string())).unwrap().body;
+
+        structure(&ir_functions(body, &Options::default()).remove(0).1.unwrap())
+    }
+
+    #[test]
+    fn loop_branches_back_to_its_header() {
+        // bb1 is the header, bb2 the body and bb3 the return after the loop
+        let tree = tree(
+            "function gcd(a, b) {\n\twhile (b != 0) {\n\t\tlet t = b;\n\t\tb = a % b;\n\t\ta = t;\n\t}\n\treturn a;\n}\n",
+        );
+
+        assert_eq!(
+            tree,
+            [
+                Structured::Code(0),
+                Structured::Copies { from: 0, to: 1 },
+                Structured::Loop(vec![
+                    Structured::Code(1),
+                    Structured::If {
+                        cond: 5,
+                        then: vec![
+                            Structured::Code(2),
+                            Structured::Copies { from: 2, to: 1 },
+                            Structured::Br(1),
+                        ],
+                        otherwise: vec![Structured::Code(3), Structured::Return(Some(3))],
+                    },
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_block_after_a_block() {
+        // bb1 is the if block, bb2 the code after it
+        let tree = tree(
+            "function f(a) {\n\tlet x = 1;\n\tif (a == 0) {\n\t\tx = 2;\n\t}\n\treturn x;\n}\n",
+        );
+
+        assert_eq!(
+            tree,
+            [
+                Structured::Block(vec![
+                    Structured::Code(0),
+                    Structured::If {
+                        cond: 3,
+                        then: vec![
+                            Structured::Code(1),
+                            Structured::Copies { from: 1, to: 2 },
+                            Structured::Br(1),
+                        ],
+                        otherwise: vec![Structured::Copies { from: 0, to: 2 }, Structured::Br(1)],
+                    },
+                ]),
+                Structured::Code(2),
+                Structured::Return(Some(5)),
+            ]
+        );
+    }
+}
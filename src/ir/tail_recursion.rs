@@ -0,0 +1,171 @@
+//! Self-recursive tail calls as loops.
+//!
+//! A function that returns the result of a call to itself can go back to its start with the
+//! arguments as its parameters instead of making the call, the stack doesn't grow with the
+//! recursion. The entry block is split: the parameters stay in it and the rest of it moves to
+//! a loop header with a phi for every parameter, which picks the parameter on entry and the
+//! argument on the edge from each call. Calls that aren't in tail position, like the inner one
+//! of `ack(m - 1, ack(m, n - 1))`, are left alone.
+
+use crate::ir::{Block, Function, Inst, InstKind, Terminator, ValueId};
+
+/// Turns the calls of a function to itself in tail position into jumps back to its start
+pub fn loop_tail_recursion(function: &mut Function) {
+    if !function
+        .tail_calls()
+        .iter()
+        .any(|call| is_self_call(function, *call))
+    {
+        return;
+    }
+
+    let header = function.blocks.len();
+    let entry = &mut function.blocks[0];
+
+    let insts = entry.insts.split_off(function.params.len());
+    let terminator = std::mem::replace(&mut entry.terminator, Terminator::Jump(header));
+
+    function.blocks.push(Block {
+        insts,
+        terminator,
+        preds: Vec::new(),
+        succs: Vec::new(),
+    });
+
+    // the blocks after the entry now come after the header
+    for inst in &mut function.values {
+        if let InstKind::Phi(operands) = &mut inst.kind {
+            for (pred, _) in operands.iter_mut().filter(|(pred, _)| *pred == 0) {
+                *pred = header;
+            }
+        }
+    }
+
+    let params = function.params.clone();
+    let phis: Vec<ValueId> = (function.values.len()..).take(params.len()).collect();
+
+    // every use of a parameter reads its phi, the arguments of the calls included
+    function.replace_uses(|value| {
+        params
+            .iter()
+            .position(|param| *param == value)
+            .map_or(value, |i| phis[i])
+    });
+
+    for param in &params {
+        function.values.push(Inst {
+            kind: InstKind::Phi(vec![(0, *param)]),
+            ty: function.values[*param].ty,
+        });
+    }
+
+    function.blocks[header]
+        .insts
+        .splice(0..0, phis.iter().copied());
+
+    let calls: Vec<ValueId> = function
+        .tail_calls()
+        .into_iter()
+        .filter(|call| is_self_call(function, *call))
+        .collect();
+
+    for call in calls {
+        let InstKind::Call { args, .. } = function.values[call].kind.clone() else {
+            continue;
+        };
+
+        // UNWRAP: tail calls are the last instruction of a block
+        let block = function
+            .blocks
+            .iter()
+            .position(|block| block.insts.last() == Some(&call))
+            .unwrap();
+
+        function.blocks[block].insts.pop();
+        function.blocks[block].terminator = Terminator::Jump(header);
+
+        for (phi, arg) in phis.iter().zip(args) {
+            if let InstKind::Phi(operands) = &mut function.values[*phi].kind {
+                operands.push((block, arg));
+            }
+        }
+    }
+
+    function.link();
+    // a parameter passed on as it is needs no phi
+    function.remove_trivial_phis();
+    function.renumber();
+}
+
+fn is_self_call(function: &Function, value: ValueId) -> bool {
+    matches!(
+        &function.values[value].kind,
+        InstKind::Call { func, args } if *func == function.name && args.len() == function.params.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::{Options, ir_functions},
+        ir::verify::verify,
+        lexer::lex,
+        parser::parse,
+    };
+
+    fn looped(src: &str) -> Function {
+        let body = parse(lex(src.to_string())).unwrap().body;
+        let mut function = ir_functions(body, &Options::default()).remove(0).1.unwrap();
+
+        loop_tail_recursion(&mut function);
+        assert_eq!(verify(&function), Ok(()));
+
+        function
+    }
+
+    #[test]
+    fn tail_call_jumps_back_to_the_header() {
+        // bb3 is the header, it's added after the other blocks
+        let function =
+            looped("function gcd(a, b) {\n\tif (b == 0) return a;\n\treturn gcd(b, a % b);\n}\n");
+
+        assert_eq!(
+            function.to_string(),
+            "function gcd(a: i32, b: i32) -> i32
+bb0: preds []
+\t%0: i32 = param a
+\t%1: i32 = param b
+\tjump bb3
+bb1: preds [bb3]
+\treturn %3
+bb2: preds [bb3]
+\t%2: i32 = %3 % %4
+\tjump bb3
+bb3: preds [bb0, bb2]
+\t%3: i32 = phi [bb0: %0, bb2: %4]
+\t%4: i32 = phi [bb0: %1, bb2: %2]
+\t%5: i32 = const 0
+\t%6: i32 = %4 == %5
+\tbranch %6 bb1 bb2
+"
+        );
+    }
+
+    #[test]
+    fn calls_that_are_not_in_tail_position_stay() {
+        let function = looped(
+            "function ack(m, n) {\n\tif (m == 0) return n + 1;\n\tif (n == 0) return ack(m - 1, 1);\n\n\treturn ack(m - 1, ack(m, n - 1));\n}\n",
+        );
+
+        let calls = function
+            .values
+            .iter()
+            .filter(|inst| matches!(inst.kind, InstKind::Call { .. }))
+            .count();
+
+        // only the inner call is left
+        assert_eq!(calls, 1);
+        assert_eq!(function.tail_calls(), []);
+    }
+}
@@ -0,0 +1,413 @@
+//! IR verifier.
+//!
+//! Checks the invariants the passes and the lowering rely on: the edges match the
+//! terminators, every value is defined once and before it's used on every path, phis come
+//! first and have an operand for each predecessor, and the types line up.
+
+use std::fmt;
+
+use crate::{
+    analysis::{
+        dominators::{DomTree, postorder},
+        types::ValType,
+    },
+    ir::{BlockId, Function, Inst, InstKind, Terminator, ValueId},
+};
+
+/// A broken invariant in a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub block: BlockId,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}: {}", self.block, self.message)
+    }
+}
+
+pub fn verify(function: &Function) -> Result<(), Vec<VerifyError>> {
+    let mut verifier = Verifier {
+        function,
+        defs: vec![None; function.values.len()],
+        errors: Vec::new(),
+    };
+
+    verifier.edges();
+    verifier.defs();
+
+    // dominance doesn't mean much in a broken graph
+    if verifier.errors.is_empty() {
+        verifier.uses();
+    }
+
+    if verifier.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(verifier.errors)
+    }
+}
+
+struct Verifier<'a> {
+    function: &'a Function,
+    // (block, position) of the definition of every value
+    defs: Vec<Option<(BlockId, usize)>>,
+    errors: Vec<VerifyError>,
+}
+
+impl Verifier<'_> {
+    fn error(&mut self, block: BlockId, message: String) {
+        self.errors.push(VerifyError { block, message });
+    }
+
+    /// The successors are the targets of the terminator, the predecessors the blocks that
+    /// have a block as a successor, every block is reachable from the entry
+    fn edges(&mut self) {
+        let blocks = &self.function.blocks;
+
+        if blocks.is_empty() {
+            self.error(0, "the function has no entry block".to_string());
+            return;
+        }
+
+        for (id, block) in blocks.iter().enumerate() {
+            let succs = block.terminator.succs();
+
+            if let Some(target) = succs.iter().find(|succ| **succ >= blocks.len()) {
+                self.error(id, format!("jumps to bb{}, which doesn't exist", target));
+                return;
+            }
+
+            if block.succs != succs {
+                self.error(id, "successors don't match the terminator".to_string());
+            }
+
+            let mut preds: Vec<BlockId> = (0..blocks.len())
+                .flat_map(|pred| {
+                    blocks[pred]
+                        .terminator
+                        .succs()
+                        .into_iter()
+                        .filter(move |succ| *succ == id)
+                        .map(move |_| pred)
+                })
+                .collect();
+            let mut stored = block.preds.clone();
+
+            preds.sort_unstable();
+            stored.sort_unstable();
+
+            if preds != stored {
+                self.error(id, "predecessors don't match the terminators".to_string());
+            }
+        }
+
+        if !blocks[0].preds.is_empty() {
+            self.error(0, "the entry block has predecessors".to_string());
+        }
+
+        let reachable = postorder(0, blocks.len(), &|block| blocks[block].succs.as_slice());
+
+        for id in (0..blocks.len()).filter(|id| !reachable.contains(id)) {
+            self.error(id, "unreachable from the entry".to_string());
+        }
+    }
+
+    /// Every value is defined by exactly one block, phis come before the other instructions
+    fn defs(&mut self) {
+        let function = self.function;
+
+        for (id, block) in function.blocks.iter().enumerate() {
+            let mut phis_done = false;
+
+            for (position, value) in block.insts.iter().enumerate() {
+                if *value >= function.values.len() {
+                    self.error(id, format!("%{} isn't a value", value));
+                    continue;
+                }
+
+                if let Some((other, _)) = self.defs[*value] {
+                    self.error(id, format!("%{} is already defined in bb{}", value, other));
+                    continue;
+                }
+
+                self.defs[*value] = Some((id, position));
+
+                let inst = &function.values[*value];
+
+                if inst.is_phi() && phis_done {
+                    self.error(id, format!("phi %{} comes after other instructions", value));
+                }
+
+                phis_done |= !inst.is_phi();
+
+                if matches!(inst.kind, InstKind::Param(_)) && id != 0 {
+                    self.error(
+                        id,
+                        format!("parameter %{} is outside the entry block", value),
+                    );
+                }
+            }
+        }
+
+        for value in 0..function.values.len() {
+            if self.defs[value].is_none() {
+                self.error(0, format!("%{} isn't in any block", value));
+            }
+        }
+
+        for param in &function.params {
+            if !matches!(
+                function.values.get(*param),
+                Some(Inst {
+                    kind: InstKind::Param(_),
+                    ..
+                })
+            ) {
+                self.error(0, format!("%{} isn't a parameter", param));
+            }
+        }
+    }
+
+    /// Every use is dominated by its definition and has the type its user expects
+    fn uses(&mut self) {
+        let function = self.function;
+        let doms = function.dominators();
+
+        for (id, block) in function.blocks.iter().enumerate() {
+            for (position, value) in block.insts.iter().enumerate() {
+                let inst = &function.values[*value];
+
+                if let InstKind::Phi(operands) = &inst.kind {
+                    let mut preds: Vec<BlockId> = operands.iter().map(|(pred, _)| *pred).collect();
+                    let mut expected = block.preds.clone();
+
+                    preds.sort_unstable();
+                    expected.sort_unstable();
+
+                    if preds != expected {
+                        self.error(
+                            id,
+                            format!("phi %{} needs one operand for each predecessor", value),
+                        );
+                    }
+
+                    for (pred, operand) in operands {
+                        // the value leaves the predecessor, so it's defined at its end
+                        if !self.defined_at(*operand, *pred, usize::MAX, &doms) {
+                            self.error(
+                                id,
+                                format!(
+                                    "phi %{} reads %{} which bb{} doesn't have",
+                                    value, operand, pred
+                                ),
+                            );
+                        }
+                    }
+                } else {
+                    for operand in inst.operands() {
+                        if !self.defined_at(operand, id, position, &doms) {
+                            self.error(
+                                id,
+                                format!("%{} uses %{} before it's defined", value, operand),
+                            );
+                        }
+                    }
+                }
+
+                self.types(id, *value, inst);
+            }
+
+            for operand in block.terminator.operands() {
+                if !self.defined_at(operand, id, usize::MAX, &doms) {
+                    self.error(
+                        id,
+                        format!("the terminator uses %{} before it's defined", operand),
+                    );
+                }
+            }
+
+            match &block.terminator {
+                Terminator::Branch { cond, .. } if self.ty(*cond) != Some(ValType::I32) => {
+                    self.error(id, format!("branches on %{}, which isn't an i32", cond));
+                }
+                Terminator::Return(value) => {
+                    let ty = value.and_then(|value| self.ty(value));
+
+                    if ty != function.result || value.is_some() != function.result.is_some() {
+                        self.error(id, "returns a value of the wrong type".to_string());
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Whether `value` is defined on every path to `position` in `block`
+    fn defined_at(&self, value: ValueId, block: BlockId, position: usize, doms: &DomTree) -> bool {
+        match self.defs.get(value).copied().flatten() {
+            Some((def_block, def_position)) if def_block == block => def_position < position,
+            Some((def_block, _)) => doms.dominates(def_block, block),
+            None => false,
+        }
+    }
+
+    fn ty(&self, value: ValueId) -> Option<ValType> {
+        self.function.values.get(value).and_then(|inst| inst.ty)
+    }
+
+    fn types(&mut self, block: BlockId, value: ValueId, inst: &Inst) {
+        // (operand, the type it needs)
+        let expected: Vec<(ValueId, ValType)> = match &inst.kind {
+            InstKind::Param(_)
+            | InstKind::Const(_)
+            | InstKind::Call { .. }
+            | InstKind::Convert(_) => Vec::new(),
+            InstKind::Binary { op, lhs, rhs } => match self.ty(*lhs) {
+                Some(ty) => {
+                    if inst.ty != Some(Inst::binary_type(op, ty)) {
+                        self.error(block, format!("%{} has the wrong type for `{}`", value, op));
+                    }
+
+                    vec![(*rhs, ty)]
+                }
+                None => Vec::new(),
+            },
+            InstKind::Index { array, index } => {
+                vec![(*array, ValType::I32), (*index, ValType::I32)]
+            }
+            InstKind::Length(array) => vec![(*array, ValType::I32)],
+            InstKind::Array { elements, element } => elements
+                .iter()
+                .map(|element_value| (*element_value, *element))
+                .collect(),
+            InstKind::Phi(operands) => match inst.ty {
+                Some(ty) => operands.iter().map(|(_, operand)| (*operand, ty)).collect(),
+                None => Vec::new(),
+            },
+        };
+
+        for (operand, ty) in expected {
+            if self.ty(operand) != Some(ty) {
+                self.error(
+                    block,
+                    format!("%{} needs %{} to be an {}", value, operand, ty.as_str()),
+                );
+            }
+        }
+
+        // every operand has to be a value, not the missing result of a call
+        for operand in inst.operands() {
+            if self.ty(operand).is_none() {
+                self.error(
+                    block,
+                    format!("%{} uses %{}, which has no value", value, operand),
+                );
+            }
+        }
+
+        if inst.ty.is_none() && !matches!(inst.kind, InstKind::Call { .. }) {
+            self.error(block, format!("%{} has no type", value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::{Options, ir_functions},
+        lexer::Token,
+        lexer::lex,
+        parser::parse,
+    };
+
+    // bb1 is the loop header with the phis %2 for b and %3 for a, bb2 the body and bb3 returns
+    const GCD: &str = "function gcd(a, b) {\n\twhile (b != 0) {\n\t\tlet t = b;\n\t\tb = a % b;\n\t\ta = t;\n\t}\n\treturn a;\n}\n";
+
+    fn gcd() -> Function {
+        let body = parse(lex(GCD.to_string())).unwrap().body;
+
+        ir_functions(body, &Options::default()).remove(0).1.unwrap()
+    }
+
+    fn messages(function: &Function) -> Vec<String> {
+        verify(function)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn built_functions_verify() {
+        assert_eq!(verify(&gcd()), Ok(()));
+    }
+
+    #[test]
+    fn use_not_dominated_by_its_definition() {
+        let mut function = gcd();
+        // the header reads the remainder, which is only computed in the body
+        function.values[5] = Inst {
+            kind: InstKind::Binary {
+                op: Token::NotEq,
+                lhs: 6,
+                rhs: 4,
+            },
+            ty: Some(ValType::I32),
+        };
+
+        assert_eq!(messages(&function), ["bb1: %5 uses %6 before it's defined"]);
+    }
+
+    #[test]
+    fn phi_missing_an_operand() {
+        let mut function = gcd();
+        function.values[2].kind = InstKind::Phi(vec![(0, 1)]);
+
+        assert_eq!(
+            messages(&function),
+            ["bb1: phi %2 needs one operand for each predecessor"]
+        );
+    }
+
+    #[test]
+    fn edges_that_dont_match_the_terminators() {
+        let mut function = gcd();
+        function.blocks[3].terminator = Terminator::Jump(1);
+
+        assert_eq!(
+            messages(&function),
+            [
+                "bb1: predecessors don't match the terminators",
+                "bb3: successors don't match the terminator",
+            ]
+        );
+
+        function.link();
+
+        assert_eq!(
+            messages(&function),
+            [
+                "bb1: phi %2 needs one operand for each predecessor",
+                "bb1: phi %3 needs one operand for each predecessor",
+            ]
+        );
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let mut function = gcd();
+        function.values[4].ty = Some(ValType::F64);
+        function.blocks[3].terminator = Terminator::Return(None);
+
+        assert_eq!(
+            messages(&function),
+            [
+                "bb1: %5 needs %4 to be an i32",
+                "bb3: returns a value of the wrong type",
+            ]
+        );
+    }
+}
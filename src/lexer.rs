@@ -1,10 +1,15 @@
-#[derive(Debug, PartialEq, Eq, Clone)]
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Token {
     Function,
     ParenOpen,
     ParenClose,
     CurlyOpen,
     CurlyClose,
+    BracketOpen,
+    BracketClose,
+    Dot,
     SemiColon,
     Return,
     Comma,
@@ -34,18 +39,125 @@ pub enum Token {
     While,
     If,
     Else,
+    True,
+    False,
+    Null,
+    Undefined,
+    Export,
     Comment,
     WhiteSpace,
     Literal(String),
     None,
 }
 
+impl Token {
+    /// The source text of the token
+    pub fn as_str(&self) -> &str {
+        match self {
+            Token::Function => "function",
+            Token::ParenOpen => "(",
+            Token::ParenClose => ")",
+            Token::CurlyOpen => "{",
+            Token::CurlyClose => "}",
+            Token::BracketOpen => "[",
+            Token::BracketClose => "]",
+            Token::Dot => ".",
+            Token::SemiColon => ";",
+            Token::Return => "return",
+            Token::Comma => ",",
+            Token::Tab => "\t",
+            Token::NewLine => "\n",
+            Token::Eq => "=",
+            Token::DoubleEq => "==",
+            Token::Sub => "-",
+            Token::Add => "+",
+            Token::Mul => "*",
+            Token::Percent => "%",
+            Token::Exclaim => "!",
+            Token::GreaterThan => "<",
+            Token::LessThan => ">",
+            Token::Let => "let",
+            Token::Const => "const",
+            Token::NotEq => "!=",
+            Token::While => "while",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Null => "null",
+            Token::Undefined => "undefined",
+            Token::Export => "export",
+            Token::Comment => "//",
+            Token::WhiteSpace => " ",
+            Token::Literal(literal) => literal,
+            Token::None => "",
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Location of a token or a node in the source, `line` and `col` start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// Spans of a lexed token stream, every byte of the source ends up in exactly one token so
+/// the positions can be worked out from the tokens alone
+pub fn spans(tokens: &[Token]) -> Vec<Span> {
+    let mut spans = Vec::with_capacity(tokens.len());
+
+    let mut start = 0;
+    let mut line = 1;
+    let mut col = 1;
+
+    for token in tokens {
+        // non ascii bytes are pushed into literals one char per byte
+        let len = token.as_str().chars().count();
+
+        spans.push(Span {
+            start,
+            end: start + len,
+            line,
+            col,
+        });
+
+        start += len;
+
+        if let Token::NewLine = token {
+            line += 1;
+            col = 1;
+        } else {
+            col += len;
+        }
+    }
+
+    spans
+}
+
 fn get_byte_keyword(byte: &u8) -> Token {
     match byte {
         b'(' => Token::ParenOpen,
         b')' => Token::ParenClose,
         b'}' => Token::CurlyClose,
         b'{' => Token::CurlyOpen,
+        b'[' => Token::BracketOpen,
+        b']' => Token::BracketClose,
+        b'.' => Token::Dot,
         b';' => Token::SemiColon,
         b'=' => Token::Eq,
         b'-' => Token::Sub,
@@ -73,6 +185,11 @@ fn get_literal_keyword(keyword: &str) -> Token {
         "if" => Token::If,
         "//" => Token::Comment,
         "else" => Token::Else,
+        "true" => Token::True,
+        "false" => Token::False,
+        "null" => Token::Null,
+        "undefined" => Token::Undefined,
+        "export" => Token::Export,
         _ => Token::Literal(keyword.to_owned()),
     }
 }
@@ -142,6 +259,26 @@ mod tests {
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn test_rest_and_index_tokens() {
+        let input = String::from("...xs[i].length");
+        let tokens = lex(input);
+
+        let expected = vec![
+            Token::Dot,
+            Token::Dot,
+            Token::Dot,
+            Token::Literal("xs".into()),
+            Token::BracketOpen,
+            Token::Literal("i".into()),
+            Token::BracketClose,
+            Token::Dot,
+            Token::Literal("length".into()),
+        ];
+
+        assert_eq!(tokens, expected);
+    }
+
     #[test]
     fn test_keywords() {
         let input = String::from("function let return const if else while");
@@ -166,6 +303,26 @@ mod tests {
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn test_value_keywords() {
+        let input = String::from("true false null undefined truthy");
+        let tokens = lex(input);
+
+        let expected = vec![
+            Token::True,
+            Token::WhiteSpace,
+            Token::False,
+            Token::WhiteSpace,
+            Token::Null,
+            Token::WhiteSpace,
+            Token::Undefined,
+            Token::WhiteSpace,
+            Token::Literal("truthy".into()),
+        ];
+
+        assert_eq!(tokens, expected);
+    }
+
     #[test]
     fn test_literal_identifiers() {
         let input = String::from("foo bar baz");
@@ -205,6 +362,35 @@ mod tests {
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn test_spans() {
+        let tokens = lex(String::from("let x = 1;\n\treturn x;"));
+        let spans = spans(&tokens);
+
+        // `x` on the first line
+        assert_eq!(
+            spans[2],
+            Span {
+                start: 4,
+                end: 5,
+                line: 1,
+                col: 5
+            }
+        );
+
+        // `return` on the second line, after the tab
+        assert_eq!(tokens[10], Token::Return);
+        assert_eq!(
+            spans[10],
+            Span {
+                start: 12,
+                end: 18,
+                line: 2,
+                col: 2
+            }
+        );
+    }
+
     #[test]
     fn test_comment_literal() {
         let input = String::from("// comment");
@@ -1,11 +1,23 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
-use crate::{lexer::lex, parser::parse};
+use crate::{
+    analysis::{
+        call_graph::{Removed, remove_unused_funcs},
+        cfg::{build_cfgs, cfgs_to_dot},
+        liveness::Coalesced,
+        resolve::{ResolveError, resolve},
+        unreachable::{Warning, remove_unreachable},
+    },
+    codegen::Options,
+    lexer::{Span, lex},
+    parser::{ParseError, Program, json::program_to_json, parse, printer::print_program},
+};
 
 mod analysis;
 mod codegen;
+mod ir;
 mod lexer;
 mod parser;
 
@@ -15,22 +27,281 @@ struct Args {
     /// path of the js file to compile to WAT format
     #[arg(short, long)]
     path: PathBuf,
+
+    /// what to output, `ast` prints the syntax tree as JSON, `js` prints the parsed program
+    /// back as JavaScript, `symbols` prints the scopes and their names, `cfg` and `cfg-dot`
+    /// print the control-flow graph of every function as text or as DOT, `ssa` prints the
+    /// SSA IR of every function and `effects` whether every function is pure, read-only or
+    /// side-effecting instead of writing output.wat
+    #[arg(long, value_enum, default_value_t = Emit::Wat)]
+    emit: Emit,
+
+    /// list the functions that were left out of output.wat because nothing reaches them
+    #[arg(long)]
+    list_removed: bool,
+
+    /// report how many locals every function needs once variables that are never live at the
+    /// same time share one
+    #[arg(long)]
+    report_locals: bool,
+
+    /// return the result of calls in tail position with `return_call`, the runtime needs to
+    /// support the WebAssembly tail-call proposal
+    #[arg(long)]
+    tail_calls: bool,
+
+    /// how much to optimize, 0 compiles functions as they're written, 1 folds constants and
+    /// removes dead code, 2 also turns the tail calls of a function to itself into loops,
+    /// hoists loop invariants, reduces the strength of integer operations, computes repeated
+    /// expressions once and, with `--ssa`, inlines small functions
+    #[arg(short = 'O', long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=2))]
+    opt_level: u8,
+
+    /// the most instructions a function can have to be inlined at -O2
+    #[arg(long, default_value_t = 12)]
+    inline_threshold: usize,
+
+    /// compile functions through the SSA IR, the ones it can't express yet are compiled as
+    /// before
+    #[arg(long)]
+    ssa: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Emit {
+    Wat,
+    Ast,
+    Js,
+    Symbols,
+    Cfg,
+    CfgDot,
+    Ssa,
+    Effects,
+}
+
+/// Anything that stops a program from compiling
+#[derive(Debug)]
+enum CompileError {
+    Parse(ParseError),
+    Resolve(ResolveError),
+}
+
+impl CompileError {
+    fn span(&self) -> Span {
+        match self {
+            CompileError::Parse(error) => error.span,
+            CompileError::Resolve(error) => error.span,
+        }
+    }
+
+    /// Where the name the error is about is declared, printed after the error
+    fn declared(&self) -> Option<(&str, Span)> {
+        match self {
+            CompileError::Parse(_) => None,
+            CompileError::Resolve(error) => error.declared.map(|span| (error.name.as_str(), span)),
+        }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::Parse(error) => error.fmt(f),
+            CompileError::Resolve(error) => error.fmt(f),
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    std::fs::write("output.wat", js2wat(load_file(args.path))).unwrap();
+    let code = load_file(&args.path);
+
+    let options = Options {
+        ssa: args.ssa,
+        tail_calls: args.tail_calls,
+        opt_level: args.opt_level,
+        inline_threshold: args.inline_threshold,
+    };
+
+    let result = match args.emit {
+        Emit::Wat => {
+            js2wat(code, &args.path, &options).map(|(wat, warnings, removed, coalesced)| {
+                print_warnings(&args.path, &warnings);
+
+                if args.list_removed {
+                    print_removed(&args.path, &removed);
+                }
+
+                if args.report_locals {
+                    print_coalesced(&coalesced);
+                }
+
+                std::fs::write("output.wat", wat).unwrap()
+            })
+        }
+        Emit::Ast => parse_program(code).map(|program| print!("{}", program_to_json(&program))),
+        Emit::Js => parse_program(code).map(|program| print!("{}", print_program(&program))),
+        Emit::Symbols => parse_program(code).and_then(|program| {
+            resolve_program(&program, &args.path).map(|table| print!("{}", table))
+        }),
+        Emit::Cfg => parse_program(code).map(|program| {
+            for cfg in build_cfgs(&program.body) {
+                print!("{}", cfg);
+            }
+        }),
+        Emit::CfgDot => {
+            parse_program(code).map(|program| print!("{}", cfgs_to_dot(&build_cfgs(&program.body))))
+        }
+        Emit::Ssa => lower_program(code, &args.path).map(|(program, _, _)| {
+            for (name, function) in codegen::ir_functions(program.body, &options) {
+                match function {
+                    Ok(function) => print!("{}", function),
+                    Err(reason) => println!("; {}: {}", name, reason),
+                }
+            }
+        }),
+        Emit::Effects => lower_program(code, &args.path).map(|(program, _, _)| {
+            for (name, effect) in codegen::func_effects(program.body) {
+                println!("{}: {}", name, effect);
+            }
+        }),
+    };
+
+    match result {
+        Ok(()) => (),
+        Err(errors) => {
+            for error in &errors {
+                print_error(&args.path, error);
+            }
+
+            eprintln!("could not compile due to {} error(s)", errors.len());
+
+            std::process::exit(1);
+        }
+    }
 }
 
-fn load_file(location: PathBuf) -> String {
+fn print_error(path: &Path, error: &CompileError) {
+    eprintln!("{}", error);
+    eprintln!("  --> {}:{}", path.display(), error.span());
+
+    if let Some((name, span)) = error.declared() {
+        eprintln!("note: `{}` is declared here", name);
+        eprintln!("  --> {}:{}", path.display(), span);
+    }
+
+    eprintln!();
+}
+
+fn print_warnings(path: &Path, warnings: &[Warning]) {
+    for warning in warnings {
+        eprintln!("{}", warning);
+        eprintln!("  --> {}:{}", path.display(), warning.span);
+        eprintln!();
+    }
+}
+
+fn print_removed(path: &Path, removed: &[Removed]) {
+    for func in removed {
+        eprintln!("note: removed `{}`, no entry point calls it", func.name);
+        eprintln!("  --> {}:{}", path.display(), func.span);
+        eprintln!();
+    }
+}
+
+fn print_coalesced(coalesced: &[Coalesced]) {
+    for func in coalesced {
+        eprintln!(
+            "note: `{}` needs {} local(s) instead of {}, {} saved",
+            func.func,
+            func.slots,
+            func.locals,
+            func.locals - func.slots
+        );
+    }
+}
+
+fn load_file(location: &PathBuf) -> String {
     std::fs::read_to_string(location).unwrap()
 }
 
-fn js2wat(code: String) -> String {
-    let lexed = lex(code.to_owned());
+fn parse_program(code: String) -> Result<Program, Vec<CompileError>> {
+    parse(lex(code)).map_err(|errors| errors.into_iter().map(CompileError::Parse).collect())
+}
+
+/// The symbol table of a program, its warnings are printed right away
+fn resolve_program(
+    program: &Program,
+    path: &Path,
+) -> Result<analysis::resolve::SymbolTable, Vec<CompileError>> {
+    let (table, warnings) = resolve(program).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(CompileError::Resolve)
+            .collect::<Vec<_>>()
+    })?;
+
+    for warning in warnings {
+        print_error(path, &CompileError::Resolve(warning));
+    }
+
+    Ok(table)
+}
+
+/// The WAT of a program with its warnings, the functions that were left out and the locals
+/// every function saved
+type Compiled = (String, Vec<Warning>, Vec<Removed>, Vec<Coalesced>);
+
+fn js2wat(code: String, path: &Path, options: &Options) -> Result<Compiled, Vec<CompileError>> {
+    let (program, warnings, removed) = lower_program(code, path)?;
+    let (wat, coalesced) = codegen::wat_gen(program.body, options);
+
+    Ok((wat, warnings, removed, coalesced))
+}
+
+/// The program codegen gets, with its warnings and the functions that were left out
+fn lower_program(
+    code: String,
+    path: &Path,
+) -> Result<(Program, Vec<Warning>, Vec<Removed>), Vec<CompileError>> {
+    let mut program = parse_program(code)?;
+
+    // unknown names are reported here instead of as invalid WAT
+    resolve_program(&program, path)?;
+
+    let warnings = remove_unreachable(&mut program);
+    // after the unreachable code is gone, calls in it don't keep functions alive
+    let removed = remove_unused_funcs(&mut program);
+
+    Ok((program, warnings, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_with_missing_or_extra_arguments_compile() {
+        let src = "function k(a, b) {\n\treturn a;\n}\n\nfunction f() {\n\tlet x = k(7);\n\treturn k(x, 2, 3);\n}\n\nf();";
+
+        let (wat, ..) = js2wat(src.to_string(), Path::new("k.js"), &Options::default()).unwrap();
+
+        assert!(wat.contains("call $k@1\n"));
+        assert!(wat.contains("(func $k@1 "));
+    }
+
+    #[test]
+    fn reassigned_function_bindings_are_reported() {
+        let src = "let f = x => x;\nf = x => x + 1;\n\nf(1);";
 
-    let parsed = parse(lexed);
+        let errors = js2wat(src.to_string(), Path::new("f.js"), &Options::default()).unwrap_err();
 
-    codegen::wat_gen(parsed)
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0015]: function binding `f` is reassigned, only bindings that are never reassigned can be compiled"
+        );
+        assert_eq!(errors[0].span().line, 2);
+    }
 }
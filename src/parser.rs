@@ -1,8 +1,12 @@
-use std::iter::Peekable;
+use std::fmt;
 
-use crate::lexer::Token;
+use crate::lexer::{Span, Token, spans};
 
-#[derive(Debug, Clone)]
+pub mod json;
+pub mod printer;
+pub mod visit;
+
+#[derive(Debug, Clone, PartialEq)]
 #[allow(clippy::enum_variant_names)]
 pub enum Stmt {
     FuncDecl(FuncDeclBody),
@@ -12,422 +16,1145 @@ pub enum Stmt {
     RassignStmt(LetStmtBody),
     BinaryStmt(BinaryStmtBody),
     FuncCall(FuncCallStmt),
+    FuncExpr(FuncExprBody),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ReturnStmt {
     BinaryStmtBody(BinaryStmtBody),
     FuncCallStmt(FuncCallStmt),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FuncCallStmt {
     pub function_name: String,
     pub arguments: Vec<Stmt>,
+    // the name of the called function
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct IfStmtBody {
     pub condition: BinaryStmtBody,
     pub if_block: Vec<Stmt>,
     pub if_block_rt_val: Option<ReturnStmt>,
+    // the `if` keyword
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LetStmtBody {
     pub var_name: String,
     pub value: BinaryStmtBody,
+    // the name of the variable
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct WhileStmtBody {
     pub condition: BinaryStmtBody,
     pub block: Vec<Stmt>,
+    // the `while` keyword
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FuncDeclBody {
     pub func_name: String,
-    pub arguments: Vec<Stmt>,
+    pub arguments: Vec<Param>,
     pub return_value: Option<ReturnStmt>,
     pub block: Vec<Stmt>,
+    // the name of the function, or of the binding of a function expression
+    pub span: Span,
+    // `export function f() {..}` or `export const f = ..`, only top level declarations can
+    // be exported
+    pub exported: bool,
 }
 
-#[derive(Debug, Clone)]
+/// A function expression or arrow function bound to a name, `const f = function (a) {..}`,
+/// `let g = (a, b) => a + b` or a plain reassignment `f = (a) => a`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncExprBody {
+    pub var_name: String,
+    // Some(Token::Let) or Some(Token::Const) for declarations, None for a reassignment
+    pub decl: Option<Token>,
+    pub func: FuncDeclBody,
+}
+
+/// A parameter of a function declaration, `a`, `b = 10` or `...xs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub default: Option<BinaryStmtBody>,
+    // collects the remaining arguments into an array
+    pub rest: bool,
+    pub span: Span,
+}
+
+impl From<&str> for Param {
+    fn from(name: &str) -> Self {
+        Param {
+            name: name.to_string(),
+            default: None,
+            rest: false,
+            span: Span::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct BinaryStmtBody {
-    pub lhs: String,
-    pub rhs: Option<String>,
+    pub lhs: Operand,
+    pub rhs: Option<Operand>,
     pub op: Option<Token>,
 }
 
-pub fn parse(token_stream: Vec<Token>) -> Vec<Stmt> {
-    let mut peekable = token_stream.iter().peekable();
+/// An operand of a binary stmt, the span of a variable, index or length is where the name
+/// is in the source
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    // a variable or a number
+    Literal(String, Span),
+    // `xs[i]`
+    Index(String, Box<BinaryStmtBody>, Span),
+    // `xs.length`
+    Length(String, Span),
+    // `f(a, b)`, its result is the value
+    Call(FuncCallStmt),
+    // `true` or `false`
+    Bool(bool),
+    Null,
+    Undefined,
+}
 
-    let mut tree = Vec::new();
+impl From<&str> for Operand {
+    fn from(literal: &str) -> Self {
+        Operand::Literal(literal.to_string(), Span::default())
+    }
+}
 
-    while let Some(token) = peekable.peek() {
-        match token {
-            Token::Function => {
-                peekable.next();
+impl PartialEq<&str> for Operand {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, Operand::Literal(x, _) if x == other)
+    }
+}
 
-                exhaust_whitespace(&mut peekable);
+/// Numbers are literals too, `42` or `0.5`. The parser never makes a negative literal, a
+/// `-` in front comes from constants folded before codegen
+pub fn is_number(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
 
-                if let Some(Token::Literal(fn_name)) = peekable.peek() {
-                    peekable.next();
+    digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
 
-                    assert_token(&mut peekable, &Token::ParenOpen);
+/// A parsed source file
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub body: Vec<Stmt>,
+    // code after a `return` in the same block, the parser skips it
+    pub unreachable: Vec<Span>,
+}
 
-                    let fn_args = parse_fn_arguments(&mut peekable);
+/// Error codes of the parser, the number stays the same once a code is released
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// E0001: a specific token was expected
+    UnexpectedToken,
+    /// E0002: the input ended in the middle of a statement
+    UnexpectedEnd,
+    /// E0003: a function declaration without a name
+    MissingFunctionName,
+    /// E0004: `let` or `const` without a name
+    MissingBindingName,
+    /// E0005: a variable or a number was expected
+    ExpectedOperand,
+    /// E0006: an operator like `+` or `==` was expected
+    ExpectedOperator,
+    /// E0007: only functions can be bound at the top level
+    ExpectedFunctionExpr,
+    /// E0008: a property other than `.length`
+    UnsupportedProperty,
+    /// E0009: a rest parameter followed by another parameter
+    RestParamNotLast,
+    /// E0010: a token that can't start a statement
+    ExpectedStatement,
+}
 
-                    exhaust_whitespace(&mut peekable);
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedToken => "E0001",
+            ErrorCode::UnexpectedEnd => "E0002",
+            ErrorCode::MissingFunctionName => "E0003",
+            ErrorCode::MissingBindingName => "E0004",
+            ErrorCode::ExpectedOperand => "E0005",
+            ErrorCode::ExpectedOperator => "E0006",
+            ErrorCode::ExpectedFunctionExpr => "E0007",
+            ErrorCode::UnsupportedProperty => "E0008",
+            ErrorCode::RestParamNotLast => "E0009",
+            ErrorCode::ExpectedStatement => "E0010",
+        }
+    }
+}
 
-                    assert_token(&mut peekable, &Token::CurlyOpen);
-                    let (parsed_block, ret_val) = parse_block(&mut peekable);
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub code: ErrorCode,
+    pub span: Span,
+    pub expected: String,
+    pub found: String,
+}
 
-                    let fn_body = FuncDeclBody {
-                        func_name: fn_name.clone(),
-                        arguments: fn_args,
-                        return_value: ret_val,
-                        block: parsed_block,
-                    };
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "error[{}]: expected {}, found {}",
+            self.code.as_str(),
+            self.expected,
+            self.found
+        )
+    }
+}
 
-                    tree.push(Stmt::FuncDecl(fn_body));
-                } else {
-                    panic!("Expected function name");
-                }
-            }
-            Token::Literal(potential_func) => {
-                peekable.next();
-                assert_token(&mut peekable, &Token::ParenOpen);
+/// The token stream being parsed and the errors found so far
+struct Parser<'a> {
+    tokens: &'a [Token],
+    spans: Vec<Span>,
+    pos: usize,
+    errors: Vec<ParseError>,
+    unreachable: Vec<Span>,
+}
 
-                let fn_args = parse_fn_arguments(&mut peekable);
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Parser {
+            tokens,
+            spans: spans(tokens),
+            pos: 0,
+            errors: Vec::new(),
+            unreachable: Vec::new(),
+        }
+    }
 
-                let fn_call = FuncCallStmt {
-                    function_name: potential_func.clone(),
-                    arguments: fn_args,
-                };
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
 
-                tree.push(Stmt::FuncCall(fn_call));
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+
+        if token.is_some() {
+            self.pos += 1;
+        }
+
+        token
+    }
+
+    /// Span of the next token
+    fn span(&self) -> Span {
+        self.span_at(self.pos)
+    }
 
-                assert_token(&mut peekable, &Token::SemiColon);
+    /// Span of the token at `pos`, or an empty span at the end of the input
+    fn span_at(&self, pos: usize) -> Span {
+        self.spans.get(pos).copied().unwrap_or_else(|| {
+            let last = self.spans.last().copied().unwrap_or_default();
+
+            Span {
+                start: last.end,
+                col: last.col + (last.end - last.start),
+                ..last
             }
-            Token::Comment => {
-                peekable.next();
+        })
+    }
 
-                while let Some(token) = peekable.peek() {
-                    if let Token::NewLine = token {
-                        break;
-                    } else {
-                        peekable.next();
+    /// An error about the next token
+    fn error(&self, code: ErrorCode, expected: impl Into<String>) -> ParseError {
+        self.error_at(self.pos, code, expected)
+    }
+
+    /// An error about the token at `pos`
+    fn error_at(&self, pos: usize, code: ErrorCode, expected: impl Into<String>) -> ParseError {
+        match self.tokens.get(pos) {
+            Some(token) => ParseError {
+                code,
+                span: self.span_at(pos),
+                expected: expected.into(),
+                found: describe(token),
+            },
+            None => ParseError {
+                code: ErrorCode::UnexpectedEnd,
+                span: self.span_at(pos),
+                expected: expected.into(),
+                found: "end of input".to_string(),
+            },
+        }
+    }
+
+    /// Records an error and skips to the end of the broken statement, that's the next `;` or
+    /// the `}` of a block opened inside of it. A `}` closing the surrounding block is left for
+    /// the block to consume
+    fn recover(&mut self, error: ParseError) {
+        self.errors.push(error);
+
+        let mut depth = 0;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::SemiColon if depth == 0 => {
+                    self.next();
+
+                    return;
+                }
+                Token::CurlyOpen => depth += 1,
+                Token::CurlyClose if depth == 0 => return,
+                Token::CurlyClose => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        self.next();
+
+                        return;
                     }
                 }
+                _ => (),
             }
-            _ => {
-                peekable.next();
-            }
+
+            self.next();
         }
     }
+}
 
-    tree
+fn describe(token: &Token) -> String {
+    match token {
+        Token::NewLine => "a new line".to_string(),
+        Token::WhiteSpace | Token::Tab => "whitespace".to_string(),
+        _ => format!("`{}`", token),
+    }
 }
 
-fn parse_block<'a, I: Iterator<Item = &'a Token>>(
-    peekable: &mut Peekable<I>,
-) -> (Vec<Stmt>, Option<ReturnStmt>) {
-    let mut blocks = Vec::new();
-    let mut ret_val = None;
+pub fn parse(token_stream: Vec<Token>) -> Result<Program, Vec<ParseError>> {
+    let mut parser = Parser::new(&token_stream);
+
+    let mut tree = Vec::new();
 
-    while let Some(token) = peekable.peek() {
+    while let Some(token) = parser.peek() {
         match token {
-            Token::Let => {
-                peekable.next();
+            Token::WhiteSpace | Token::Tab | Token::NewLine | Token::SemiColon => {
+                parser.next();
+            }
+            Token::Comment => skip_comment(&mut parser),
+            _ => match parse_top_level_stmt(&mut parser) {
+                Ok(stmt) => tree.push(stmt),
+                Err(error) => {
+                    parser.recover(error);
+
+                    // a stray `}` would stop recovery forever at the top level
+                    if let Some(Token::CurlyClose) = parser.peek() {
+                        parser.next();
+                    }
+                }
+            },
+        }
+    }
 
-                exhaust_whitespace(peekable);
+    if parser.errors.is_empty() {
+        Ok(Program {
+            body: tree,
+            unreachable: parser.unreachable,
+        })
+    } else {
+        Err(parser.errors)
+    }
+}
 
-                if let Some(Token::Literal(x)) = peekable.peek() {
-                    peekable.next();
+fn parse_top_level_stmt(parser: &mut Parser) -> Result<Stmt, ParseError> {
+    match parser.peek() {
+        Some(Token::Export) => {
+            parser.next();
 
-                    exhaust_whitespace(peekable);
-                    assert_token(peekable, &Token::Eq);
-                    exhaust_whitespace(peekable);
+            exhaust_whitespace(parser);
 
-                    let let_stmt = LetStmtBody {
-                        var_name: x.clone(),
-                        value: parse_binary_stmt(peekable, None),
-                    };
+            let (Some(Token::Function) | Some(Token::Let) | Some(Token::Const)) = parser.peek()
+            else {
+                return Err(
+                    parser.error(ErrorCode::UnexpectedToken, "`function`, `let` or `const`")
+                );
+            };
 
-                    assert_token(peekable, &Token::SemiColon);
+            match parse_top_level_stmt(parser)? {
+                Stmt::FuncDecl(func) => Ok(Stmt::FuncDecl(FuncDeclBody {
+                    exported: true,
+                    ..func
+                })),
+                Stmt::FuncExpr(mut func_expr) => {
+                    func_expr.func.exported = true;
 
-                    blocks.push(Stmt::LetStmt(let_stmt));
-                } else {
-                    panic!("Execpted literal after eq in let stmt");
+                    Ok(Stmt::FuncExpr(func_expr))
                 }
+                _ => unreachable!("`function`, `let` and `const` start a function declaration"),
             }
-            Token::While => {
-                peekable.next();
+        }
+        Some(Token::Function) => {
+            parser.next();
 
-                exhaust_whitespace(peekable);
-                assert_token(peekable, &Token::ParenOpen);
+            exhaust_whitespace(parser);
 
-                let condition = parse_binary_stmt(peekable, None);
+            if let Some(Token::Literal(fn_name)) = parser.peek() {
+                let span = parser.span();
+                parser.next();
 
-                assert_token(peekable, &Token::ParenClose);
+                let fn_body = parse_func_body(parser, fn_name.clone(), span)?;
 
-                exhaust_whitespace(peekable);
-                let while_stmt = WhileStmtBody {
-                    condition,
-                    block: parse_block(peekable).0,
-                };
+                Ok(Stmt::FuncDecl(fn_body))
+            } else {
+                Err(parser.error(ErrorCode::MissingFunctionName, "a function name"))
+            }
+        }
+        Some(Token::Let) | Some(Token::Const) => {
+            let decl = parser.next().cloned();
+
+            exhaust_whitespace(parser);
+
+            if let Some(Token::Literal(var_name)) = parser.peek() {
+                let span = parser.span();
+                parser.next();
+
+                exhaust_whitespace(parser);
+                expect_token(parser, &Token::Eq)?;
+                exhaust_whitespace(parser);
+
+                Ok(Stmt::FuncExpr(parse_func_expr(
+                    parser,
+                    var_name.clone(),
+                    span,
+                    decl,
+                )?))
+            } else {
+                Err(parser.error(ErrorCode::MissingBindingName, "a binding name"))
+            }
+        }
+        Some(Token::Literal(potential_func)) => {
+            let span = parser.span();
+            parser.next();
+            exhaust_whitespace(parser);
+
+            if let Some(Token::Eq) = parser.peek() {
+                parser.next();
+                exhaust_whitespace(parser);
+
+                return Ok(Stmt::FuncExpr(parse_func_expr(
+                    parser,
+                    potential_func.clone(),
+                    span,
+                    None,
+                )?));
+            }
+
+            expect_token(parser, &Token::ParenOpen)?;
+
+            let fn_args = parse_fn_arguments(parser)?;
 
-                blocks.push(Stmt::WhileStmt(while_stmt));
+            let fn_call = FuncCallStmt {
+                function_name: potential_func.clone(),
+                arguments: fn_args,
+                span,
+            };
+
+            expect_token(parser, &Token::SemiColon)?;
+
+            Ok(Stmt::FuncCall(fn_call))
+        }
+        _ => Err(parser.error(
+            ErrorCode::ExpectedStatement,
+            "a function, a binding or a call",
+        )),
+    }
+}
+
+fn skip_comment(parser: &mut Parser) {
+    while let Some(token) = parser.peek() {
+        if let Token::NewLine = token {
+            break;
+        } else {
+            parser.next();
+        }
+    }
+}
+
+/// Parses the parameter list and block of a function, starting at `(`
+fn parse_func_body(
+    parser: &mut Parser,
+    func_name: String,
+    span: Span,
+) -> Result<FuncDeclBody, ParseError> {
+    expect_token(parser, &Token::ParenOpen)?;
+
+    let fn_args = parse_fn_params(parser)?;
+
+    exhaust_whitespace(parser);
+
+    expect_token(parser, &Token::CurlyOpen)?;
+    let (parsed_block, ret_val) = parse_block(parser);
+
+    Ok(FuncDeclBody {
+        func_name,
+        arguments: fn_args,
+        return_value: ret_val,
+        block: parsed_block,
+        span,
+        exported: false,
+    })
+}
+
+/// Parses the function expression on the right of a binding's `=`, the function takes the
+/// name of the variable it's bound to
+fn parse_func_expr(
+    parser: &mut Parser,
+    var_name: String,
+    span: Span,
+    decl: Option<Token>,
+) -> Result<FuncExprBody, ParseError> {
+    let func = match parser.peek() {
+        Some(Token::Function) => {
+            parser.next();
+            exhaust_whitespace(parser);
+
+            // the name of a named function expression is only visible inside of it, calls
+            // go through the binding so we don't keep it around
+            if let Some(Token::Literal(_)) = parser.peek() {
+                parser.next();
+                exhaust_whitespace(parser);
             }
-            Token::Literal(x) => {
-                peekable.next();
 
-                exhaust_whitespace(peekable);
+            parse_func_body(parser, var_name.clone(), span)?
+        }
+        // (a, b) => ..
+        Some(Token::ParenOpen) => {
+            parser.next();
 
-                match peekable.peek() {
-                    Some(Token::Eq) => {
-                        peekable.next();
+            let arguments = parse_fn_params(parser)?;
 
-                        exhaust_whitespace(peekable);
-                        let stmt = Stmt::RassignStmt(LetStmtBody {
-                            var_name: x.clone(),
-                            value: parse_binary_stmt(peekable, None),
-                        });
+            parse_arrow_body(parser, var_name.clone(), span, arguments)?
+        }
+        // a => ..
+        Some(Token::Literal(arg)) => {
+            let start = parser.pos;
+
+            parser.next();
+            exhaust_whitespace(parser);
+
+            if parser.peek() != Some(&Token::Eq) {
+                return Err(parser.error_at(
+                    start,
+                    ErrorCode::ExpectedFunctionExpr,
+                    "a function expression or an arrow function",
+                ));
+            }
 
-                        blocks.push(stmt);
+            let arguments = vec![Param {
+                span: parser.span_at(start),
+                ..Param::from(arg.as_str())
+            }];
 
-                        assert_token(peekable, &Token::SemiColon);
-                    }
-                    Some(Token::ParenOpen) => {
-                        peekable.next();
-                        let args = parse_fn_arguments(peekable);
+            parse_arrow_body(parser, var_name.clone(), span, arguments)?
+        }
+        _ => {
+            return Err(parser.error(
+                ErrorCode::ExpectedFunctionExpr,
+                "a function expression or an arrow function",
+            ));
+        }
+    };
 
-                        blocks.push(Stmt::FuncCall(FuncCallStmt {
-                            function_name: x.clone(),
-                            arguments: args,
-                        }));
+    Ok(FuncExprBody {
+        var_name,
+        decl,
+        func,
+    })
+}
 
-                        assert_token(peekable, &Token::SemiColon);
-                    }
-                    _ => panic!("Unexpected token"),
+/// Parses the `=> a + b;` or `=> { .. }` part of an arrow function
+fn parse_arrow_body(
+    parser: &mut Parser,
+    func_name: String,
+    span: Span,
+    arguments: Vec<Param>,
+) -> Result<FuncDeclBody, ParseError> {
+    exhaust_whitespace(parser);
+    // the lexer has no `=>` token, its an `=` followed by a `>`
+    expect_token(parser, &Token::Eq)?;
+    expect_token(parser, &Token::LessThan)?;
+    exhaust_whitespace(parser);
+
+    if let Some(Token::CurlyOpen) = parser.peek() {
+        parser.next();
+
+        let (block, return_value) = parse_block(parser);
+
+        Ok(FuncDeclBody {
+            func_name,
+            arguments,
+            return_value,
+            block,
+            span,
+            exported: false,
+        })
+    } else {
+        // an expression body is an implicit return
+        let return_value = parse_return_value(parser)?;
+
+        expect_token(parser, &Token::SemiColon)?;
+
+        Ok(FuncDeclBody {
+            func_name,
+            arguments,
+            return_value,
+            block: Vec::new(),
+            span,
+            exported: false,
+        })
+    }
+}
+
+/// Parses the statements of a block up to and including its closing `}`, the opening `{`
+/// should already be consumed
+fn parse_block(parser: &mut Parser) -> (Vec<Stmt>, Option<ReturnStmt>) {
+    parse_stmts(parser, false)
+}
+
+/// Parses the body of an `if` or `while`, either a block or a single statement
+fn parse_stmt_body(parser: &mut Parser) -> (Vec<Stmt>, Option<ReturnStmt>) {
+    exhaust_whitespace(parser);
+
+    if let Some(Token::CurlyOpen) = parser.peek() {
+        parser.next();
+
+        parse_block(parser)
+    } else {
+        parse_stmts(parser, true)
+    }
+}
+
+/// Parses statements until the end of the block, errors are recorded in the parser and the
+/// broken statement is skipped. The code after a `return` is still parsed for its errors and
+/// then dropped, but for the functions declared in it: they're hoisted, the code before the
+/// `return` can call them
+fn parse_stmts(parser: &mut Parser, single: bool) -> (Vec<Stmt>, Option<ReturnStmt>) {
+    let mut blocks = Vec::new();
+    let mut ret_val = None;
+    // whether there was a `return`, what comes after it never runs
+    let mut returned = false;
+    let mut warned = false;
+
+    while let Some(token) = parser.peek() {
+        match token {
+            Token::CurlyClose => {
+                if !single {
+                    parser.next();
                 }
-            }
-            Token::If => {
-                peekable.next();
-                exhaust_whitespace(peekable);
-                assert_token(peekable, &Token::ParenOpen);
 
-                let expr = parse_binary_stmt(peekable, None);
+                return (blocks, ret_val);
+            }
+            Token::WhiteSpace | Token::Tab | Token::NewLine | Token::SemiColon => {
+                parser.next();
 
-                assert_token(peekable, &Token::ParenClose);
+                continue;
+            }
+            Token::Comment => {
+                skip_comment(parser);
 
-                exhaust_whitespace(peekable);
+                continue;
+            }
+            _ => (),
+        }
 
-                let (body, ret_val) = parse_block(peekable);
+        let span = parser.span();
 
-                blocks.push(Stmt::IfStmt(IfStmtBody {
-                    condition: expr,
-                    if_block: body,
-                    if_block_rt_val: ret_val,
-                }));
+        // where the unreachable code starts is kept for the warnings, a function is hoisted
+        // so it isn't unreachable
+        let mut unreachable = |parser: &mut Parser| {
+            if returned && !warned {
+                parser.unreachable.push(span);
+                warned = true;
             }
+        };
+
+        match token {
             Token::Return => {
-                peekable.next();
+                unreachable(parser);
+                parser.next();
 
-                exhaust_whitespace(peekable);
+                exhaust_whitespace(parser);
 
-                if let Some(Token::Literal(val)) = peekable.peek() {
-                    peekable.next();
+                match parse_return_value(parser)
+                    .and_then(|val| expect_token(parser, &Token::SemiColon).map(|_| val))
+                {
+                    Ok(val) if !returned => ret_val = val,
+                    Ok(_) => (),
+                    Err(error) => parser.recover(error),
+                }
 
-                    if let Some(Token::ParenOpen) = peekable.peek() {
-                        peekable.next();
+                if single {
+                    return (blocks, ret_val);
+                }
 
-                        let fn_call = FuncCallStmt {
-                            function_name: val.to_owned(),
-                            arguments: parse_fn_arguments(peekable),
-                        };
+                returned = true;
+            }
+            _ => match parse_stmt(parser) {
+                Ok(stmt @ Stmt::FuncDecl(_)) => blocks.push(stmt),
+                Ok(_) if returned => unreachable(parser),
+                Ok(stmt) => blocks.push(stmt),
+                Err(error) => {
+                    unreachable(parser);
+                    parser.recover(error);
+                }
+            },
+        }
 
-                        ret_val = Some(ReturnStmt::FuncCallStmt(fn_call));
-                    } else {
-                        let stmt = parse_binary_stmt(peekable, Some(val));
+        if single {
+            break;
+        }
+    }
 
-                        ret_val = Some(ReturnStmt::BinaryStmtBody(stmt));
-                    }
-                };
+    // the blocks around this one end here too, one error is enough
+    if !single
+        && parser
+            .errors
+            .last()
+            .is_none_or(|error| error.code != ErrorCode::UnexpectedEnd)
+    {
+        let error = parser.error(ErrorCode::UnexpectedEnd, "`}`");
+        parser.errors.push(error);
+    }
+
+    (blocks, ret_val)
+}
+
+fn parse_stmt(parser: &mut Parser) -> Result<Stmt, ParseError> {
+    match parser.peek() {
+        Some(Token::Function) => {
+            parser.next();
+
+            exhaust_whitespace(parser);
 
-                assert_token(peekable, &Token::SemiColon);
+            if let Some(Token::Literal(fn_name)) = parser.peek() {
+                let span = parser.span();
+                parser.next();
 
-                break;
+                Ok(Stmt::FuncDecl(parse_func_body(
+                    parser,
+                    fn_name.clone(),
+                    span,
+                )?))
+            } else {
+                Err(parser.error(ErrorCode::MissingFunctionName, "a function name"))
             }
-            Token::CurlyClose => break,
-            _ => {
-                peekable.next();
+        }
+        Some(Token::Let) | Some(Token::Const) => {
+            let decl = parser.next().cloned();
+
+            exhaust_whitespace(parser);
+
+            let Some(Token::Literal(x)) = parser.peek() else {
+                return Err(parser.error(ErrorCode::MissingBindingName, "a binding name"));
+            };
+
+            let span = parser.span();
+            parser.next();
+
+            exhaust_whitespace(parser);
+            expect_token(parser, &Token::Eq)?;
+            exhaust_whitespace(parser);
+
+            // only `function` and parenthesized arrows can be told apart from a binary
+            // stmt with a single token of lookahead
+            if let Some(Token::Function) | Some(Token::ParenOpen) = parser.peek() {
+                return Ok(Stmt::FuncExpr(parse_func_expr(
+                    parser,
+                    x.clone(),
+                    span,
+                    decl,
+                )?));
             }
+
+            let let_stmt = LetStmtBody {
+                var_name: x.clone(),
+                value: parse_binary_stmt(parser)?,
+                span,
+            };
+
+            expect_token(parser, &Token::SemiColon)?;
+
+            Ok(Stmt::LetStmt(let_stmt))
+        }
+        Some(Token::While) => {
+            let span = parser.span();
+            parser.next();
+
+            exhaust_whitespace(parser);
+            expect_token(parser, &Token::ParenOpen)?;
+
+            let condition = parse_binary_stmt(parser)?;
+
+            expect_token(parser, &Token::ParenClose)?;
+
+            let while_stmt = WhileStmtBody {
+                condition,
+                block: parse_stmt_body(parser).0,
+                span,
+            };
+
+            Ok(Stmt::WhileStmt(while_stmt))
         }
+        Some(Token::Literal(x)) => {
+            let span = parser.span();
+            parser.next();
+
+            exhaust_whitespace(parser);
+
+            match parser.peek() {
+                Some(Token::Eq) => {
+                    parser.next();
+
+                    exhaust_whitespace(parser);
+                    let stmt = Stmt::RassignStmt(LetStmtBody {
+                        var_name: x.clone(),
+                        value: parse_binary_stmt(parser)?,
+                        span,
+                    });
+
+                    expect_token(parser, &Token::SemiColon)?;
+
+                    Ok(stmt)
+                }
+                Some(Token::ParenOpen) => {
+                    parser.next();
+                    let args = parse_fn_arguments(parser)?;
+
+                    expect_token(parser, &Token::SemiColon)?;
+
+                    Ok(Stmt::FuncCall(FuncCallStmt {
+                        function_name: x.clone(),
+                        arguments: args,
+                        span,
+                    }))
+                }
+                _ => {
+                    Err(parser.error(ErrorCode::UnexpectedToken, "`=` or `(` after an identifier"))
+                }
+            }
+        }
+        Some(Token::If) => {
+            let span = parser.span();
+            parser.next();
+            exhaust_whitespace(parser);
+            expect_token(parser, &Token::ParenOpen)?;
+
+            let expr = parse_binary_stmt(parser)?;
+
+            expect_token(parser, &Token::ParenClose)?;
+
+            let (body, ret_val) = parse_stmt_body(parser);
+
+            Ok(Stmt::IfStmt(IfStmtBody {
+                condition: expr,
+                if_block: body,
+                if_block_rt_val: ret_val,
+                span,
+            }))
+        }
+        _ => Err(parser.error(ErrorCode::ExpectedStatement, "a statement")),
     }
+}
 
-    peekable.next();
+fn parse_return_value(parser: &mut Parser) -> Result<Option<ReturnStmt>, ParseError> {
+    if let None | Some(Token::SemiColon) = parser.peek() {
+        return Ok(None);
+    }
 
-    (blocks, ret_val)
+    Ok(Some(bare_call(parse_binary_stmt(parser)?)))
 }
 
-fn parse_fn_arguments<'a, I: Iterator<Item = &'a Token>>(peekable: &mut Peekable<I>) -> Vec<Stmt> {
-    let mut fn_args = Vec::new();
+/// A call on its own is kept as a call so it can be told apart from a call inside of an
+/// expression
+fn bare_call(stmt: BinaryStmtBody) -> ReturnStmt {
+    match stmt {
+        BinaryStmtBody {
+            lhs: Operand::Call(fn_call),
+            op: None,
+            ..
+        } => ReturnStmt::FuncCallStmt(fn_call),
+        stmt => ReturnStmt::BinaryStmtBody(stmt),
+    }
+}
 
-    while let Some(token) = peekable.peek() {
+/// Parses the parameters of a function declaration up to and including the `)`
+fn parse_fn_params(parser: &mut Parser) -> Result<Vec<Param>, ParseError> {
+    let mut params = Vec::new();
+    let mut rest = false;
+
+    while let Some(token) = parser.peek() {
         match token {
-            Token::Literal(val) => {
-                peekable.next();
-                exhaust_whitespace(peekable);
+            // the lexer has no `...` token
+            Token::Dot => {
+                parser.next();
+                expect_token(parser, &Token::Dot)?;
+                expect_token(parser, &Token::Dot)?;
+
+                rest = true;
+            }
+            Token::Literal(name) => {
+                if params.last().is_some_and(|param: &Param| param.rest) {
+                    return Err(parser.error(ErrorCode::RestParamNotLast, "`)`"));
+                }
 
-                if let Some(Token::ParenOpen) = peekable.peek() {
-                    peekable.next();
+                let span = parser.span();
+                parser.next();
+                exhaust_whitespace(parser);
 
-                    let fn_call = FuncCallStmt {
-                        function_name: val.to_owned(),
-                        arguments: parse_fn_arguments(peekable),
-                    };
+                let default = if let Some(Token::Eq) = parser.peek() {
+                    parser.next();
+                    exhaust_whitespace(parser);
 
-                    fn_args.push(Stmt::FuncCall(fn_call));
+                    Some(parse_binary_stmt(parser)?)
                 } else {
-                    let stmt = parse_binary_stmt(peekable, Some(val));
-                    fn_args.push(Stmt::BinaryStmt(stmt));
-                }
+                    None
+                };
+
+                params.push(Param {
+                    name: name.clone(),
+                    default,
+                    rest,
+                    span,
+                });
             }
             Token::Comma | Token::WhiteSpace => {
-                peekable.next();
+                parser.next();
             }
             Token::ParenClose => {
-                peekable.next();
+                parser.next();
 
-                break;
+                return Ok(params);
             }
-            _ => panic!("Unexpected token in function decl"),
+            _ => return Err(parser.error(ErrorCode::UnexpectedToken, "a parameter or `)`")),
         }
     }
 
-    fn_args
+    Err(parser.error(ErrorCode::UnexpectedEnd, "`)`"))
 }
 
-fn parse_binary_stmt<'a, I: Iterator<Item = &'a Token>>(
-    peekable: &mut Peekable<I>,
-    lhs: Option<&String>,
-) -> BinaryStmtBody {
-    fn parse_op_rhs<'b, I: Iterator<Item = &'b Token>>(
-        x: &str,
-        peekable: &mut Peekable<I>,
-    ) -> BinaryStmtBody {
+fn parse_fn_arguments(parser: &mut Parser) -> Result<Vec<Stmt>, ParseError> {
+    let mut fn_args = Vec::new();
+
+    while let Some(token) = parser.peek() {
+        match token {
+            token if starts_value(token) => match bare_call(parse_binary_stmt(parser)?) {
+                ReturnStmt::FuncCallStmt(fn_call) => fn_args.push(Stmt::FuncCall(fn_call)),
+                ReturnStmt::BinaryStmtBody(stmt) => fn_args.push(Stmt::BinaryStmt(stmt)),
+            },
+            Token::Comma | Token::WhiteSpace => {
+                parser.next();
+            }
+            Token::ParenClose => {
+                parser.next();
+
+                return Ok(fn_args);
+            }
+            _ => return Err(parser.error(ErrorCode::UnexpectedToken, "an argument or `)`")),
+        }
+    }
+
+    Err(parser.error(ErrorCode::UnexpectedEnd, "`)`"))
+}
+
+fn parse_binary_stmt(parser: &mut Parser) -> Result<BinaryStmtBody, ParseError> {
+    fn parse_op_rhs(x: Operand, parser: &mut Parser) -> Result<BinaryStmtBody, ParseError> {
         // if its a single value
-        if let Some(Token::Comma) | Some(Token::ParenClose) = peekable.peek() {
-            return BinaryStmtBody {
-                lhs: x.to_string(),
+        if let Some(Token::Comma) | Some(Token::ParenClose) | Some(Token::BracketClose) =
+            parser.peek()
+        {
+            return Ok(BinaryStmtBody {
+                lhs: x,
                 rhs: None,
                 op: None,
-            };
+            });
         }
 
-        exhaust_whitespace(peekable);
-
-        let op = if let Some(token) = peekable.peek() {
-            match token {
-                Token::SemiColon => {
-                    return BinaryStmtBody {
-                        lhs: x.to_string(),
-                        rhs: None,
-                        op: None,
-                    }
-                }
-                Token::Exclaim => {
-                    peekable.next();
+        exhaust_whitespace(parser);
 
-                    assert_token(peekable, &Token::Eq);
+        let op = match parser.peek() {
+            None | Some(Token::SemiColon) | Some(Token::NewLine) => {
+                return Ok(BinaryStmtBody {
+                    lhs: x,
+                    rhs: None,
+                    op: None,
+                });
+            }
+            Some(Token::Exclaim) => {
+                parser.next();
 
-                    Some(Token::NotEq)
-                }
-                Token::Eq => {
-                    peekable.next();
+                expect_token(parser, &Token::Eq)?;
 
-                    if let Some(Token::Eq) = peekable.peek() {
-                        peekable.next();
+                Token::NotEq
+            }
+            Some(Token::Eq) => {
+                parser.next();
 
-                        Some(Token::DoubleEq)
-                    } else {
-                        Some(Token::Eq)
-                    }
-                }
-                _ => {
-                    let op = peekable.next().unwrap();
+                expect_token(parser, &Token::Eq)?;
 
-                    Some(op.clone())
-                }
+                Token::DoubleEq
             }
-        } else {
-            None
+            Some(
+                token @ (Token::Add
+                | Token::Sub
+                | Token::Mul
+                | Token::Percent
+                | Token::GreaterThan
+                | Token::LessThan
+                | Token::DoubleEq
+                | Token::NotEq),
+            ) => {
+                parser.next();
+
+                token.clone()
+            }
+            Some(_) => return Err(parser.error(ErrorCode::ExpectedOperator, "an operator")),
         };
 
-        exhaust_whitespace(peekable);
+        exhaust_whitespace(parser);
 
-        let rhs = if let Some(Token::Literal(y)) = peekable.peek() {
-            peekable.next();
+        let rhs = parse_value(parser)?;
 
-            Some(y.clone())
-        } else {
-            None
-        };
+        Ok(BinaryStmtBody {
+            lhs: x,
+            rhs: Some(rhs),
+            op: Some(op),
+        })
+    }
 
-        BinaryStmtBody {
-            lhs: x.to_string(),
-            rhs,
-            op,
+    let lhs = parse_value(parser)?;
+
+    parse_op_rhs(lhs, parser)
+}
+
+/// Tokens an operand can start with
+fn starts_value(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Literal(_) | Token::True | Token::False | Token::Null | Token::Undefined
+    )
+}
+
+/// Parses a single operand, a variable, a number, a keyword literal, an index, a `.length`
+/// or a call
+fn parse_value(parser: &mut Parser) -> Result<Operand, ParseError> {
+    let operand = match parser.peek() {
+        Some(Token::Literal(x)) => {
+            let span = parser.span();
+            parser.next();
+
+            return parse_operand(parser, x, span);
         }
-    }
+        Some(Token::True) => Operand::Bool(true),
+        Some(Token::False) => Operand::Bool(false),
+        Some(Token::Null) => Operand::Null,
+        Some(Token::Undefined) => Operand::Undefined,
+        _ => return Err(parser.error(ErrorCode::ExpectedOperand, "a variable or a number")),
+    };
 
-    if let Some(Token::Literal(x)) = peekable.peek() {
-        peekable.next();
+    parser.next();
 
-        return parse_op_rhs(x, peekable);
-    } else if let Some(passed_lhs) = lhs {
-        return parse_op_rhs(passed_lhs, peekable);
-    }
+    Ok(operand)
+}
+
+/// Parses what follows a literal that's used as an operand, `[i]`, `.length` or the
+/// arguments of a call
+fn parse_operand(parser: &mut Parser, literal: &str, span: Span) -> Result<Operand, ParseError> {
+    match parser.peek() {
+        Some(Token::ParenOpen) => {
+            parser.next();
+
+            Ok(Operand::Call(FuncCallStmt {
+                function_name: literal.to_string(),
+                arguments: parse_fn_arguments(parser)?,
+                span,
+            }))
+        }
+        Some(Token::BracketOpen) => {
+            parser.next();
+            exhaust_whitespace(parser);
+
+            let index = parse_binary_stmt(parser)?;
 
-    panic!("Invalid binary stmt");
+            exhaust_whitespace(parser);
+            expect_token(parser, &Token::BracketClose)?;
+
+            Ok(Operand::Index(literal.to_string(), Box::new(index), span))
+        }
+        Some(Token::Dot) => {
+            parser.next();
+
+            match parser.peek() {
+                // the lexer splits `0.5` at the dot
+                Some(Token::Literal(fraction)) if is_number(literal) && is_number(fraction) => {
+                    parser.next();
+
+                    Ok(Operand::Literal(format!("{}.{}", literal, fraction), span))
+                }
+                Some(Token::Literal(property)) if property == "length" => {
+                    parser.next();
+
+                    Ok(Operand::Length(literal.to_string(), span))
+                }
+                _ => Err(parser.error(ErrorCode::UnsupportedProperty, "`length`")),
+            }
+        }
+        _ => Ok(Operand::Literal(literal.to_string(), span)),
+    }
 }
 
-fn exhaust_whitespace<'a, I: Iterator<Item = &'a Token>>(
-    peekable: &mut Peekable<I>,
-) -> &mut Peekable<I> {
+fn exhaust_whitespace<'a, 'b>(parser: &'a mut Parser<'b>) -> &'a mut Parser<'b> {
     // skip both space and tabs
-    while let Some(Token::WhiteSpace) | Some(Token::Tab) = peekable.peek() {
-        peekable.next();
+    while let Some(Token::WhiteSpace) | Some(Token::Tab) = parser.peek() {
+        parser.next();
     }
 
-    peekable
+    parser
 }
 
-fn assert_token<'a, I: Iterator<Item = &'a Token>>(peekable: &mut Peekable<I>, token: &Token) {
-    if let Some(assert_token) = peekable.next() {
-        if assert_token != token {
-            panic!("Expected: {:?} token, found: {:?} ", token, assert_token);
-        }
+fn expect_token(parser: &mut Parser, token: &Token) -> Result<(), ParseError> {
+    if parser.peek() == Some(token) {
+        parser.next();
+
+        Ok(())
     } else {
-        panic!("Expected token found None");
+        Err(parser.error(ErrorCode::UnexpectedToken, format!("`{}`", token)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::Token;
+    use crate::lexer::{Token, lex};
 
     fn t(s: &str) -> Token {
         Token::Literal(s.to_string())
     }
 
+    fn parse_src(src: &str) -> Vec<Stmt> {
+        parse(lex(src.to_string())).unwrap().body
+    }
+
+    fn parse_errors(src: &str) -> Vec<ParseError> {
+        parse(lex(src.to_string())).unwrap_err()
+    }
+
     #[test]
     fn parses_empty_input() {
-        let ast = parse(vec![]);
+        let ast = parse(vec![]).unwrap().body;
         assert!(ast.is_empty());
     }
 
@@ -443,7 +1170,7 @@ mod tests {
             Token::CurlyClose,
         ];
 
-        let ast = parse(tokens);
+        let ast = parse(tokens).unwrap().body;
 
         assert_eq!(ast.len(), 1);
 
@@ -482,7 +1209,7 @@ mod tests {
             Token::CurlyClose,
         ];
 
-        let ast = parse(tokens);
+        let ast = parse(tokens).unwrap().body;
 
         match &ast[0] {
             Stmt::FuncDecl(body) => {
@@ -520,7 +1247,7 @@ mod tests {
             Token::SemiColon,
         ];
 
-        let ast = parse(tokens);
+        let ast = parse(tokens).unwrap().body;
 
         assert_eq!(ast.len(), 1);
 
@@ -555,15 +1282,15 @@ mod tests {
             Token::CurlyClose,
         ];
 
-        let mut it = tokens.iter().peekable();
-        let (block, _) = parse_block(&mut it);
+        let mut parser = Parser::new(&tokens);
+        let (block, _) = parse_block(&mut parser);
 
         assert_eq!(block.len(), 1);
 
         match &block[0] {
             Stmt::WhileStmt(while_stmt) => {
                 assert_eq!(while_stmt.condition.lhs, "x");
-                assert_eq!(while_stmt.condition.rhs.as_deref(), Some("10"));
+                assert_eq!(*while_stmt.condition.rhs.as_ref().unwrap(), "10");
                 assert_eq!(while_stmt.block.len(), 1);
             }
             _ => panic!("Expected WhileStmt"),
@@ -588,15 +1315,15 @@ mod tests {
             Token::CurlyClose,
         ];
 
-        let mut it = tokens.iter().peekable();
-        let (block, _) = parse_block(&mut it);
+        let mut parser = Parser::new(&tokens);
+        let (block, _) = parse_block(&mut parser);
 
         assert_eq!(block.len(), 1);
 
         match &block[0] {
             Stmt::IfStmt(if_stmt) => {
                 assert_eq!(if_stmt.condition.lhs, "x");
-                assert_eq!(if_stmt.condition.rhs.as_deref(), Some("0"));
+                assert_eq!(*if_stmt.condition.rhs.as_ref().unwrap(), "0");
                 assert!(if_stmt.if_block.is_empty());
                 assert!(if_stmt.if_block_rt_val.is_some());
             }
@@ -608,11 +1335,363 @@ mod tests {
     fn parses_binary_expression() {
         let tokens = [t("a"), Token::Add, t("b")];
 
-        let mut it = tokens.iter().peekable();
-        let expr = parse_binary_stmt(&mut it, None);
+        let mut parser = Parser::new(&tokens);
+        let expr = parse_binary_stmt(&mut parser).unwrap();
 
         assert_eq!(expr.lhs, "a");
-        assert_eq!(expr.rhs.as_deref(), Some("b"));
+        assert_eq!(*expr.rhs.as_ref().unwrap(), "b");
         assert_eq!(expr.op, Some(Token::Add));
     }
+
+    #[test]
+    fn parses_function_expression() {
+        let ast = parse_src("const sq = function (a) {\n\treturn a * a;\n};");
+
+        assert_eq!(ast.len(), 1);
+
+        match &ast[0] {
+            Stmt::FuncExpr(expr) => {
+                assert_eq!(expr.var_name, "sq");
+                assert_eq!(expr.decl, Some(Token::Const));
+                assert_eq!(expr.func.func_name, "sq");
+                assert_eq!(expr.func.arguments.len(), 1);
+
+                match &expr.func.return_value {
+                    Some(ReturnStmt::BinaryStmtBody(bin)) => {
+                        assert_eq!(bin.lhs, "a");
+                        assert_eq!(bin.op, Some(Token::Mul));
+                    }
+                    _ => panic!("Expected return binary stmt"),
+                }
+            }
+            _ => panic!("Expected FuncExpr"),
+        }
+    }
+
+    #[test]
+    fn parses_arrow_functions() {
+        let ast = parse_src(
+            "const add = (a, b) => a + b;\nlet id = x => x;\nconst f = () => {\n\tlet y = 1;\n\treturn y;\n};",
+        );
+
+        assert_eq!(ast.len(), 3);
+
+        match &ast[0] {
+            Stmt::FuncExpr(expr) => {
+                assert_eq!(expr.func.func_name, "add");
+                assert_eq!(expr.func.arguments.len(), 2);
+                assert!(expr.func.block.is_empty());
+
+                match &expr.func.return_value {
+                    Some(ReturnStmt::BinaryStmtBody(bin)) => {
+                        assert_eq!(bin.lhs, "a");
+                        assert_eq!(*bin.rhs.as_ref().unwrap(), "b");
+                        assert_eq!(bin.op, Some(Token::Add));
+                    }
+                    _ => panic!("Expected return binary stmt"),
+                }
+            }
+            _ => panic!("Expected FuncExpr"),
+        }
+
+        match &ast[1] {
+            Stmt::FuncExpr(expr) => {
+                assert_eq!(expr.decl, Some(Token::Let));
+                assert_eq!(expr.func.arguments.len(), 1);
+            }
+            _ => panic!("Expected FuncExpr"),
+        }
+
+        match &ast[2] {
+            Stmt::FuncExpr(expr) => {
+                assert!(expr.func.arguments.is_empty());
+                assert_eq!(expr.func.block.len(), 1);
+                assert!(expr.func.return_value.is_some());
+            }
+            _ => panic!("Expected FuncExpr"),
+        }
+    }
+
+    #[test]
+    fn parses_function_binding_reassignment() {
+        let ast = parse_src("let f = x => x;\nf = x => x + 1;");
+
+        match &ast[1] {
+            Stmt::FuncExpr(expr) => {
+                assert_eq!(expr.var_name, "f");
+                assert!(expr.decl.is_none());
+            }
+            _ => panic!("Expected FuncExpr"),
+        }
+    }
+
+    #[test]
+    fn parses_default_and_rest_params() {
+        let ast = parse_src("function f(a, b = a + 1, ...xs) {\n\treturn xs[b];\n}");
+
+        match &ast[0] {
+            Stmt::FuncDecl(body) => {
+                assert_eq!(body.arguments.len(), 3);
+                assert_eq!(body.arguments[0].name, "a");
+                assert!(body.arguments[0].default.is_none());
+
+                let default = body.arguments[1].default.as_ref().unwrap();
+                assert_eq!(default.lhs, "a");
+                assert_eq!(*default.rhs.as_ref().unwrap(), "1");
+                assert_eq!(default.op, Some(Token::Add));
+
+                assert_eq!(body.arguments[2].name, "xs");
+                assert!(body.arguments[2].rest);
+
+                match &body.return_value {
+                    Some(ReturnStmt::BinaryStmtBody(bin)) => match &bin.lhs {
+                        Operand::Index(array, index, _) => {
+                            assert_eq!(array, "xs");
+                            assert_eq!(index.lhs, "b");
+                        }
+                        _ => panic!("Expected index operand"),
+                    },
+                    _ => panic!("Expected return binary stmt"),
+                }
+            }
+            _ => panic!("Expected FuncDecl"),
+        }
+    }
+
+    #[test]
+    fn parses_length_operand() {
+        let tokens = [t("i"), Token::GreaterThan, t("xs"), Token::Dot, t("length")];
+
+        let mut parser = Parser::new(&tokens);
+        let expr = parse_binary_stmt(&mut parser).unwrap();
+
+        assert!(matches!(&expr.rhs, Some(Operand::Length(array, _)) if array == "xs"));
+    }
+
+    #[test]
+    fn parses_calls_as_operands() {
+        let ast =
+            parse_src("function f(n) {\n\tlet x = g(n) + h(1, n);\n\treturn n * f(n - 1);\n}");
+
+        let Stmt::FuncDecl(func) = &ast[0] else {
+            panic!("expected a function");
+        };
+
+        let Stmt::LetStmt(let_stmt) = &func.block[0] else {
+            panic!("expected a let");
+        };
+
+        assert!(matches!(&let_stmt.value.lhs, Operand::Call(call) if call.function_name == "g"));
+        assert!(
+            matches!(&let_stmt.value.rhs, Some(Operand::Call(call)) if call.arguments.len() == 2)
+        );
+
+        let Some(ReturnStmt::BinaryStmtBody(ret)) = &func.return_value else {
+            panic!("expected a binary return value");
+        };
+
+        assert_eq!(ret.lhs, "n");
+        assert!(matches!(&ret.rhs, Some(Operand::Call(call)) if call.function_name == "f"));
+    }
+
+    #[test]
+    fn parses_decimals() {
+        let stmts = parse_src("let half = (n) => n * 0.5;");
+
+        let Stmt::FuncExpr(FuncExprBody { func, .. }) = &stmts[0] else {
+            panic!("expected a function expression");
+        };
+
+        let Some(ReturnStmt::BinaryStmtBody(ret)) = &func.return_value else {
+            panic!("expected a return value");
+        };
+
+        assert_eq!(*ret.rhs.as_ref().unwrap(), "0.5");
+        assert!(is_number("0.5"));
+        assert!(!is_number("xs"));
+    }
+
+    #[test]
+    fn parses_value_keywords() {
+        let ast =
+            parse_src("function f(n) {\n\tif (true) return null;\n\treturn n != undefined;\n}");
+
+        let Stmt::FuncDecl(func) = &ast[0] else {
+            panic!("expected a function");
+        };
+
+        let Stmt::IfStmt(if_stmt) = &func.block[0] else {
+            panic!("expected an if");
+        };
+
+        assert_eq!(if_stmt.condition.lhs, Operand::Bool(true));
+        assert!(matches!(
+            &if_stmt.if_block_rt_val,
+            Some(ReturnStmt::BinaryStmtBody(BinaryStmtBody {
+                lhs: Operand::Null,
+                ..
+            }))
+        ));
+
+        let Some(ReturnStmt::BinaryStmtBody(ret)) = &func.return_value else {
+            panic!("expected a binary return value");
+        };
+
+        assert_eq!(ret.rhs, Some(Operand::Undefined));
+    }
+
+    #[test]
+    fn keeps_bare_calls_as_calls() {
+        let ast = parse_src("function f(n) {\n\treturn g(h(n), n + 1);\n}");
+
+        let Stmt::FuncDecl(func) = &ast[0] else {
+            panic!("expected a function");
+        };
+
+        let Some(ReturnStmt::FuncCallStmt(call)) = &func.return_value else {
+            panic!("expected a call");
+        };
+
+        assert!(matches!(&call.arguments[0], Stmt::FuncCall(_)));
+        assert!(matches!(&call.arguments[1], Stmt::BinaryStmt(_)));
+    }
+
+    #[test]
+    fn reports_every_error_in_one_run() {
+        let errors = parse_errors(
+            "function f(a) {\n\tlet x = a +;\n\tlet y = 2\n\treturn x;\n}\n\nfoo(1;\nlet g = 4;\n",
+        );
+
+        let codes: Vec<_> = errors.iter().map(|e| e.code).collect();
+
+        assert_eq!(
+            codes,
+            [
+                ErrorCode::ExpectedOperand,
+                ErrorCode::UnexpectedToken,
+                ErrorCode::UnexpectedToken,
+                ErrorCode::ExpectedFunctionExpr,
+            ]
+        );
+
+        assert_eq!(errors[0].span.line, 2);
+        assert_eq!(errors[0].span.col, 13);
+        assert_eq!(errors[1].expected, "`;`");
+        assert_eq!(errors[1].found, "a new line");
+        assert_eq!(
+            errors[3].to_string(),
+            "error[E0007]: expected a function expression or an arrow function, found `4`"
+        );
+    }
+
+    #[test]
+    fn recovers_at_end_of_block() {
+        // the broken `if` is skipped up to its `}`, the function after it still parses
+        let errors = parse_errors(
+            "function f(a) {\n\tif (a ! 1) {\n\t\ta = 1;\n\t}\n\treturn a;\n}\n\nfunction g( {\n}\n",
+        );
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, ErrorCode::UnexpectedToken);
+        assert_eq!(errors[0].span.line, 2);
+        assert_eq!(errors[1].code, ErrorCode::UnexpectedToken);
+        assert_eq!(errors[1].span.line, 8);
+    }
+
+    #[test]
+    fn reports_unexpected_end() {
+        let errors = parse_errors("function f(a, b");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, ErrorCode::UnexpectedEnd);
+        assert_eq!(errors[0].found, "end of input");
+        assert_eq!(errors[0].code.as_str(), "E0002");
+    }
+
+    #[test]
+    fn reports_unclosed_blocks() {
+        let errors = parse_errors("function f(a) {\n\tlet x = 1;\n\treturn x;\n");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, ErrorCode::UnexpectedEnd);
+        assert_eq!(errors[0].expected, "`}`");
+
+        // the function isn't closed either, it's the same missing `}`
+        let errors = parse_errors("function f(a) {\n\twhile (a > 0) {\n\t\ta = a - 1;\n");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, ErrorCode::UnexpectedEnd);
+    }
+
+    #[test]
+    fn reports_errors_after_return() {
+        let errors = parse_errors(
+            "function f(a) {\n\treturn 1;\n\tlet x = ;\n\treturn a +;\n}\n\nfunction g() {\n\treturn 2\n}\n",
+        );
+
+        let found: Vec<_> = errors.iter().map(|e| (e.code, e.span.line)).collect();
+
+        assert_eq!(
+            found,
+            [
+                (ErrorCode::ExpectedOperand, 3),
+                (ErrorCode::ExpectedOperand, 4),
+                (ErrorCode::UnexpectedToken, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_functions_declared_after_return() {
+        let program = parse(lex(
+            "function f() {\n\treturn g();\n\tfunction g() {\n\t\treturn 1;\n\t}\n\tlet x = 1;\n}\n"
+                .to_string(),
+        ))
+        .unwrap();
+
+        let Stmt::FuncDecl(f) = &program.body[0] else {
+            panic!("expected a function");
+        };
+
+        assert_eq!(f.block.len(), 1);
+        assert!(matches!(&f.block[0], Stmt::FuncDecl(g) if g.func_name == "g"));
+        // only the `let` is unreachable
+        assert_eq!(program.unreachable.len(), 1);
+        assert_eq!(program.unreachable[0].line, 6);
+    }
+
+    #[test]
+    fn rejects_stray_tokens() {
+        let errors = parse_errors("}\nfunction f() {\n\telse;\n}\n");
+
+        assert_eq!(errors.len(), 2);
+        assert!(
+            errors
+                .iter()
+                .all(|e| e.code == ErrorCode::ExpectedStatement)
+        );
+        assert_eq!(errors[1].found, "`else`");
+    }
+
+    #[test]
+    fn parses_exported_functions() {
+        let ast = parse_src("export function f(a) {\n\treturn a;\n}\n\nfunction g() {\n}\n");
+
+        assert!(matches!(&ast[0], Stmt::FuncDecl(func) if func.exported && func.func_name == "f"));
+        assert!(matches!(&ast[1], Stmt::FuncDecl(func) if !func.exported));
+
+        let ast = parse_src("export const f = (a) => a;\n");
+
+        assert!(
+            matches!(&ast[0], Stmt::FuncExpr(expr) if expr.func.exported && expr.var_name == "f")
+        );
+
+        let errors = parse_errors("export f = (a) => a;\n");
+
+        assert_eq!(errors[0].code, ErrorCode::UnexpectedToken);
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0001]: expected `function`, `let` or `const`, found `f`"
+        );
+    }
 }
@@ -0,0 +1,331 @@
+//! JSON dump of the syntax tree for `--emit ast`.
+//!
+//! Every node is an object with a `"type"` field first and the rest of its fields in a fixed
+//! order, optional fields are `null` instead of being left out, so the output only changes
+//! when the tree does.
+
+use super::{
+    BinaryStmtBody, FuncCallStmt, FuncDeclBody, FuncExprBody, LetStmtBody, Operand, Param, Program,
+    ReturnStmt, Stmt,
+};
+
+enum Json {
+    Null,
+    Bool(bool),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn node(kind: &str, fields: Vec<(&'static str, Json)>) -> Json {
+        let mut object = vec![("type", Json::Str(kind.to_string()))];
+        object.extend(fields);
+
+        Json::Object(object)
+    }
+
+    fn str(value: &str) -> Json {
+        Json::Str(value.to_string())
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Str(value) => write_str(out, value),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push('[');
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+
+                    newline(out, indent + 1);
+                    item.write(out, indent + 1);
+                }
+
+                newline(out, indent);
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+
+                    newline(out, indent + 1);
+                    write_str(out, key);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                }
+
+                newline(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+fn write_str(out: &mut String, value: &str) {
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+/// Pretty printed JSON of the whole program, ends with a newline
+pub fn program_to_json(program: &Program) -> String {
+    let mut out = String::new();
+
+    Json::node("Program", vec![("body", block_json(&program.body))]).write(&mut out, 0);
+    out.push('\n');
+
+    out
+}
+
+fn block_json(block: &[Stmt]) -> Json {
+    Json::Array(block.iter().map(stmt_json).collect())
+}
+
+fn stmt_json(stmt: &Stmt) -> Json {
+    match stmt {
+        Stmt::FuncDecl(func) => func_decl_json(func),
+        Stmt::WhileStmt(while_stmt) => Json::node(
+            "While",
+            vec![
+                ("condition", binary_stmt_json(&while_stmt.condition)),
+                ("body", block_json(&while_stmt.block)),
+            ],
+        ),
+        Stmt::IfStmt(if_stmt) => Json::node(
+            "If",
+            vec![
+                ("condition", binary_stmt_json(&if_stmt.condition)),
+                ("body", block_json(&if_stmt.if_block)),
+                ("return", return_json(if_stmt.if_block_rt_val.as_ref())),
+            ],
+        ),
+        Stmt::LetStmt(let_stmt) => let_json("Let", let_stmt),
+        Stmt::RassignStmt(reassign) => let_json("Assign", reassign),
+        Stmt::BinaryStmt(binary_stmt) => binary_stmt_json(binary_stmt),
+        Stmt::FuncCall(call) => func_call_json(call),
+        Stmt::FuncExpr(func_expr) => func_expr_json(func_expr),
+    }
+}
+
+fn func_decl_json(func: &FuncDeclBody) -> Json {
+    Json::node(
+        "FuncDecl",
+        vec![
+            ("name", Json::str(&func.func_name)),
+            ("exported", Json::Bool(func.exported)),
+            (
+                "params",
+                Json::Array(func.arguments.iter().map(param_json).collect()),
+            ),
+            ("body", block_json(&func.block)),
+            ("return", return_json(func.return_value.as_ref())),
+        ],
+    )
+}
+
+fn func_expr_json(func_expr: &FuncExprBody) -> Json {
+    Json::node(
+        "FuncExpr",
+        vec![
+            ("name", Json::str(&func_expr.var_name)),
+            (
+                "decl",
+                match &func_expr.decl {
+                    Some(decl) => Json::str(decl.as_str()),
+                    None => Json::Null,
+                },
+            ),
+            ("func", func_decl_json(&func_expr.func)),
+        ],
+    )
+}
+
+fn param_json(param: &Param) -> Json {
+    Json::node(
+        "Param",
+        vec![
+            ("name", Json::str(&param.name)),
+            (
+                "default",
+                param.default.as_ref().map_or(Json::Null, binary_stmt_json),
+            ),
+            ("rest", Json::Bool(param.rest)),
+        ],
+    )
+}
+
+fn let_json(kind: &str, let_stmt: &LetStmtBody) -> Json {
+    Json::node(
+        kind,
+        vec![
+            ("name", Json::str(&let_stmt.var_name)),
+            ("value", binary_stmt_json(&let_stmt.value)),
+        ],
+    )
+}
+
+fn return_json(return_stmt: Option<&ReturnStmt>) -> Json {
+    match return_stmt {
+        Some(ReturnStmt::BinaryStmtBody(binary_stmt)) => binary_stmt_json(binary_stmt),
+        Some(ReturnStmt::FuncCallStmt(call)) => func_call_json(call),
+        None => Json::Null,
+    }
+}
+
+fn func_call_json(call: &FuncCallStmt) -> Json {
+    Json::node(
+        "Call",
+        vec![
+            ("callee", Json::str(&call.function_name)),
+            ("arguments", block_json(&call.arguments)),
+        ],
+    )
+}
+
+fn binary_stmt_json(binary_stmt: &BinaryStmtBody) -> Json {
+    Json::node(
+        "Binary",
+        vec![
+            ("lhs", operand_json(&binary_stmt.lhs)),
+            (
+                "op",
+                match &binary_stmt.op {
+                    Some(op) => Json::str(op.as_str()),
+                    None => Json::Null,
+                },
+            ),
+            (
+                "rhs",
+                binary_stmt.rhs.as_ref().map_or(Json::Null, operand_json),
+            ),
+        ],
+    )
+}
+
+fn operand_json(operand: &Operand) -> Json {
+    match operand {
+        Operand::Literal(value, _) => Json::node("Literal", vec![("value", Json::str(value))]),
+        Operand::Index(array, index, _) => Json::node(
+            "Index",
+            vec![
+                ("array", Json::str(array)),
+                ("index", binary_stmt_json(index)),
+            ],
+        ),
+        Operand::Length(array, _) => Json::node("Length", vec![("array", Json::str(array))]),
+        Operand::Call(call) => func_call_json(call),
+        Operand::Bool(value) => Json::node("Bool", vec![("value", Json::Bool(*value))]),
+        Operand::Null => Json::node("Null", vec![]),
+        Operand::Undefined => Json::node("Undefined", vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+
+    #[test]
+    fn dumps_program_as_json() {
+        let program = parse(lex(
+            "function f(a, ...xs) {\n\treturn a + xs.length;\n}\n\nf(1);".to_string(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            program_to_json(&program),
+            r#"{
+  "type": "Program",
+  "body": [
+    {
+      "type": "FuncDecl",
+      "name": "f",
+      "exported": false,
+      "params": [
+        {
+          "type": "Param",
+          "name": "a",
+          "default": null,
+          "rest": false
+        },
+        {
+          "type": "Param",
+          "name": "xs",
+          "default": null,
+          "rest": true
+        }
+      ],
+      "body": [],
+      "return": {
+        "type": "Binary",
+        "lhs": {
+          "type": "Literal",
+          "value": "a"
+        },
+        "op": "+",
+        "rhs": {
+          "type": "Length",
+          "array": "xs"
+        }
+      }
+    },
+    {
+      "type": "Call",
+      "callee": "f",
+      "arguments": [
+        {
+          "type": "Binary",
+          "lhs": {
+            "type": "Literal",
+            "value": "1"
+          },
+          "op": null,
+          "rhs": null
+        }
+      ]
+    }
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let mut out = String::new();
+        write_str(&mut out, "a\"b\\c\n\u{1}");
+
+        assert_eq!(out, r#""a\"b\\c\n\u0001""#);
+    }
+}
@@ -0,0 +1,259 @@
+//! Renders the syntax tree back to JavaScript.
+//!
+//! The output parses back to the same tree, comments and the original formatting are lost.
+//! Blocks are indented with tabs like the programs in `programs/`.
+
+use super::{
+    BinaryStmtBody, FuncCallStmt, FuncExprBody, Operand, Param, Program, ReturnStmt, Stmt,
+};
+
+pub fn print_program(program: &Program) -> String {
+    let mut out = String::new();
+
+    let is_func = |stmt: &Stmt| matches!(stmt, Stmt::FuncDecl(_) | Stmt::FuncExpr(_));
+
+    for (i, stmt) in program.body.iter().enumerate() {
+        // an empty line around top level functions
+        if i > 0 && (is_func(stmt) || is_func(&program.body[i - 1])) {
+            out.push('\n');
+        }
+
+        print_stmt(&mut out, stmt, 0);
+    }
+
+    out
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push('\t');
+    }
+}
+
+/// The statements and the return of a block, followed by the closing `}`
+fn print_block(out: &mut String, block: &[Stmt], ret: Option<&ReturnStmt>, depth: usize) {
+    out.push_str("{\n");
+
+    for stmt in block {
+        print_stmt(out, stmt, depth + 1);
+    }
+
+    if let Some(ret) = ret {
+        indent(out, depth + 1);
+        out.push_str("return ");
+        out.push_str(&return_js(ret));
+        out.push_str(";\n");
+    }
+
+    indent(out, depth);
+    out.push('}');
+}
+
+fn print_stmt(out: &mut String, stmt: &Stmt, depth: usize) {
+    indent(out, depth);
+
+    match stmt {
+        Stmt::FuncDecl(func) => {
+            if func.exported {
+                out.push_str("export ");
+            }
+
+            out.push_str(&format!(
+                "function {}({}) ",
+                func.func_name,
+                params_js(&func.arguments)
+            ));
+            print_block(out, &func.block, func.return_value.as_ref(), depth);
+        }
+        Stmt::FuncExpr(func_expr) => print_func_expr(out, func_expr, depth),
+        Stmt::WhileStmt(while_stmt) => {
+            out.push_str(&format!(
+                "while ({}) ",
+                binary_stmt_js(&while_stmt.condition)
+            ));
+            print_block(out, &while_stmt.block, None, depth);
+        }
+        Stmt::IfStmt(if_stmt) => {
+            out.push_str(&format!("if ({}) ", binary_stmt_js(&if_stmt.condition)));
+            print_block(
+                out,
+                &if_stmt.if_block,
+                if_stmt.if_block_rt_val.as_ref(),
+                depth,
+            );
+        }
+        Stmt::LetStmt(let_stmt) => out.push_str(&format!(
+            "let {} = {};",
+            let_stmt.var_name,
+            binary_stmt_js(&let_stmt.value)
+        )),
+        Stmt::RassignStmt(reassign) => out.push_str(&format!(
+            "{} = {};",
+            reassign.var_name,
+            binary_stmt_js(&reassign.value)
+        )),
+        Stmt::BinaryStmt(binary_stmt) => {
+            out.push_str(&binary_stmt_js(binary_stmt));
+            out.push(';');
+        }
+        Stmt::FuncCall(call) => {
+            out.push_str(&func_call_js(call));
+            out.push(';');
+        }
+    }
+
+    out.push('\n');
+}
+
+/// Function expressions with only a return value come out as `(a) => a + 1`, everything
+/// else as `function (a) {..}`, both parse to the same tree
+fn print_func_expr(out: &mut String, func_expr: &FuncExprBody, depth: usize) {
+    if func_expr.func.exported {
+        out.push_str("export ");
+    }
+
+    if let Some(decl) = &func_expr.decl {
+        out.push_str(&format!("{} ", decl));
+    }
+
+    out.push_str(&format!("{} = ", func_expr.var_name));
+
+    let func = &func_expr.func;
+
+    match &func.return_value {
+        Some(ret) if func.block.is_empty() => {
+            out.push_str(&format!(
+                "({}) => {}",
+                params_js(&func.arguments),
+                return_js(ret)
+            ));
+        }
+        _ => {
+            out.push_str(&format!("function ({}) ", params_js(&func.arguments)));
+            print_block(out, &func.block, func.return_value.as_ref(), depth);
+        }
+    }
+
+    out.push(';');
+}
+
+fn params_js(params: &[Param]) -> String {
+    params
+        .iter()
+        .map(|param| match (&param.default, param.rest) {
+            (_, true) => format!("...{}", param.name),
+            (Some(default), false) => format!("{} = {}", param.name, binary_stmt_js(default)),
+            (None, false) => param.name.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A statement on one line, without the indentation
+pub fn stmt_js(stmt: &Stmt) -> String {
+    let mut out = String::new();
+    print_stmt(&mut out, stmt, 0);
+
+    out.trim_end().to_string()
+}
+
+pub fn return_js(ret: &ReturnStmt) -> String {
+    match ret {
+        ReturnStmt::BinaryStmtBody(binary_stmt) => binary_stmt_js(binary_stmt),
+        ReturnStmt::FuncCallStmt(call) => func_call_js(call),
+    }
+}
+
+fn func_call_js(call: &FuncCallStmt) -> String {
+    let arguments = call
+        .arguments
+        .iter()
+        .map(|arg| match arg {
+            Stmt::BinaryStmt(binary_stmt) => binary_stmt_js(binary_stmt),
+            Stmt::FuncCall(call) => func_call_js(call),
+            // the parser only puts expressions in argument lists
+            _ => unreachable!("arguments are expressions"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("{}({})", call.function_name, arguments)
+}
+
+pub fn binary_stmt_js(binary_stmt: &BinaryStmtBody) -> String {
+    match (&binary_stmt.op, &binary_stmt.rhs) {
+        (Some(op), Some(rhs)) => format!(
+            "{} {} {}",
+            operand_js(&binary_stmt.lhs),
+            op,
+            operand_js(rhs)
+        ),
+        _ => operand_js(&binary_stmt.lhs),
+    }
+}
+
+fn operand_js(operand: &Operand) -> String {
+    match operand {
+        Operand::Literal(value, _) => value.clone(),
+        Operand::Index(array, index, _) => format!("{}[{}]", array, binary_stmt_js(index)),
+        Operand::Length(array, _) => format!("{}.length", array),
+        Operand::Call(call) => func_call_js(call),
+        Operand::Bool(value) => value.to_string(),
+        Operand::Null => "null".to_string(),
+        Operand::Undefined => "undefined".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::{json::program_to_json, parse};
+
+    fn parse_src(src: &str) -> Program {
+        parse(lex(src.to_string())).unwrap()
+    }
+
+    /// Printing and parsing again gives the same tree, and printing that gives the same text
+    fn assert_round_trip(src: &str) -> String {
+        let program = parse_src(src);
+        let printed = print_program(&program);
+        let reparsed = parse_src(&printed);
+
+        assert_eq!(program_to_json(&program), program_to_json(&reparsed));
+        assert_eq!(printed, print_program(&reparsed));
+
+        printed
+    }
+
+    #[test]
+    fn round_trips_programs() {
+        for src in [
+            include_str!("../../programs/gcd.js"),
+            include_str!("../../programs/factorial.js"),
+            include_str!("../../programs/ack.js"),
+            include_str!("../../programs/recursive_gcd.js"),
+            include_str!("../../programs/dead_code.js"),
+            include_str!("../../programs/function_expr.js"),
+            include_str!("../../programs/closures.js"),
+            include_str!("../../programs/params.js"),
+            include_str!("../../programs/nested_calls.js"),
+            include_str!("../../programs/literals.js"),
+            include_str!("../../programs/numbers.js"),
+        ] {
+            assert_round_trip(src);
+        }
+    }
+
+    #[test]
+    fn prints_normalized_source() {
+        let printed = assert_round_trip(
+            "function f(a,b=2,...xs){ // sum\n  if (a != b) return xs[a - 1];\n  let n = xs.length;\n  return g(n, a);\n}\nconst h = x => x * 2;\nf(1, h(2));",
+        );
+
+        assert_eq!(
+            printed,
+            "function f(a, b = 2, ...xs) {\n\tif (a != b) {\n\t\treturn xs[a - 1];\n\t}\n\tlet n = xs.length;\n\treturn g(n, a);\n}\n\nconst h = (x) => x * 2;\n\nf(1, h(2));\n"
+        );
+    }
+}
@@ -0,0 +1,448 @@
+//! Traversal of the syntax tree.
+//!
+//! [`Visitor`] walks the tree by reference and [`VisitorMut`] by mutable reference. Every
+//! `visit_*` method defaults to the matching `walk_*` function, which visits the children of
+//! the node in source order. An implementation overrides the methods of the nodes it cares
+//! about and calls the `walk_*` function from the override to keep descending, or leaves it
+//! out to skip the children.
+
+use super::{
+    BinaryStmtBody, FuncCallStmt, FuncDeclBody, FuncExprBody, IfStmtBody, LetStmtBody, Operand,
+    Param, Program, ReturnStmt, Stmt, WhileStmtBody,
+};
+
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_block(&mut self, block: &[Stmt]) {
+        walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_func_decl(&mut self, func: &FuncDeclBody) {
+        walk_func_decl(self, func);
+    }
+
+    fn visit_func_expr(&mut self, func_expr: &FuncExprBody) {
+        walk_func_expr(self, func_expr);
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        walk_param(self, param);
+    }
+
+    fn visit_while_stmt(&mut self, while_stmt: &WhileStmtBody) {
+        walk_while_stmt(self, while_stmt);
+    }
+
+    fn visit_if_stmt(&mut self, if_stmt: &IfStmtBody) {
+        walk_if_stmt(self, if_stmt);
+    }
+
+    fn visit_let_stmt(&mut self, let_stmt: &LetStmtBody) {
+        walk_let_stmt(self, let_stmt);
+    }
+
+    /// `x = value;`, it has the same shape as a `let`
+    fn visit_reassign_stmt(&mut self, reassign: &LetStmtBody) {
+        walk_let_stmt(self, reassign);
+    }
+
+    fn visit_func_call(&mut self, call: &FuncCallStmt) {
+        walk_func_call(self, call);
+    }
+
+    fn visit_return_stmt(&mut self, return_stmt: &ReturnStmt) {
+        walk_return_stmt(self, return_stmt);
+    }
+
+    fn visit_binary_stmt(&mut self, binary_stmt: &BinaryStmtBody) {
+        walk_binary_stmt(self, binary_stmt);
+    }
+
+    fn visit_operand(&mut self, operand: &Operand) {
+        walk_operand(self, operand);
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    visitor.visit_block(&program.body);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &[Stmt]) {
+    for stmt in block {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::FuncDecl(func) => visitor.visit_func_decl(func),
+        Stmt::WhileStmt(while_stmt) => visitor.visit_while_stmt(while_stmt),
+        Stmt::IfStmt(if_stmt) => visitor.visit_if_stmt(if_stmt),
+        Stmt::LetStmt(let_stmt) => visitor.visit_let_stmt(let_stmt),
+        Stmt::RassignStmt(reassign) => visitor.visit_reassign_stmt(reassign),
+        Stmt::BinaryStmt(binary_stmt) => visitor.visit_binary_stmt(binary_stmt),
+        Stmt::FuncCall(call) => visitor.visit_func_call(call),
+        Stmt::FuncExpr(func_expr) => visitor.visit_func_expr(func_expr),
+    }
+}
+
+pub fn walk_func_decl<V: Visitor + ?Sized>(visitor: &mut V, func: &FuncDeclBody) {
+    for param in &func.arguments {
+        visitor.visit_param(param);
+    }
+
+    visitor.visit_block(&func.block);
+
+    if let Some(return_stmt) = &func.return_value {
+        visitor.visit_return_stmt(return_stmt);
+    }
+}
+
+pub fn walk_func_expr<V: Visitor + ?Sized>(visitor: &mut V, func_expr: &FuncExprBody) {
+    visitor.visit_func_decl(&func_expr.func);
+}
+
+pub fn walk_param<V: Visitor + ?Sized>(visitor: &mut V, param: &Param) {
+    if let Some(default) = &param.default {
+        visitor.visit_binary_stmt(default);
+    }
+}
+
+pub fn walk_while_stmt<V: Visitor + ?Sized>(visitor: &mut V, while_stmt: &WhileStmtBody) {
+    visitor.visit_binary_stmt(&while_stmt.condition);
+    visitor.visit_block(&while_stmt.block);
+}
+
+pub fn walk_if_stmt<V: Visitor + ?Sized>(visitor: &mut V, if_stmt: &IfStmtBody) {
+    visitor.visit_binary_stmt(&if_stmt.condition);
+    visitor.visit_block(&if_stmt.if_block);
+
+    if let Some(return_stmt) = &if_stmt.if_block_rt_val {
+        visitor.visit_return_stmt(return_stmt);
+    }
+}
+
+pub fn walk_let_stmt<V: Visitor + ?Sized>(visitor: &mut V, let_stmt: &LetStmtBody) {
+    visitor.visit_binary_stmt(&let_stmt.value);
+}
+
+pub fn walk_func_call<V: Visitor + ?Sized>(visitor: &mut V, call: &FuncCallStmt) {
+    // arguments are expressions, not a block, so they don't go through visit_block
+    for argument in &call.arguments {
+        visitor.visit_stmt(argument);
+    }
+}
+
+pub fn walk_return_stmt<V: Visitor + ?Sized>(visitor: &mut V, return_stmt: &ReturnStmt) {
+    match return_stmt {
+        ReturnStmt::BinaryStmtBody(binary_stmt) => visitor.visit_binary_stmt(binary_stmt),
+        ReturnStmt::FuncCallStmt(call) => visitor.visit_func_call(call),
+    }
+}
+
+pub fn walk_binary_stmt<V: Visitor + ?Sized>(visitor: &mut V, binary_stmt: &BinaryStmtBody) {
+    visitor.visit_operand(&binary_stmt.lhs);
+
+    if let Some(rhs) = &binary_stmt.rhs {
+        visitor.visit_operand(rhs);
+    }
+}
+
+pub fn walk_operand<V: Visitor + ?Sized>(visitor: &mut V, operand: &Operand) {
+    match operand {
+        Operand::Index(_, index, _) => visitor.visit_binary_stmt(index),
+        Operand::Call(call) => visitor.visit_func_call(call),
+        Operand::Literal(_, _)
+        | Operand::Length(_, _)
+        | Operand::Bool(_)
+        | Operand::Null
+        | Operand::Undefined => (),
+    }
+}
+
+/// Same as [`Visitor`] but allowed to change the tree. [`VisitorMut::visit_block_mut`] gets the
+/// whole `Vec` so statements can be added, removed or replaced
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Vec<Stmt>) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_func_decl_mut(&mut self, func: &mut FuncDeclBody) {
+        walk_func_decl_mut(self, func);
+    }
+
+    fn visit_func_expr_mut(&mut self, func_expr: &mut FuncExprBody) {
+        walk_func_expr_mut(self, func_expr);
+    }
+
+    fn visit_param_mut(&mut self, param: &mut Param) {
+        walk_param_mut(self, param);
+    }
+
+    fn visit_while_stmt_mut(&mut self, while_stmt: &mut WhileStmtBody) {
+        walk_while_stmt_mut(self, while_stmt);
+    }
+
+    fn visit_if_stmt_mut(&mut self, if_stmt: &mut IfStmtBody) {
+        walk_if_stmt_mut(self, if_stmt);
+    }
+
+    fn visit_let_stmt_mut(&mut self, let_stmt: &mut LetStmtBody) {
+        walk_let_stmt_mut(self, let_stmt);
+    }
+
+    fn visit_reassign_stmt_mut(&mut self, reassign: &mut LetStmtBody) {
+        walk_let_stmt_mut(self, reassign);
+    }
+
+    fn visit_func_call_mut(&mut self, call: &mut FuncCallStmt) {
+        walk_func_call_mut(self, call);
+    }
+
+    fn visit_return_stmt_mut(&mut self, return_stmt: &mut ReturnStmt) {
+        walk_return_stmt_mut(self, return_stmt);
+    }
+
+    fn visit_binary_stmt_mut(&mut self, binary_stmt: &mut BinaryStmtBody) {
+        walk_binary_stmt_mut(self, binary_stmt);
+    }
+
+    fn visit_operand_mut(&mut self, operand: &mut Operand) {
+        walk_operand_mut(self, operand);
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    visitor.visit_block_mut(&mut program.body);
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Vec<Stmt>) {
+    for stmt in block {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::FuncDecl(func) => visitor.visit_func_decl_mut(func),
+        Stmt::WhileStmt(while_stmt) => visitor.visit_while_stmt_mut(while_stmt),
+        Stmt::IfStmt(if_stmt) => visitor.visit_if_stmt_mut(if_stmt),
+        Stmt::LetStmt(let_stmt) => visitor.visit_let_stmt_mut(let_stmt),
+        Stmt::RassignStmt(reassign) => visitor.visit_reassign_stmt_mut(reassign),
+        Stmt::BinaryStmt(binary_stmt) => visitor.visit_binary_stmt_mut(binary_stmt),
+        Stmt::FuncCall(call) => visitor.visit_func_call_mut(call),
+        Stmt::FuncExpr(func_expr) => visitor.visit_func_expr_mut(func_expr),
+    }
+}
+
+pub fn walk_func_decl_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func: &mut FuncDeclBody) {
+    for param in &mut func.arguments {
+        visitor.visit_param_mut(param);
+    }
+
+    visitor.visit_block_mut(&mut func.block);
+
+    if let Some(return_stmt) = &mut func.return_value {
+        visitor.visit_return_stmt_mut(return_stmt);
+    }
+}
+
+pub fn walk_func_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func_expr: &mut FuncExprBody) {
+    visitor.visit_func_decl_mut(&mut func_expr.func);
+}
+
+pub fn walk_param_mut<V: VisitorMut + ?Sized>(visitor: &mut V, param: &mut Param) {
+    if let Some(default) = &mut param.default {
+        visitor.visit_binary_stmt_mut(default);
+    }
+}
+
+pub fn walk_while_stmt_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    while_stmt: &mut WhileStmtBody,
+) {
+    visitor.visit_binary_stmt_mut(&mut while_stmt.condition);
+    visitor.visit_block_mut(&mut while_stmt.block);
+}
+
+pub fn walk_if_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, if_stmt: &mut IfStmtBody) {
+    visitor.visit_binary_stmt_mut(&mut if_stmt.condition);
+    visitor.visit_block_mut(&mut if_stmt.if_block);
+
+    if let Some(return_stmt) = &mut if_stmt.if_block_rt_val {
+        visitor.visit_return_stmt_mut(return_stmt);
+    }
+}
+
+pub fn walk_let_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, let_stmt: &mut LetStmtBody) {
+    visitor.visit_binary_stmt_mut(&mut let_stmt.value);
+}
+
+pub fn walk_func_call_mut<V: VisitorMut + ?Sized>(visitor: &mut V, call: &mut FuncCallStmt) {
+    for argument in &mut call.arguments {
+        visitor.visit_stmt_mut(argument);
+    }
+}
+
+pub fn walk_return_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, return_stmt: &mut ReturnStmt) {
+    match return_stmt {
+        ReturnStmt::BinaryStmtBody(binary_stmt) => visitor.visit_binary_stmt_mut(binary_stmt),
+        ReturnStmt::FuncCallStmt(call) => visitor.visit_func_call_mut(call),
+    }
+}
+
+pub fn walk_binary_stmt_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    binary_stmt: &mut BinaryStmtBody,
+) {
+    visitor.visit_operand_mut(&mut binary_stmt.lhs);
+
+    if let Some(rhs) = &mut binary_stmt.rhs {
+        visitor.visit_operand_mut(rhs);
+    }
+}
+
+pub fn walk_operand_mut<V: VisitorMut + ?Sized>(visitor: &mut V, operand: &mut Operand) {
+    match operand {
+        Operand::Index(_, index, _) => visitor.visit_binary_stmt_mut(index),
+        Operand::Call(call) => visitor.visit_func_call_mut(call),
+        Operand::Literal(_, _)
+        | Operand::Length(_, _)
+        | Operand::Bool(_)
+        | Operand::Null
+        | Operand::Undefined => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+
+    fn parse_src(src: &str) -> Program {
+        parse(lex(src.to_string())).unwrap()
+    }
+
+    /// Records the name of every node it enters
+    #[derive(Default)]
+    struct Trace(Vec<String>);
+
+    impl Visitor for Trace {
+        fn visit_stmt(&mut self, stmt: &Stmt) {
+            self.0.push(format!("{:?}", std::mem::discriminant(stmt)));
+            walk_stmt(self, stmt);
+        }
+
+        fn visit_param(&mut self, param: &Param) {
+            self.0.push(format!("param {}", param.name));
+            walk_param(self, param);
+        }
+
+        fn visit_return_stmt(&mut self, return_stmt: &ReturnStmt) {
+            self.0.push("return".to_string());
+            walk_return_stmt(self, return_stmt);
+        }
+
+        fn visit_operand(&mut self, operand: &Operand) {
+            if let Operand::Literal(x, _) | Operand::Index(x, _, _) | Operand::Length(x, _) =
+                operand
+            {
+                self.0.push(x.clone());
+            }
+
+            walk_operand(self, operand);
+        }
+    }
+
+    #[test]
+    fn visits_every_node_in_source_order() {
+        let program = parse_src(
+            "function f(a, b = 2, ...xs) {\n\tlet i = 0;\n\twhile (i > xs.length) {\n\t\ti = i + xs[i];\n\t}\n\tif (a == b) {\n\t\treturn g(i);\n\t}\n\tconst h = (c) => c;\n\treturn a;\n}\n",
+        );
+
+        let mut trace = Trace::default();
+        trace.visit_program(&program);
+
+        let operands: Vec<_> = trace
+            .0
+            .iter()
+            .filter(|x| !x.starts_with("Discriminant"))
+            .map(String::as_str)
+            .collect();
+
+        assert_eq!(
+            operands,
+            [
+                "param a", "param b", "2", "param xs", "0", "i", "xs", "i", "xs", "i", "a", "b",
+                "return", "i", "param c", "return", "c", "return", "a",
+            ]
+        );
+    }
+
+    /// Renames every use of a variable
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_operand_mut(&mut self, operand: &mut Operand) {
+            if let Operand::Literal(x, _) | Operand::Index(x, _, _) | Operand::Length(x, _) =
+                operand
+                && x == "a"
+            {
+                *x = "z".to_string();
+            }
+
+            walk_operand_mut(self, operand);
+        }
+
+        fn visit_block_mut(&mut self, block: &mut Vec<Stmt>) {
+            // drop calls to `log`
+            block.retain(
+                |stmt| !matches!(stmt, Stmt::FuncCall(call) if call.function_name == "log"),
+            );
+
+            walk_block_mut(self, block);
+        }
+    }
+
+    #[test]
+    fn mutable_visitor_rewrites_tree() {
+        let mut program =
+            parse_src("function f(a) {\n\tlog(a);\n\tlet b = a + 1;\n\treturn b * a;\n}\n");
+
+        Rename.visit_program_mut(&mut program);
+
+        let Stmt::FuncDecl(func) = &program.body[0] else {
+            panic!("expected a function");
+        };
+
+        assert_eq!(func.block.len(), 1);
+
+        let Stmt::LetStmt(let_stmt) = &func.block[0] else {
+            panic!("expected a let");
+        };
+
+        assert_eq!(let_stmt.value.lhs, "z");
+
+        let Some(ReturnStmt::BinaryStmtBody(ret)) = &func.return_value else {
+            panic!("expected a return value");
+        };
+
+        assert_eq!(*ret.rhs.as_ref().unwrap(), "z");
+    }
+}